@@ -108,7 +108,7 @@ export class IntelligenceManager {
 
     if (this.isListening) {
       console.log("Already listening, stopping first");
-      this.stop();
+      await this.stop();
     }
 
     this.initialized = false;
@@ -159,7 +159,7 @@ export class IntelligenceManager {
 
       this.audioCapture.on("data", this.audioHandler);
       try {
-        this.audioCapture.start();
+        await this.audioCapture.start();
         this.notifyListeningState(true);
         console.log("Audio capture started successfully");
       } catch (e) {
@@ -174,7 +174,7 @@ export class IntelligenceManager {
     }
   }
 
-  stop() {
+  async stop() {
     console.log("Stopping Intelligence Manager");
 
     if (!this.isListening) {
@@ -185,11 +185,17 @@ export class IntelligenceManager {
     this.isListening = false;
 
     if (this.audioCapture) {
+      // Audio buffered at stop is delivered before the promise resolves, so
+      // the listener stays until then.
+      try {
+        await this.audioCapture.stop();
+      } catch (e) {
+        console.error("Failed to stop audio capture:", e);
+      }
       if (this.audioHandler) {
         this.audioCapture.removeListener("data", this.audioHandler);
         this.audioHandler = null;
       }
-      this.audioCapture.stop();
     }
 
     if (this.stt) {
@@ -62,18 +62,19 @@ loadNativeModule();
 
 export class SystemAudioCapture extends EventEmitter {
   private capture: any = null;
+  private stopping: Promise<unknown> | null = null;
 
-  constructor() {
+  constructor(deviceId?: string) {
     super();
     if (NativeModule && NativeModule.SystemAudioCapture) {
-      this.capture = new NativeModule.SystemAudioCapture();
+      this.capture = new NativeModule.SystemAudioCapture(deviceId);
       console.log("SystemAudioCapture native instance created");
     } else {
       console.error("NativeModule.SystemAudioCapture not available");
     }
   }
 
-  start(): void {
+  async start(): Promise<void> {
     if (!this.capture) {
       const error = new Error(
         "Cannot start: capture not initialized (native module missing)",
@@ -82,6 +83,11 @@ export class SystemAudioCapture extends EventEmitter {
       throw error;
     }
 
+    // The native side refuses to start until the previous stop has finished.
+    if (this.stopping) {
+      await this.stopping.catch(() => {});
+    }
+
     this.capture.start((chunk: Uint8Array) => {
       if (chunk && chunk.length > 0) {
         this.emit("data", Buffer.from(chunk));
@@ -91,9 +97,23 @@ export class SystemAudioCapture extends EventEmitter {
     console.log("SystemAudioCapture started");
   }
 
-  stop(): void {
+  async stop(timeoutMs?: number): Promise<void> {
     if (this.capture) {
-      this.capture.stop();
+      const pending: Promise<{ timedOut: boolean; elapsedMs: number }> =
+        this.capture.stop(timeoutMs);
+      this.stopping = pending;
+      try {
+        const result = await pending;
+        if (result.timedOut) {
+          console.warn(
+            `SystemAudioCapture: capture thread did not exit within ${Math.round(result.elapsedMs)}ms, abandoned`,
+          );
+        }
+      } finally {
+        if (this.stopping === pending) {
+          this.stopping = null;
+        }
+      }
     }
     this.emit("stop");
     console.log("SystemAudioCapture stopped");
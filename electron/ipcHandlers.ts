@@ -59,7 +59,7 @@ export function registerHandlers() {
   });
 
   ipcMain.handle("stop-meeting", async () => {
-    intelligenceManager.stop();
+    await intelligenceManager.stop();
     const transcript = intelligenceManager.getTranscript();
     return { success: true, transcript };
   });
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::pipeline::{Flow, Frame, Stage};
+
+/// Frames quieter than this hold the gain instead of raising it, so pauses
+/// and background noise are not pumped up.
+const GATE_DB: f64 = -50.0;
+const MAX_GAIN_DB: f64 = 30.0;
+const MIN_GAIN_DB: f64 = -20.0;
+/// The level estimate follows a rise quickly and a fall slowly.
+const ATTACK: Duration = Duration::from_millis(50);
+const RELEASE: Duration = Duration::from_millis(1_000);
+
+/// Automatic gain control: steers the RMS level towards a target, ramping
+/// the gain across each frame and never pushing a peak past full scale.
+pub struct AgcStage {
+    sample_rate: u32,
+    target_db: f64,
+    /// Smoothed level of the frames above the gate, in dBFS.
+    level_db: Option<f64>,
+    gain: f32,
+}
+
+impl AgcStage {
+    pub fn new(sample_rate: u32, target_db: f64) -> Self {
+        Self {
+            sample_rate,
+            target_db,
+            level_db: None,
+            gain: 1.0,
+        }
+    }
+}
+
+impl Stage for AgcStage {
+    fn name(&self) -> &'static str {
+        "agc"
+    }
+
+    fn process(&mut self, frame: &mut Frame) -> Flow {
+        let channels = frame.channels.max(1);
+        let frames = frame.samples.len() / channels;
+        if frames == 0 {
+            return Flow::Continue;
+        }
+
+        let mean_square = frame
+            .samples
+            .iter()
+            .map(|&s| s as f64 * s as f64)
+            .sum::<f64>()
+            / frame.samples.len() as f64;
+        let frame_db = 10.0 * mean_square.max(1e-12).log10();
+        if frame_db > GATE_DB {
+            let elapsed = frames as f64 / self.sample_rate as f64;
+            self.level_db = Some(match self.level_db {
+                None => frame_db,
+                Some(level) => {
+                    let tau = if frame_db > level { ATTACK } else { RELEASE };
+                    level + (1.0 - (-elapsed / tau.as_secs_f64()).exp()) * (frame_db - level)
+                }
+            });
+        }
+
+        let mut target = match self.level_db {
+            Some(level) => {
+                10f64.powf((self.target_db - level).clamp(MIN_GAIN_DB, MAX_GAIN_DB) / 20.0) as f32
+            }
+            None => self.gain,
+        };
+        let peak = frame.samples.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+        if peak * target > 1.0 {
+            target = 1.0 / peak;
+        }
+
+        // A gain still too high for this frame's peak is cut at once.
+        let start = if peak * self.gain > 1.0 {
+            target
+        } else {
+            self.gain
+        };
+        for (i, samples) in frame.samples.chunks_mut(channels).enumerate() {
+            let gain = start + (target - start) * (i + 1) as f32 / frames as f32;
+            for sample in samples {
+                *sample *= gain;
+            }
+        }
+        self.gain = target;
+        Flow::Continue
+    }
+
+    fn reset(&mut self) {
+        self.level_db = None;
+        self.gain = 1.0;
+    }
+
+    fn stats(&self) -> HashMap<String, f64> {
+        HashMap::from([("gainDb".to_string(), 20.0 * (self.gain as f64).log10())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(amplitude: f32, samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16_000.0).sin())
+            .collect()
+    }
+
+    fn rms_db(samples: &[f32]) -> f64 {
+        let mean_square =
+            samples.iter().map(|&s| s as f64 * s as f64).sum::<f64>() / samples.len() as f64;
+        10.0 * mean_square.log10()
+    }
+
+    #[test]
+    fn brings_quiet_and_loud_speech_to_the_target() {
+        for amplitude in [0.01, 0.8] {
+            let mut agc = AgcStage::new(16_000, -20.0);
+            let mut last = Vec::new();
+            for _ in 0..100 {
+                let mut frame = Frame::new(tone(amplitude, 320), 1);
+                agc.process(&mut frame);
+                last = frame.samples;
+            }
+            assert!((rms_db(&last) + 20.0).abs() < 0.5, "{} dB", rms_db(&last));
+        }
+    }
+
+    #[test]
+    fn holds_the_gain_through_silence_and_resets() {
+        let mut agc = AgcStage::new(16_000, -20.0);
+        for _ in 0..50 {
+            agc.process(&mut Frame::new(tone(0.01, 320), 1));
+        }
+        let gain = agc.gain;
+        assert!(gain > 5.0);
+
+        let mut quiet = Frame::new(vec![1e-4; 320], 1);
+        agc.process(&mut quiet);
+        assert_eq!(agc.gain, gain);
+
+        agc.reset();
+        let mut frame = Frame::new(vec![1e-4; 320], 1);
+        agc.process(&mut frame);
+        assert_eq!(frame.samples, vec![1e-4; 320]);
+    }
+
+    #[test]
+    fn never_clips_a_peak() {
+        let mut agc = AgcStage::new(16_000, -3.0);
+        let mut samples = tone(0.05, 320);
+        samples[100] = 0.9;
+        for _ in 0..20 {
+            let mut frame = Frame::new(samples.clone(), 1);
+            agc.process(&mut frame);
+            assert!(frame.samples.iter().all(|s| s.abs() <= 1.0));
+        }
+    }
+}
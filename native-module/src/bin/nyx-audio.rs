@@ -0,0 +1,704 @@
+//! Command-line diagnostics for the capture pipeline, built from the same
+//! library code as the Node module:
+//!
+//! ```text
+//! cargo run --release --features cli --bin nyx-audio -- devices
+//! ```
+
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use nyx_audio::audio_config::SAMPLE_RATE;
+use nyx_audio::conversion::ConversionConfig;
+use nyx_audio::decoder::AudioFileReader;
+use nyx_audio::downmix::{ChannelLayout, DownmixMode, Downmixer};
+use nyx_audio::framing::Payload;
+use nyx_audio::loudness::LoudnessMeter;
+use nyx_audio::metering::{to_dbfs, LevelMeter, MeterConfig};
+use nyx_audio::pipeline::StageStats;
+use nyx_audio::sample_format::{SampleEncoding, SampleFormat};
+use nyx_audio::subscriber::{Output, Subscriber, SubscriberConfig, SubscriberOptions};
+use nyx_audio::system_audio::{self, SystemAudioStream};
+use nyx_audio::utterance::{
+    SegmenterConfig, UtteranceEvent, UtteranceOptions, UtteranceSubscriber,
+};
+use nyx_audio::wav::WavWriter;
+
+const USAGE: &str = "\
+nyx-audio: capture and pipeline diagnostics
+
+USAGE:
+    nyx-audio devices
+    nyx-audio capture <out.wav> [SOURCE] [--seconds N] [--i16 [--dither]]
+    nyx-audio levels [SOURCE] [--seconds N] [--high-pass HZ]
+    nyx-audio process <in> [--output out.wav] [--suppress-silence]
+                      [--min-utterance-ms N] [--max-utterance-ms N]
+                      [--diarize [--speaker-model model.onnx]] [--high-pass HZ]
+
+--high-pass HZ adds a filter ahead of VAD and suppression, e.g. 20 against DC
+offset and rumble (default 0, off).
+--speaker-model needs a build with the `speaker-model` feature.
+
+SOURCE (default: the system output mix):
+    --device ID        A device id printed by `devices`
+    --pulse [NAME]     Record a PulseAudio source through `parec`; default is
+                       the monitor of the default sink
+    --input PATH       An audio file (WAV, MP3, AAC/M4A, FLAC, Ogg Vorbis),
+                       or `-` for raw f32le PCM on stdin
+    --rate HZ          Sample rate of raw input (default 48000)
+    --channels N       Channel count of raw input (default 2)
+";
+
+/// Options that take a value; `--pulse` takes one only if it follows.
+const VALUE_FLAGS: &[&str] = &[
+    "--device",
+    "--input",
+    "--rate",
+    "--channels",
+    "--seconds",
+    "--output",
+    "--min-utterance-ms",
+    "--max-utterance-ms",
+    "--high-pass",
+    "--speaker-model",
+];
+const SWITCHES: &[&str] = &[
+    "--i16",
+    "--dither",
+    "--suppress-silence",
+    "--diarize",
+    "--pulse",
+];
+/// Longest device gap filled with silence, as in the Node module; the fill
+/// is handed out `GAP_CHUNK_FRAMES` at a time.
+const MAX_GAP_FILL_FRAMES: u64 = 192_000 * 10;
+const GAP_CHUNK_FRAMES: usize = 4_800;
+const READINGS_PER_SECOND: u32 = 10;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), Args::parse(rest)?),
+        None => {
+            print!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    match command {
+        "devices" => devices(),
+        "capture" => capture(&rest),
+        "levels" => levels(&rest),
+        "process" => process(&rest),
+        "help" | "--help" | "-h" => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        other => bail!("Unknown command: {} (see `nyx-audio help`)", other),
+    }
+}
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, Option<String>>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut iter = args.iter().peekable();
+
+        while let Some(arg) = iter.next() {
+            if VALUE_FLAGS.contains(&arg.as_str()) {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| anyhow!("{} needs a value", arg))?;
+                options.insert(arg.clone(), Some(value.clone()));
+            } else if arg == "--pulse" {
+                let name = iter.next_if(|next| !next.starts_with("--")).cloned();
+                options.insert(arg.clone(), name);
+            } else if SWITCHES.contains(&arg.as_str()) {
+                options.insert(arg.clone(), None);
+            } else if arg.starts_with("--") {
+                bail!("Unknown option: {}", arg);
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+
+        Ok(Self {
+            positional,
+            options,
+        })
+    }
+
+    fn has(&self, flag: &str) -> bool {
+        self.options.contains_key(flag)
+    }
+
+    fn value(&self, flag: &str) -> Option<&str> {
+        self.options.get(flag).and_then(|v| v.as_deref())
+    }
+
+    fn number<T: std::str::FromStr>(&self, flag: &str) -> Result<Option<T>> {
+        self.value(flag)
+            .map(|v| v.parse().map_err(|_| anyhow!("Invalid {}: {}", flag, v)))
+            .transpose()
+    }
+
+    fn path(&self, index: usize, what: &str) -> Result<PathBuf> {
+        self.positional
+            .get(index)
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow!("Missing {} (see `nyx-audio help`)", what))
+    }
+}
+
+/// Where interleaved audio comes from: the platform capture backend, a
+/// file, or raw PCM from a pipe (stdin or `parec`).
+enum Source {
+    Device {
+        stream: SystemAudioStream,
+        /// Gap frames still to be emitted as silence before `held`.
+        gap: usize,
+        held: Vec<f32>,
+    },
+    File(AudioFileReader),
+    Pipe {
+        reader: Box<dyn Read>,
+        child: Option<Child>,
+        format: SampleFormat,
+        rate: u32,
+        pending: Vec<u8>,
+    },
+}
+
+impl Source {
+    fn open(args: &Args) -> Result<Self> {
+        let rate = args.number("--rate")?.unwrap_or(48_000);
+        let channels = args.number("--channels")?.unwrap_or(2);
+        let raw = |reader: Box<dyn Read>, child| Source::Pipe {
+            reader,
+            child,
+            format: SampleFormat::new(SampleEncoding::F32, channels),
+            rate,
+            pending: Vec::new(),
+        };
+
+        if let Some(input) = args.value("--input") {
+            if input == "-" {
+                return Ok(raw(Box::new(io::stdin()), None));
+            }
+            return Ok(Source::File(AudioFileReader::open(Path::new(input))?));
+        }
+
+        if args.has("--pulse") {
+            let device = args.value("--pulse").unwrap_or("@DEFAULT_MONITOR@");
+            let mut child = Command::new("parec")
+                .args(["--raw", "--format=float32le"])
+                .arg(format!("--rate={}", rate))
+                .arg(format!("--channels={}", channels))
+                .arg(format!("--device={}", device))
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(|e| anyhow!("Failed to start parec: {}", e))?;
+            let stdout = child
+                .stdout
+                .take()
+                .ok_or_else(|| anyhow!("parec has no output"))?;
+            return Ok(raw(Box::new(stdout), Some(child)));
+        }
+
+        let mut stream = SystemAudioStream::new(args.value("--device").map(String::from))?;
+        stream.play()?;
+        Ok(Source::Device {
+            stream,
+            gap: 0,
+            held: Vec::new(),
+        })
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            Source::Device { stream, .. } => stream.sample_rate(),
+            Source::File(reader) => reader.sample_rate(),
+            Source::Pipe { rate, .. } => *rate,
+        }
+    }
+
+    fn channels(&self) -> usize {
+        match self {
+            Source::Device { stream, .. } => stream.channels() as usize,
+            Source::File(reader) => reader.channels(),
+            Source::Pipe { format, .. } => format.channels(),
+        }
+    }
+
+    fn layout(&self) -> ChannelLayout {
+        match self {
+            Source::Device { stream, .. } => stream.channel_layout(),
+            _ => ChannelLayout::default_for(self.channels()),
+        }
+    }
+
+    /// The next block of interleaved samples, possibly empty while a device
+    /// is idle; `None` once a file or pipe ends. Device gaps come back as
+    /// silence so the timeline matches wall time.
+    fn read(&mut self) -> Result<Option<Vec<f32>>> {
+        match self {
+            Source::Device { stream, gap, held } => {
+                if *gap == 0 && held.is_empty() {
+                    let packet = stream.poll_audio();
+                    *gap = packet.gap_frames.min(MAX_GAP_FILL_FRAMES) as usize;
+                    *held = packet.samples;
+                }
+                if *gap > 0 {
+                    let frames = (*gap).min(GAP_CHUNK_FRAMES);
+                    *gap -= frames;
+                    return Ok(Some(vec![0.0; frames * stream.channels() as usize]));
+                }
+                Ok(Some(std::mem::take(held)))
+            }
+            Source::File(reader) => reader.read(),
+            Source::Pipe {
+                reader,
+                format,
+                pending,
+                ..
+            } => {
+                let mut buffer = [0u8; 16_384];
+                let read = reader.read(&mut buffer)?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                pending.extend_from_slice(&buffer[..read]);
+                let whole = pending.len() - pending.len() % format.block_align();
+                let samples = format.decode(&pending[..whole]);
+                pending.drain(..whole);
+                Ok(Some(samples))
+            }
+        }
+    }
+}
+
+impl Drop for Source {
+    fn drop(&mut self) {
+        match self {
+            Source::Device { stream, .. } => {
+                let _ = stream.stop();
+            }
+            Source::Pipe {
+                child: Some(child), ..
+            } => {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn devices() -> Result<()> {
+    for device in system_audio::list_devices()? {
+        match device.process_id {
+            Some(pid) => println!(
+                "{:<40} {:<12} {} (pid {})",
+                device.id, device.kind, device.name, pid
+            ),
+            None => println!("{:<40} {:<12} {}", device.id, device.kind, device.name),
+        }
+    }
+    Ok(())
+}
+
+/// Records the source as-is (rate and channels unchanged) until `--seconds`
+/// of audio, the end of the input, or Ctrl-C.
+fn capture(args: &Args) -> Result<()> {
+    let path = args.path(0, "output WAV path")?;
+    let seconds: Option<f64> = args.number("--seconds")?;
+    let encoding = if args.has("--i16") {
+        SampleEncoding::I16
+    } else {
+        SampleEncoding::F32
+    };
+
+    let mut source = Source::open(args)?;
+    let (rate, channels) = (source.sample_rate(), source.channels());
+    let mut writer = WavWriter::create(&path, rate, channels as u16, encoding)?;
+    writer.set_conversion(ConversionConfig {
+        dither: args.has("--dither"),
+        ..ConversionConfig::default()
+    });
+    let mut loudness = LoudnessMeter::new(rate, &ChannelLayout::default_for(channels));
+    let limit = seconds.map(|s| (s * rate as f64) as usize * channels);
+    eprintln!(
+        "Recording {} Hz, {} channel(s) to {}",
+        rate,
+        channels,
+        path.display()
+    );
+
+    let mut written = 0usize;
+    let mut last_update = Instant::now();
+    while let Some(mut samples) = source.read()? {
+        if let Some(limit) = limit {
+            samples.truncate(limit - written);
+        }
+        writer.write(&samples)?;
+        loudness.push(&samples);
+        written += samples.len();
+
+        if last_update.elapsed() >= Duration::from_secs(1) {
+            writer.set_loudness(loudness.summary());
+            writer.update_header()?;
+            last_update = Instant::now();
+            eprint!(
+                "\r{:.1} s",
+                written as f64 / (rate as f64 * channels as f64)
+            );
+        }
+        if limit == Some(written) {
+            break;
+        }
+    }
+
+    writer.set_loudness(loudness.summary());
+    let clipped = writer.clipped_samples();
+    writer.finish()?;
+    eprintln!(
+        "\r{:.1} s written",
+        written as f64 / (rate as f64 * channels as f64)
+    );
+    if clipped > 0 {
+        eprintln!("{} samples clipped", clipped);
+    }
+    Ok(())
+}
+
+/// 16 kHz mono i16 frames from the library's subscriber pipeline, as a
+/// Node `subscribe` callback would receive them.
+struct FrameStream {
+    subscriber: Subscriber,
+    delivered: mpsc::Receiver<Payload>,
+}
+
+impl FrameStream {
+    fn new(options: SubscriberOptions) -> Result<Self> {
+        let config =
+            SubscriberConfig::from_options(Some(options)).map_err(|e| anyhow!(e.reason))?;
+        let (tx, delivered) = mpsc::channel();
+        let output = Output::Sink(Box::new(move |payload| {
+            let _ = tx.send(payload);
+        }));
+        Ok(Self {
+            subscriber: Subscriber::new(0, config, output),
+            delivered,
+        })
+    }
+
+    /// The frames delivered for `samples`.
+    fn push(
+        &mut self,
+        samples: &[f32],
+        rate: u32,
+        layout: &ChannelLayout,
+    ) -> Result<Vec<Vec<i16>>> {
+        self.subscriber
+            .push(samples, rate, layout)
+            .map_err(anyhow::Error::msg)?;
+        Ok(self.take())
+    }
+
+    /// The frames still buffered at the end of the input.
+    fn finish(&mut self) -> Vec<Vec<i16>> {
+        self.subscriber.finish();
+        self.take()
+    }
+
+    fn take(&self) -> Vec<Vec<i16>> {
+        self.delivered
+            .try_iter()
+            .filter_map(|payload| match payload {
+                Payload::Binary(bytes) => Some(
+                    bytes
+                        .chunks_exact(2)
+                        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                        .collect(),
+                ),
+                Payload::Text(_) => None,
+            })
+            .collect()
+    }
+
+    /// Frames a pipeline stage has seen.
+    fn stage_frames(&self, stage: &str) -> u32 {
+        self.stage(stage).map_or(0, |s| s.frames)
+    }
+
+    /// A counter reported by a pipeline stage, e.g. `suppressSilence`'s
+    /// `keepalive`.
+    fn stage_count(&self, stage: &str, value: &str) -> u64 {
+        self.stage(stage)
+            .and_then(|s| s.values.get(value).copied())
+            .map_or(0, |v| v as u64)
+    }
+
+    fn stage(&self, name: &str) -> Option<StageStats> {
+        self.subscriber
+            .pipeline_stats()
+            .stages
+            .into_iter()
+            .find(|s| s.name == name)
+    }
+}
+
+/// Utterance events from the library's segmenter.
+struct Utterances {
+    subscriber: UtteranceSubscriber,
+    events: mpsc::Receiver<UtteranceEvent>,
+}
+
+impl Utterances {
+    fn new(args: &Args) -> Result<Self> {
+        let options = UtteranceOptions {
+            deliver_audio: None,
+            min_duration_ms: args.number("--min-utterance-ms")?,
+            max_duration_ms: args.number("--max-utterance-ms")?,
+            diarize: Some(args.has("--diarize")),
+            speaker_model: args.value("--speaker-model").map(String::from),
+            speaker_threshold: None,
+            max_speakers: None,
+            high_pass_hz: args.number("--high-pass")?,
+        };
+        let config = SegmenterConfig::from_options(Some(options)).map_err(|e| anyhow!(e.reason))?;
+        let (tx, events) = mpsc::channel();
+        let subscriber = UtteranceSubscriber::with_events(
+            0,
+            config,
+            Box::new(move |event| {
+                let _ = tx.send(event);
+            }),
+        )
+        .map_err(|e| anyhow!(e.reason))?;
+        Ok(Self { subscriber, events })
+    }
+
+    /// Events for a block of mono audio.
+    fn push(&mut self, mono: &[f32], rate: u32) -> Vec<UtteranceEvent> {
+        self.subscriber.push(mono, rate);
+        self.events.try_iter().collect()
+    }
+
+    /// The utterance in progress and the outstanding speaker labels.
+    fn finish(&mut self) -> Vec<UtteranceEvent> {
+        self.subscriber.finish();
+        self.events.try_iter().collect()
+    }
+}
+
+fn print_boundary(event: &UtteranceEvent) {
+    match (event.kind.as_str(), event.end_ms) {
+        ("speechStart", _) => println!("  -- speech from {:.2} s", secs(event.start_ms)),
+        ("speechEnd", Some(end_ms)) => println!(
+            "  -- speech {:.2}-{:.2} s ({:.2} s)",
+            secs(event.start_ms),
+            secs(end_ms),
+            secs(end_ms - event.start_ms)
+        ),
+        _ => {}
+    }
+}
+
+/// Prints a level/VAD line ten times a second, plus utterance boundaries.
+fn levels(args: &Args) -> Result<()> {
+    let seconds: Option<f64> = args.number("--seconds")?;
+    let mut source = Source::open(args)?;
+    let (rate, layout) = (source.sample_rate(), source.layout());
+    let meter_config = MeterConfig {
+        rate_hz: READINGS_PER_SECOND,
+        ..MeterConfig::default()
+    };
+
+    let mut frames = FrameStream::new(SubscriberOptions {
+        high_pass_hz: args.number("--high-pass")?,
+        vad: Some(true),
+        ..SubscriberOptions::default()
+    })?;
+    let mut utterances = Utterances::new(args)?;
+    let mut mono_mix =
+        Downmixer::new(DownmixMode::Average, layout.clone()).map_err(|e| anyhow!(e))?;
+    let mut meter = LevelMeter::new(meter_config, rate);
+    let mut loudness = LoudnessMeter::new(rate, &layout);
+    let mut speech = false;
+    let mut readings = 0u64;
+
+    println!(
+        "{:>8}  {:>8}  {:>8}  {:>8}  vad",
+        "time", "rms dB", "peak dB", "LUFS-M"
+    );
+    while let Some(samples) = source.read()? {
+        let mono = mono_mix.process(&samples);
+        loudness.push(&samples);
+
+        // The VAD stage drops non-speech frames, so a block is speech if
+        // any of the frames it completed came through.
+        let seen = frames.stage_frames("vad");
+        let delivered = frames.push(&samples, rate, &layout)?;
+        if frames.stage_frames("vad") > seen {
+            speech = !delivered.is_empty();
+        }
+        for event in utterances.push(&mono, rate) {
+            print_boundary(&event);
+        }
+
+        for reading in meter.push(&mono) {
+            readings += 1;
+            println!(
+                "{:>7.1}s  {:>8.1}  {:>8.1}  {:>8}  {}",
+                readings as f64 / READINGS_PER_SECOND as f64,
+                to_dbfs(reading.rms),
+                to_dbfs(reading.peak),
+                loudness
+                    .momentary_lufs()
+                    .filter(|l| l.is_finite())
+                    .map(|l| format!("{:.1}", l))
+                    .unwrap_or_else(|| "-".to_string()),
+                if speech { "speech" } else { "-" }
+            );
+        }
+
+        if seconds.is_some_and(|s| readings as f64 >= s * READINGS_PER_SECOND as f64) {
+            break;
+        }
+    }
+    for event in utterances.finish() {
+        print_boundary(&event);
+    }
+    Ok(())
+}
+
+/// Runs a file through the 16 kHz pipeline as fast as possible and prints
+/// what the Node module would have delivered.
+fn process(args: &Args) -> Result<()> {
+    let path = args.path(0, "input audio path")?;
+    let mut reader = AudioFileReader::open(&path)?;
+    let (rate, channels) = (reader.sample_rate(), reader.channels());
+    let layout = ChannelLayout::default_for(channels);
+
+    let suppress_silence = args.has("--suppress-silence");
+    let mut frames = FrameStream::new(SubscriberOptions {
+        suppress_silence: Some(suppress_silence),
+        high_pass_hz: args.number("--high-pass")?,
+        ..SubscriberOptions::default()
+    })?;
+    let mut utterances = Utterances::new(args)?;
+    let mut output = args
+        .value("--output")
+        .map(|p| WavWriter::create(Path::new(p), SAMPLE_RATE, 1, SampleEncoding::I16))
+        .transpose()?;
+
+    let started = Instant::now();
+    let mut mono_mix =
+        Downmixer::new(DownmixMode::Average, layout.clone()).map_err(|e| anyhow!(e))?;
+    let mut loudness = LoudnessMeter::new(rate, &layout);
+    let (mut decoded, mut delivered) = (0usize, 0u64);
+    let mut events = Vec::new();
+    let mut write = |frames: Vec<Vec<i16>>| -> Result<()> {
+        for frame in frames {
+            delivered += 1;
+            if let Some(writer) = output.as_mut() {
+                writer.write_i16(&frame)?;
+            }
+        }
+        Ok(())
+    };
+
+    while let Some(samples) = reader.read()? {
+        decoded += samples.len();
+        loudness.push(&samples);
+        events.extend(utterances.push(&mono_mix.process(&samples), rate));
+        write(frames.push(&samples, rate, &layout)?)?;
+    }
+    write(frames.finish())?;
+    events.extend(utterances.finish());
+    let elapsed = started.elapsed().as_secs_f64();
+
+    if let Some(writer) = output {
+        writer.finish()?;
+    }
+
+    // Speaker labels follow the `speechEnd` they belong to.
+    let mut found: Vec<(f64, f64, Option<u32>)> = Vec::new();
+    for event in events {
+        match (event.kind.as_str(), event.end_ms) {
+            ("speechEnd", Some(end_ms)) => found.push((event.start_ms, end_ms, None)),
+            ("speaker", _) => {
+                if let Some(utterance) = found.iter_mut().find(|u| u.0 == event.start_ms) {
+                    utterance.2 = event.speaker;
+                }
+            }
+            _ => {}
+        }
+    }
+    let (sent, keepalive, suppressed) = if suppress_silence {
+        let count = |value| frames.stage_count("suppressSilence", value);
+        (count("sent"), count("keepalive"), count("suppressed"))
+    } else {
+        (delivered, 0, 0)
+    };
+
+    let duration = decoded as f64 / (rate as f64 * channels as f64);
+    let summary = loudness.summary();
+    println!(
+        "{}: {:.2} s, {} Hz, {} channel(s)",
+        path.display(),
+        duration,
+        rate,
+        channels
+    );
+    println!(
+        "processed in {:.2} s ({:.0}x real time)",
+        elapsed,
+        duration / elapsed.max(1e-6)
+    );
+    println!(
+        "frames: {} sent, {} keepalive, {} suppressed",
+        sent, keepalive, suppressed
+    );
+    if let Some(lufs) = summary.integrated_lufs {
+        println!("integrated loudness: {:.1} LUFS", lufs);
+    }
+    if let Some(peak) = summary.true_peak_dbtp {
+        println!("true peak: {:.1} dBTP", peak);
+    }
+    println!("utterances: {}", found.len());
+    for (start_ms, end_ms, speaker) in found {
+        match speaker {
+            Some(id) => println!(
+                "  {:>8.2} - {:>8.2} s  speaker {}",
+                secs(start_ms),
+                secs(end_ms),
+                id
+            ),
+            None => println!("  {:>8.2} - {:>8.2} s", secs(start_ms), secs(end_ms)),
+        }
+    }
+    Ok(())
+}
+
+fn secs(ms: f64) -> f64 {
+    ms / 1000.0
+}
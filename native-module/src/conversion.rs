@@ -0,0 +1,261 @@
+/// Where soft clipping starts bending the curve, as a fraction of full scale.
+const SOFT_CLIP_KNEE: f32 = 0.9;
+/// Largest value soft clipping produces: the top i16 code.
+const SOFT_CLIP_CEILING: f32 = 32_767.0 / 32_768.0;
+
+/// Rounds a sample in [-1, 1) to the nearest 16-bit value, saturating
+/// outside it. The inverse of the `s / 32768` used when reading i16.
+pub fn f32_to_i16(sample: f32) -> i16 {
+    (sample * 32_768.0).round().clamp(-32_768.0, 32_767.0) as i16
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ConversionConfig {
+    /// Add triangular (TPDF) dither of ±1 LSB before rounding, decorrelating
+    /// the quantization error from the signal. Exact zeros are left alone.
+    pub dither: bool,
+    /// Compress peaks above -0.9 dBFS smoothly into full scale instead of
+    /// clipping them flat.
+    pub soft_clip: bool,
+}
+
+/// Float to 16-bit conversion for audio that leaves the module, counting
+/// samples that had to be limited.
+pub struct I16Converter {
+    config: ConversionConfig,
+    rng: u32,
+    clipped: u64,
+    soft_clipped: u64,
+}
+
+impl I16Converter {
+    pub fn new(config: ConversionConfig) -> Self {
+        Self {
+            config,
+            rng: 0x9E37_79B9,
+            clipped: 0,
+            soft_clipped: 0,
+        }
+    }
+
+    pub fn convert(&mut self, samples: &[f32]) -> Vec<i16> {
+        samples.iter().map(|&s| self.convert_sample(s)).collect()
+    }
+
+    pub fn convert_sample(&mut self, sample: f32) -> i16 {
+        let mut sample = if sample.is_nan() { 0.0 } else { sample };
+        if self.config.soft_clip && sample.abs() > SOFT_CLIP_KNEE {
+            self.soft_clipped += 1;
+            sample = soft_clip(sample);
+        }
+
+        let mut scaled = sample * 32_768.0;
+        // Digital silence stays exact, so keepalive frames remain zeros.
+        if self.config.dither && sample != 0.0 {
+            scaled += self.uniform() + self.uniform();
+        }
+        let rounded = scaled.round();
+        if !(-32_768.0..=32_767.0).contains(&rounded) {
+            self.clipped += 1;
+        }
+        rounded.clamp(-32_768.0, 32_767.0) as i16
+    }
+
+    /// Samples limited to the i16 range.
+    pub fn clipped(&self) -> u64 {
+        self.clipped
+    }
+
+    /// Samples reshaped by the soft clipper.
+    pub fn soft_clipped(&self) -> u64 {
+        self.soft_clipped
+    }
+
+    /// Uniform in [-0.5, 0.5) LSB; two of them sum to TPDF dither. xorshift32
+    /// is plenty for noise and keeps the stream deterministic.
+    fn uniform(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+    }
+}
+
+/// Linear below the knee, then a tanh curve approaching full scale.
+fn soft_clip(sample: f32) -> f32 {
+    let headroom = SOFT_CLIP_CEILING - SOFT_CLIP_KNEE;
+    let over = (sample.abs() - SOFT_CLIP_KNEE) / headroom;
+    sample.signum() * (SOFT_CLIP_KNEE + headroom * over.tanh())
+}
+
+/// μ-law works on 14-bit magnitudes, biased so each segment starts on a
+/// power of two.
+const MULAW_BIAS: i32 = 0x21;
+const MULAW_CLIP: i32 = 8_159;
+/// Top of each μ-law segment, in biased 14-bit magnitude.
+const MULAW_SEGMENT_ENDS: [i32; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+/// Top of each A-law segment, in 13-bit magnitude.
+const ALAW_SEGMENT_ENDS: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+
+/// G.711 μ-law encoding of a 16-bit sample.
+pub fn linear_to_mulaw(sample: i16) -> u8 {
+    let mut magnitude = (sample as i32) >> 2;
+    let mask = if magnitude < 0 {
+        magnitude = -magnitude;
+        0x7F
+    } else {
+        0xFF
+    };
+    let biased = magnitude.min(MULAW_CLIP) + MULAW_BIAS;
+    let segment = match MULAW_SEGMENT_ENDS.iter().position(|&end| biased <= end) {
+        Some(segment) => segment as i32,
+        None => return 0x7F ^ mask,
+    };
+    let code = (segment << 4) | ((biased >> (segment + 1)) & 0x0F);
+    code as u8 ^ mask
+}
+
+pub fn mulaw_to_linear(byte: u8) -> i16 {
+    let byte = !byte;
+    let segment = (byte & 0x70) >> 4;
+    let mantissa = (byte & 0x0F) as i32;
+    // Decoded in 16-bit units, hence the bias scaled by 4.
+    let magnitude = (((mantissa << 3) + MULAW_BIAS * 4) << segment) - MULAW_BIAS * 4;
+    if byte & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+/// G.711 A-law encoding of a 16-bit sample.
+pub fn linear_to_alaw(sample: i16) -> u8 {
+    let mut magnitude = (sample as i32) >> 3;
+    let mask = if magnitude >= 0 {
+        0xD5
+    } else {
+        magnitude = -magnitude - 1;
+        0x55
+    };
+    let segment = match ALAW_SEGMENT_ENDS.iter().position(|&end| magnitude <= end) {
+        Some(segment) => segment as i32,
+        None => return 0x7F ^ mask,
+    };
+    let shift = if segment < 2 { 1 } else { segment };
+    let code = (segment << 4) | ((magnitude >> shift) & 0x0F);
+    code as u8 ^ mask
+}
+
+pub fn alaw_to_linear(byte: u8) -> i16 {
+    let byte = byte ^ 0x55;
+    let segment = (byte & 0x70) >> 4;
+    let mut magnitude = ((byte & 0x0F) as i32) << 4;
+    match segment {
+        0 => magnitude += 8,
+        1 => magnitude += 0x108,
+        _ => magnitude = (magnitude + 0x108) << (segment - 1),
+    }
+    if byte & 0x80 != 0 {
+        magnitude as i16
+    } else {
+        -magnitude as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_to_nearest_and_saturates_symmetrically() {
+        assert_eq!(f32_to_i16(0.0), 0);
+        assert_eq!(f32_to_i16(0.6 / 32_768.0), 1);
+        assert_eq!(f32_to_i16(-0.6 / 32_768.0), -1);
+        assert_eq!(f32_to_i16(0.5), 16_384);
+        assert_eq!(f32_to_i16(-1.0), -32_768);
+        assert_eq!(f32_to_i16(2.0), 32_767);
+        assert_eq!(f32_to_i16(-2.0), -32_768);
+    }
+
+    #[test]
+    fn counts_clipped_samples() {
+        let mut converter = I16Converter::new(ConversionConfig::default());
+        let out = converter.convert(&[0.5, 1.5, -1.0, -1.5]);
+        assert_eq!(out, vec![16_384, 32_767, -32_768, -32_768]);
+        assert_eq!(converter.clipped(), 2);
+    }
+
+    #[test]
+    fn soft_clip_stays_below_full_scale() {
+        let mut converter = I16Converter::new(ConversionConfig {
+            soft_clip: true,
+            ..ConversionConfig::default()
+        });
+        let out = converter.convert(&[0.5, 0.95, 3.0, -3.0]);
+        assert_eq!(out[0], 16_384);
+        assert!(out[1] > f32_to_i16(0.9) && out[1] < f32_to_i16(0.95));
+        assert!(out[2] > 32_000 && out[3] < -32_000);
+        assert_eq!((converter.soft_clipped(), converter.clipped()), (3, 0));
+    }
+
+    #[test]
+    fn dither_is_zero_mean_and_within_one_lsb() {
+        let mut converter = I16Converter::new(ConversionConfig {
+            dither: true,
+            ..ConversionConfig::default()
+        });
+        let level = 100.25 / 32_768.0;
+        let out = converter.convert(&vec![level; 10_000]);
+        assert!(out.iter().all(|&s| (99..=102).contains(&s)));
+        let mean = out.iter().map(|&s| s as f64).sum::<f64>() / out.len() as f64;
+        assert!((mean - 100.25).abs() < 0.05, "mean {}", mean);
+    }
+
+    #[test]
+    fn g711_silence_codes() {
+        assert_eq!(linear_to_mulaw(0), 0xFF);
+        assert_eq!(linear_to_alaw(0), 0xD5);
+        assert_eq!(mulaw_to_linear(0xFF), 0);
+        assert_eq!(alaw_to_linear(0xD5), 8);
+    }
+
+    #[test]
+    fn g711_round_trips_within_quantization_step() {
+        for sample in (-32_768..=32_767).step_by(7) {
+            let sample = sample as i16;
+            // Both laws keep roughly 4 mantissa bits plus sign.
+            let tolerance = (sample as i32).abs() / 16 + 16;
+
+            let mu = mulaw_to_linear(linear_to_mulaw(sample)) as i32;
+            let expected = (sample as i32).clamp(-MULAW_CLIP * 4, MULAW_CLIP * 4);
+            assert!(
+                (mu - expected).abs() <= tolerance,
+                "μ-law {} -> {}",
+                sample,
+                mu
+            );
+
+            let a = alaw_to_linear(linear_to_alaw(sample)) as i32;
+            assert!(
+                (a - sample as i32).abs() <= tolerance,
+                "A-law {} -> {}",
+                sample,
+                a
+            );
+        }
+    }
+
+    #[test]
+    fn g711_is_monotonic() {
+        let mut previous = (i32::MIN, i32::MIN);
+        for sample in (-32_768..=32_767).step_by(3) {
+            let sample = sample as i16;
+            let decoded = (
+                mulaw_to_linear(linear_to_mulaw(sample)) as i32,
+                alaw_to_linear(linear_to_alaw(sample)) as i32,
+            );
+            assert!(decoded.0 >= previous.0 && decoded.1 >= previous.1);
+            previous = decoded;
+        }
+    }
+}
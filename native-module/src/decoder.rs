@@ -0,0 +1,183 @@
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::path::Path;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Streams interleaved `f32` samples out of a compressed or PCM audio file
+/// (WAV, MP3, AAC/M4A, FLAC, Ogg Vorbis), first audio track only.
+pub struct AudioFileReader {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    sample_rate: u32,
+    channels: usize,
+    total_frames: Option<u64>,
+    /// First block, decoded while opening to learn the channel count.
+    pending: Option<Vec<f32>>,
+}
+
+impl AudioFileReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+
+        let probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(|e| anyhow!("Unsupported audio file {}: {}", path.display(), e))?;
+        let format = probed.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow!("{} has no audio track", path.display()))?;
+        let params = track.codec_params.clone();
+        let decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .map_err(|e| anyhow!("Unsupported codec in {}: {}", path.display(), e))?;
+
+        let mut reader = Self {
+            track_id: track.id,
+            format,
+            decoder,
+            sample_rate: params.sample_rate.unwrap_or(0),
+            channels: params.channels.map(|c| c.count()).unwrap_or(0),
+            total_frames: params.n_frames,
+            pending: None,
+        };
+        reader.pending = reader.decode_next()?;
+        if reader.sample_rate == 0 || reader.channels == 0 {
+            return Err(anyhow!("{} has an unknown sample format", path.display()));
+        }
+        Ok(reader)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Frames in the file, if the container says.
+    pub fn total_frames(&self) -> Option<u64> {
+        self.total_frames
+    }
+
+    /// The next block of interleaved samples; `None` at the end of the file.
+    pub fn read(&mut self) -> Result<Option<Vec<f32>>> {
+        match self.pending.take() {
+            Some(samples) => Ok(Some(samples)),
+            None => self.decode_next(),
+        }
+    }
+
+    fn decode_next(&mut self) -> Result<Option<Vec<f32>>> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(ref e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(anyhow!("Failed to read audio: {}", e)),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet is skipped, as players do.
+                Err(Error::DecodeError(_)) => continue,
+                Err(e) => return Err(anyhow!("Failed to decode audio: {}", e)),
+            };
+            if decoded.frames() == 0 {
+                continue;
+            }
+
+            let spec = *decoded.spec();
+            self.sample_rate = spec.rate;
+            self.channels = spec.channels.count();
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+            return Ok(Some(buffer.samples().to_vec()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample_format::SampleEncoding;
+    use crate::wav::WavWriter;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("nyx-audio-{}-{}", std::process::id(), name))
+    }
+
+    fn read_all(reader: &mut AudioFileReader) -> Vec<f32> {
+        let mut samples = Vec::new();
+        while let Some(block) = reader.read().unwrap() {
+            samples.extend(block);
+        }
+        samples
+    }
+
+    #[test]
+    fn decodes_i16_and_f32_wav() {
+        let samples: Vec<f32> = (0..3_000)
+            .map(|i| ((i % 200) as f32 - 100.0) / 128.0)
+            .collect();
+        for (encoding, tolerance) in [
+            (SampleEncoding::I16, 1.0 / 32_768.0),
+            (SampleEncoding::F32, 0.0),
+        ] {
+            let path = temp_path(&format!("decoder-{:?}.wav", encoding));
+            let mut writer = WavWriter::create(&path, 22_050, 3, encoding).unwrap();
+            writer.write(&samples).unwrap();
+            writer.finish().unwrap();
+
+            let mut reader = AudioFileReader::open(&path).unwrap();
+            assert_eq!(reader.sample_rate(), 22_050);
+            assert_eq!(reader.channels(), 3);
+            assert_eq!(reader.total_frames(), Some(1_000));
+            let decoded = read_all(&mut reader);
+            assert!(reader.read().unwrap().is_none());
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(decoded.len(), samples.len());
+            for (d, s) in decoded.iter().zip(&samples) {
+                assert!((d - s).abs() <= tolerance, "{:?}: {} vs {}", encoding, d, s);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_files() {
+        assert!(AudioFileReader::open(&temp_path("missing.wav")).is_err());
+
+        let path = temp_path("not-audio.txt");
+        std::fs::write(&path, b"not audio at all, just some text").unwrap();
+        let result = AudioFileReader::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
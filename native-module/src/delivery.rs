@@ -0,0 +1,477 @@
+use napi::bindgen_prelude::{
+    Buffer, Either3, FromNapiValue, FunctionRef, JsValuesTupleIntoVec, ToNapiValue, Unknown,
+};
+use napi::threadsafe_function::{
+    ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{sys, CallContext, Env, JsFunction, JsUndefined, NapiRaw};
+use napi_derive::js_function;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::framing::{Chunk, Payload};
+
+const DEFAULT_MAX_QUEUE_MS: u32 = 2_000;
+
+/// What to do with new audio when the JS thread is behind.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued chunk; the next one delivered carries a
+    /// `DeliveryGap`.
+    DropOldest,
+    /// Hold new audio back and deliver it as one larger chunk once there is
+    /// room, dropping only what exceeds the queue duration.
+    Coalesce,
+}
+
+impl OverflowPolicy {
+    pub fn parse(value: &str) -> napi::Result<Self> {
+        match value {
+            "dropOldest" => Ok(OverflowPolicy::DropOldest),
+            "coalesce" => Ok(OverflowPolicy::Coalesce),
+            other => Err(napi::Error::from_reason(format!(
+                "Unknown overflow policy: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DeliveryConfig {
+    /// Audio waiting for the JS thread at most.
+    pub max_queue: Duration,
+    pub policy: OverflowPolicy,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            max_queue: Duration::from_millis(DEFAULT_MAX_QUEUE_MS as u64),
+            policy: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+/// Passed as the callback's second argument when audio before the chunk was
+/// dropped because the JS thread fell behind.
+#[napi(object)]
+#[derive(Clone, Default)]
+pub struct DeliveryGap {
+    pub dropped_chunks: u32,
+    pub dropped_ms: f64,
+}
+
+impl DeliveryGap {
+    fn add(&mut self, other: &DeliveryGap) {
+        self.dropped_chunks += other.dropped_chunks;
+        self.dropped_ms += other.dropped_ms;
+    }
+}
+
+#[napi(object)]
+#[derive(Clone, Default)]
+pub struct DeliveryStats {
+    /// Chunks waiting for the JS thread now.
+    pub queue_depth: u32,
+    pub max_queue_depth: u32,
+    pub queue_limit: u32,
+    pub delivered_chunks: f64,
+    pub dropped_chunks: f64,
+    pub dropped_ms: f64,
+    /// Time the last chunk waited between being queued and its callback.
+    pub latency_ms: f64,
+    pub average_latency_ms: f64,
+    pub max_latency_ms: f64,
+}
+
+struct Queued {
+    payload: Payload,
+    duration_ms: f64,
+    gap: Option<DeliveryGap>,
+    queued_at: Instant,
+}
+
+#[derive(Default)]
+struct QueueState {
+    limit: usize,
+    entries: VecDeque<Queued>,
+    /// Dropped audio not yet attached to a queued chunk.
+    gap: Option<DeliveryGap>,
+    stats: DeliveryStats,
+    total_latency_ms: f64,
+}
+
+/// A chunk taken off the queue, with the audio dropped before it.
+pub struct Delivery {
+    pub payload: Payload,
+    pub gap: Option<DeliveryGap>,
+}
+
+/// Bounded hand-off of chunks to a JS callback. Each queued chunk has
+/// exactly one pending notification, whose receiver takes whatever chunk
+/// is oldest when it runs; replacing the oldest chunk therefore needs no
+/// new notification, and the JS side never sees more calls than the limit.
+pub struct DeliveryQueue {
+    config: DeliveryConfig,
+    state: Arc<Mutex<QueueState>>,
+    notify: Box<dyn Fn() + Send>,
+}
+
+/// Takes chunks off a `DeliveryQueue`, one per notification.
+#[derive(Clone)]
+pub struct DeliveryReceiver {
+    state: Arc<Mutex<QueueState>>,
+}
+
+/// Arguments of one callback: the chunk, then the gap if there was one.
+struct ChunkArgs(Vec<Either3<Buffer, String, DeliveryGap>>);
+
+impl JsValuesTupleIntoVec for ChunkArgs {
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn into_vec(self, env: sys::napi_env) -> napi::Result<Vec<sys::napi_value>> {
+        self.0
+            .into_iter()
+            .map(|arg| unsafe { ToNapiValue::to_napi_value(env, arg) })
+            .collect()
+    }
+}
+
+/// The subscriber's callback, called from the threadsafe function's own.
+struct Callback(FunctionRef<ChunkArgs, Unknown>);
+
+// Only used, and dropped, on the JS thread: the threadsafe function calls
+// and finalizes its closure there.
+unsafe impl Send for Callback {}
+
+impl Callback {
+    fn call(&self, env: &Env, args: Vec<Either3<Buffer, String, DeliveryGap>>) -> napi::Result<()> {
+        self.0.borrow_back(env)?.call(ChunkArgs(args))?;
+        Ok(())
+    }
+}
+
+#[js_function]
+fn wake(ctx: CallContext) -> napi::Result<JsUndefined> {
+    ctx.env.get_undefined()
+}
+
+impl DeliveryQueue {
+    /// `chunk_ms` is the audio per chunk, used to size the queue.
+    pub fn new(
+        env: Env,
+        callback: &JsFunction,
+        config: DeliveryConfig,
+        chunk_ms: u32,
+    ) -> napi::Result<Self> {
+        let callback =
+            Callback(unsafe { FunctionRef::from_napi_value(env.raw(), callback.raw())? });
+        // The threadsafe function calls a no-op; the callback itself is only
+        // called when there is a chunk, so a drained queue calls nothing.
+        let wake = env.create_function("deliver", wake)?;
+
+        let mut queue = Self::with_notify(config, chunk_ms, || {});
+        let receiver = queue.receiver();
+        let tsfn: ThreadsafeFunction<(), ErrorStrategy::Fatal> =
+            wake.create_threadsafe_function(0, move |ctx: ThreadSafeCallContext<()>| {
+                if let Some(delivery) = receiver.next() {
+                    let mut args = vec![match delivery.payload {
+                        Payload::Binary(bytes) => Either3::A(Buffer::from(bytes)),
+                        Payload::Text(text) => Either3::B(text),
+                    }];
+                    if let Some(gap) = delivery.gap {
+                        args.push(Either3::C(gap));
+                    }
+                    callback.call(&ctx.env, args)?;
+                }
+                Ok(Vec::<()>::new())
+            })?;
+        queue.notify = Box::new(move || {
+            tsfn.call((), ThreadsafeFunctionCallMode::NonBlocking);
+        });
+        Ok(queue)
+    }
+
+    /// For native consumers: `notify` is called once per chunk queued
+    /// without replacing another, and each call should take one chunk from
+    /// `receiver`.
+    pub fn with_notify(
+        config: DeliveryConfig,
+        chunk_ms: u32,
+        notify: impl Fn() + Send + 'static,
+    ) -> Self {
+        let limit = (config.max_queue.as_millis() as u32)
+            .div_ceil(chunk_ms.max(1))
+            .max(1) as usize;
+        Self {
+            config,
+            state: Arc::new(Mutex::new(QueueState::new(limit))),
+            notify: Box::new(notify),
+        }
+    }
+
+    pub fn receiver(&self) -> DeliveryReceiver {
+        DeliveryReceiver {
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.config.policy
+    }
+
+    /// Longest audio held back while coalescing.
+    pub fn max_queue(&self) -> Duration {
+        self.config.max_queue
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.state
+            .lock()
+            .map(|s| s.entries.len() >= s.limit)
+            .unwrap_or(false)
+    }
+
+    /// Queues a chunk for the callback. A full queue loses its oldest chunk
+    /// whatever the policy; coalescing subscribers hold audio back instead
+    /// of getting here.
+    pub fn push(&self, chunk: Chunk) {
+        let replaced = match self.state.lock() {
+            Ok(mut state) => state.push(chunk, Instant::now()),
+            Err(_) => return,
+        };
+        // The dropped chunk's notification delivers the new one.
+        if !replaced {
+            (self.notify)();
+        }
+    }
+
+    /// Records audio dropped before it was queued, reported with the next
+    /// chunk queued.
+    pub fn note_dropped(&self, dropped_ms: f64) {
+        if let Ok(mut state) = self.state.lock() {
+            state.stats.dropped_ms += dropped_ms;
+            state
+                .gap
+                .get_or_insert_with(DeliveryGap::default)
+                .dropped_ms += dropped_ms;
+        }
+    }
+
+    pub fn stats(&self) -> DeliveryStats {
+        self.state.lock().map(|s| s.stats()).unwrap_or_default()
+    }
+}
+
+impl DeliveryReceiver {
+    /// Takes the oldest chunk, if any, and records how long it waited.
+    pub fn next(&self) -> Option<Delivery> {
+        self.state.lock().ok()?.next(Instant::now())
+    }
+}
+
+impl QueueState {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            stats: DeliveryStats {
+                queue_limit: limit as u32,
+                ..DeliveryStats::default()
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Queues a chunk with the pending gap, dropping the oldest one if the
+    /// queue is full. Returns whether one was dropped.
+    fn push(&mut self, chunk: Chunk, now: Instant) -> bool {
+        let replaced = self.entries.len() >= self.limit;
+        if replaced {
+            self.drop_oldest();
+        }
+
+        let gap = self.gap.take();
+        self.entries.push_back(Queued {
+            payload: chunk.payload,
+            duration_ms: chunk.duration_ms,
+            gap,
+            queued_at: now,
+        });
+        let depth = self.entries.len() as u32;
+        self.stats.max_queue_depth = self.stats.max_queue_depth.max(depth);
+        replaced
+    }
+
+    fn next(&mut self, now: Instant) -> Option<Delivery> {
+        let entry = self.entries.pop_front()?;
+        self.record_delivery(now.duration_since(entry.queued_at));
+        Some(Delivery {
+            payload: entry.payload,
+            gap: entry.gap,
+        })
+    }
+
+    fn stats(&self) -> DeliveryStats {
+        DeliveryStats {
+            queue_depth: self.entries.len() as u32,
+            ..self.stats.clone()
+        }
+    }
+
+    /// Removes the oldest chunk. It, and any gap it was carrying, becomes
+    /// the gap of the chunk now oldest, or of the next chunk queued.
+    fn drop_oldest(&mut self) {
+        let oldest = match self.entries.pop_front() {
+            Some(oldest) => oldest,
+            None => return,
+        };
+        self.stats.dropped_chunks += 1.0;
+        self.stats.dropped_ms += oldest.duration_ms;
+
+        let mut gap = oldest.gap.unwrap_or_default();
+        gap.add(&DeliveryGap {
+            dropped_chunks: 1,
+            dropped_ms: oldest.duration_ms,
+        });
+        match self.entries.front_mut() {
+            Some(front) => front.gap.get_or_insert_with(DeliveryGap::default),
+            None => self.gap.get_or_insert_with(DeliveryGap::default),
+        }
+        .add(&gap);
+    }
+
+    fn record_delivery(&mut self, waited: Duration) {
+        let waited_ms = waited.as_secs_f64() * 1000.0;
+        self.stats.delivered_chunks += 1.0;
+        self.total_latency_ms += waited_ms;
+        self.stats.latency_ms = waited_ms;
+        self.stats.average_latency_ms = self.total_latency_ms / self.stats.delivered_chunks;
+        self.stats.max_latency_ms = self.stats.max_latency_ms.max(waited_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn chunk(duration_ms: f64) -> Chunk {
+        Chunk {
+            payload: Payload::Binary(vec![duration_ms as u8]),
+            duration_ms,
+        }
+    }
+
+    /// A 40 ms queue of 20 ms chunks, counting its notifications.
+    fn counted(policy: OverflowPolicy) -> (DeliveryQueue, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let config = DeliveryConfig {
+            max_queue: Duration::from_millis(40),
+            policy,
+        };
+        let queue = DeliveryQueue::with_notify(config, 20, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        (queue, calls)
+    }
+
+    fn queued(duration_ms: f64) -> Queued {
+        Queued {
+            payload: Payload::Binary(Vec::new()),
+            duration_ms,
+            gap: None,
+            queued_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn dropped_chunks_become_the_gap_of_the_next_one() {
+        let mut state = QueueState::default();
+        state
+            .entries
+            .extend([queued(10.0), queued(20.0), queued(30.0)]);
+
+        state.drop_oldest();
+        state.drop_oldest();
+        let gap = state.entries[0].gap.clone().unwrap();
+        assert_eq!((gap.dropped_chunks, gap.dropped_ms), (2, 30.0));
+
+        // With the queue empty the gap waits for the next chunk.
+        state.drop_oldest();
+        let gap = state.gap.clone().unwrap();
+        assert_eq!((gap.dropped_chunks, gap.dropped_ms), (3, 60.0));
+        assert_eq!(
+            (state.stats.dropped_chunks, state.stats.dropped_ms),
+            (3.0, 60.0)
+        );
+    }
+
+    #[test]
+    fn drop_oldest_replaces_without_a_new_call_and_reports_the_gap() {
+        let (queue, calls) = counted(OverflowPolicy::DropOldest);
+        let receiver = queue.receiver();
+        for ms in [10.0, 20.0, 30.0, 40.0] {
+            queue.push(chunk(ms));
+        }
+        assert!(queue.is_full());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let third = receiver.next().unwrap();
+        assert!(matches!(third.payload, Payload::Binary(ref b) if b == &[30]));
+        let gap = third.gap.unwrap();
+        assert_eq!((gap.dropped_chunks, gap.dropped_ms), (2, 30.0));
+        assert!(receiver.next().unwrap().gap.is_none());
+        // One chunk per call: the queue is drained when the calls are.
+        assert!(receiver.next().is_none());
+
+        let stats = queue.stats();
+        assert_eq!((stats.dropped_chunks, stats.dropped_ms), (2.0, 30.0));
+        assert_eq!(stats.delivered_chunks, 2.0);
+    }
+
+    #[test]
+    fn audio_dropped_before_queueing_is_the_gap_of_the_next_chunk() {
+        let (queue, _) = counted(OverflowPolicy::Coalesce);
+        let receiver = queue.receiver();
+        queue.note_dropped(15.0);
+        queue.push(chunk(20.0));
+        queue.push(chunk(20.0));
+
+        let gap = receiver.next().unwrap().gap.unwrap();
+        assert_eq!((gap.dropped_chunks, gap.dropped_ms), (0, 15.0));
+        assert!(receiver.next().unwrap().gap.is_none());
+        let stats = queue.stats();
+        assert_eq!((stats.dropped_chunks, stats.dropped_ms), (0.0, 15.0));
+    }
+
+    #[test]
+    fn stats_track_depth_and_latency() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut state = QueueState::new(3);
+        state.push(chunk(20.0), at(0));
+        state.push(chunk(20.0), at(10));
+        let stats = state.stats();
+        assert_eq!(
+            (stats.queue_depth, stats.max_queue_depth, stats.queue_limit),
+            (2, 2, 3)
+        );
+
+        state.next(at(30)).unwrap();
+        state.next(at(20)).unwrap();
+        let stats = state.stats();
+        assert_eq!((stats.queue_depth, stats.max_queue_depth), (0, 2));
+        assert_eq!(stats.delivered_chunks, 2.0);
+        assert_eq!(
+            (
+                stats.latency_ms,
+                stats.average_latency_ms,
+                stats.max_latency_ms
+            ),
+            (10.0, 20.0, 30.0)
+        );
+        assert!(state.next(at(40)).is_none());
+    }
+}
@@ -0,0 +1,270 @@
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::pipeline::{Flow, Frame, Stage};
+
+/// The noise floor of a bin is the quietest it has been over the last
+/// 1.5 s, tracked as the minimum of two alternating halves.
+const NOISE_WINDOW: Duration = Duration::from_millis(750);
+/// Corrects the minimum of the smoothed power up to its mean, then
+/// over-subtracts so that residual noise is not left as isolated tones.
+const NOISE_SCALE: f32 = 8.0;
+/// Power smoothing per hop ahead of the minimum search.
+const SMOOTHING: f32 = 0.7;
+/// Most a bin is attenuated, in power (-20 dB).
+const FLOOR: f32 = 0.01;
+
+/// Spectral subtraction noise reduction. Each channel is cut into 50%
+/// overlapping windows of about 20 ms (square-root Hann, so analysis and
+/// synthesis together sum to one); every bin keeps only its power above the
+/// estimated noise floor. Adds one window of latency.
+pub struct DenoiseStage {
+    sample_rate: u32,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    hops_per_half: usize,
+    channels: Vec<ChannelState>,
+    /// Input over output energy of the last frame, in dB.
+    reduction_db: f64,
+}
+
+struct ChannelState {
+    input: Vec<f32>,
+    overlap: Vec<f32>,
+    output: VecDeque<f32>,
+    smoothed: Vec<f32>,
+    current_min: Vec<f32>,
+    previous_min: Vec<f32>,
+    hops: usize,
+}
+
+impl DenoiseStage {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let size = (sample_rate as usize / 50).next_power_of_two().max(64);
+        let hop = size / 2;
+        let mut planner = FftPlanner::new();
+        let window = (0..size)
+            .map(|i| (0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos()).sqrt())
+            .collect();
+        let hops_per_half =
+            ((NOISE_WINDOW.as_secs_f64() * sample_rate as f64 / hop as f64).ceil() as usize).max(1);
+
+        let mut stage = Self {
+            sample_rate,
+            fft: planner.plan_fft_forward(size),
+            ifft: planner.plan_fft_inverse(size),
+            window,
+            hops_per_half,
+            channels: Vec::new(),
+            reduction_db: 0.0,
+        };
+        stage.channels = (0..channels.max(1)).map(|_| stage.channel()).collect();
+        stage
+    }
+
+    fn size(&self) -> usize {
+        self.window.len()
+    }
+
+    fn channel(&self) -> ChannelState {
+        let size = self.size();
+        let bins = size / 2 + 1;
+        ChannelState {
+            // Primed so that every hop of input yields a hop of output.
+            input: vec![0.0; size - size / 2],
+            overlap: vec![0.0; size],
+            output: VecDeque::from(vec![0.0; size / 2]),
+            smoothed: vec![0.0; bins],
+            current_min: vec![f32::MAX; bins],
+            previous_min: vec![f32::MAX; bins],
+            hops: 0,
+        }
+    }
+
+    fn process_plane(&mut self, ch: usize, plane: &mut [f32]) {
+        let size = self.size();
+        let hop = size / 2;
+        let state = &mut self.channels[ch];
+        state.input.extend_from_slice(plane);
+
+        let mut buffer = vec![Complex::new(0.0f32, 0.0); size];
+        let mut gains = vec![1.0f32; size / 2 + 1];
+        while state.input.len() >= size {
+            for (slot, (&x, &w)) in buffer.iter_mut().zip(state.input.iter().zip(&self.window)) {
+                *slot = Complex::new(x * w, 0.0);
+            }
+            self.fft.process(&mut buffer);
+
+            if state.hops.is_multiple_of(self.hops_per_half) {
+                std::mem::swap(&mut state.previous_min, &mut state.current_min);
+                state.current_min.fill(f32::MAX);
+            }
+            state.hops += 1;
+            for (k, gain) in gains.iter_mut().enumerate() {
+                let power = buffer[k].norm_sqr();
+                let smoothed = &mut state.smoothed[k];
+                *smoothed = SMOOTHING * *smoothed + (1.0 - SMOOTHING) * power;
+                state.current_min[k] = state.current_min[k].min(*smoothed);
+                let noise = NOISE_SCALE * state.current_min[k].min(state.previous_min[k]);
+                *gain = if power > 0.0 {
+                    (1.0 - noise / power).max(FLOOR).sqrt()
+                } else {
+                    1.0
+                };
+            }
+            for (k, bin) in buffer.iter_mut().enumerate() {
+                *bin *= gains[k.min(size - k)];
+            }
+            self.ifft.process(&mut buffer);
+
+            for ((acc, bin), &w) in state.overlap.iter_mut().zip(&buffer).zip(&self.window) {
+                *acc += bin.re * w / size as f32;
+            }
+            state.output.extend(state.overlap.drain(..hop));
+            state.overlap.resize(size, 0.0);
+            state.input.drain(..hop);
+        }
+
+        for sample in plane.iter_mut() {
+            *sample = state.output.pop_front().unwrap_or(0.0);
+        }
+    }
+}
+
+impl Stage for DenoiseStage {
+    fn name(&self) -> &'static str {
+        "denoise"
+    }
+
+    fn process(&mut self, frame: &mut Frame) -> Flow {
+        let energy = |samples: &[f32]| samples.iter().map(|&s| s as f64 * s as f64).sum::<f64>();
+        let before = energy(&frame.samples);
+
+        let channels = self.channels.len();
+        if channels == 1 {
+            self.process_plane(0, &mut frame.samples);
+        } else {
+            for ch in 0..channels {
+                let mut plane: Vec<f32> = frame
+                    .samples
+                    .iter()
+                    .skip(ch)
+                    .step_by(channels)
+                    .copied()
+                    .collect();
+                self.process_plane(ch, &mut plane);
+                for (sample, denoised) in frame
+                    .samples
+                    .iter_mut()
+                    .skip(ch)
+                    .step_by(channels)
+                    .zip(plane)
+                {
+                    *sample = denoised;
+                }
+            }
+        }
+
+        let after = energy(&frame.samples);
+        if before > 0.0 && after > 0.0 {
+            self.reduction_db = 10.0 * (before / after).log10();
+        }
+        Flow::Continue
+    }
+
+    fn reset(&mut self) {
+        self.channels = (0..self.channels.len()).map(|_| self.channel()).collect();
+        self.reduction_db = 0.0;
+    }
+
+    fn latency(&self) -> Duration {
+        Duration::from_secs_f64(self.size() as f64 / self.sample_rate as f64)
+    }
+
+    fn stats(&self) -> HashMap<String, f64> {
+        HashMap::from([("reductionDb".to_string(), self.reduction_db)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16_000;
+
+    fn noise(samples: usize, amplitude: f32) -> Vec<f32> {
+        let mut state = 7u32;
+        (0..samples)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                amplitude * ((state >> 8) as f32 / (1 << 23) as f32 - 1.0)
+            })
+            .collect()
+    }
+
+    fn tone(samples: usize, amplitude: f32) -> Vec<f32> {
+        (0..samples)
+            .map(|i| amplitude * (2.0 * PI * 1_000.0 * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// Runs `input` through in 20 ms frames.
+    fn run(stage: &mut DenoiseStage, input: &[f32]) -> Vec<f32> {
+        input
+            .chunks(320)
+            .flat_map(|chunk| {
+                let mut frame = Frame::new(chunk.to_vec(), 1);
+                stage.process(&mut frame);
+                frame.samples
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sound_over_silence_comes_out_delayed() {
+        let mut stage = DenoiseStage::new(RATE, 1);
+        let delay = (stage.latency().as_secs_f64() * RATE as f64).round() as usize;
+        assert_eq!(delay, 512);
+
+        let mut input = vec![0.0; RATE as usize];
+        input[8_000..8_320].copy_from_slice(&tone(320, 0.5));
+        let output = run(&mut stage, &input);
+        assert_eq!(output.len(), input.len());
+        for (out, x) in output[delay..].iter().zip(&input) {
+            assert!((out - x).abs() < 1e-3, "{} vs {}", out, x);
+        }
+    }
+
+    #[test]
+    fn steady_noise_is_attenuated_and_speech_like_bursts_kept() {
+        let samples = 3 * RATE as usize;
+        let mut stage = DenoiseStage::new(RATE, 1);
+        let output = run(&mut stage, &noise(samples, 0.05));
+        let last = samples - RATE as usize..samples;
+        let reduction = 20.0 * (0.05 / 3f32.sqrt() / rms(&output[last])).log10();
+        assert!(reduction > 10.0, "{} dB", reduction);
+
+        // 250 ms of tone every 750 ms: the floor is found in the pauses.
+        stage.reset();
+        let burst = tone(samples, 0.3);
+        let mixed: Vec<f32> = noise(samples, 0.05)
+            .iter()
+            .enumerate()
+            .map(|(i, n)| if i % 12_000 < 4_000 { n + burst[i] } else { *n })
+            .collect();
+        let output = run(&mut stage, &mixed);
+        // The last burst, clear of the window edges and the delay.
+        let last_burst = 36_000 + 1_024..40_000;
+        let level = rms(&output[last_burst]) / (0.3 / 2f32.sqrt());
+        assert!((0.9..1.1).contains(&level), "tone at {}", level);
+    }
+}
@@ -0,0 +1,513 @@
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use crate::mfcc::{Fbank, Mfcc, CEPSTRA};
+
+/// Frames quieter than this (relative to the loudest in the utterance) are
+/// pauses and left out of the embedding.
+const ENERGY_FLOOR_DB: f32 = 30.0;
+/// Frames below this level (dBFS) never count as voice.
+const SILENCE_DB: f32 = -60.0;
+/// Voiced frames (10 ms each) needed for a usable embedding.
+const MIN_STATISTICS_FRAMES: usize = 10;
+const MIN_MODEL_FRAMES: usize = 30;
+/// Mel bands fed to speaker models (Kaldi-style 80-dimensional fbank).
+const MODEL_BANDS: usize = 80;
+/// Utterances waiting for the worker; more are reported unlabelled.
+const QUEUE_UTTERANCES: usize = 8;
+
+/// Default for the MFCC-statistics embedder, whose vectors of different
+/// voices still lie close together.
+pub const DEFAULT_SIMILARITY_THRESHOLD: f64 = 0.85;
+/// Default for model embeddings (ECAPA-TDNN, ResNet), where scores of the
+/// same speaker typically sit above 0.6 and of others near 0.
+pub const DEFAULT_MODEL_SIMILARITY_THRESHOLD: f64 = 0.5;
+pub const DEFAULT_MAX_SPEAKERS: u32 = 8;
+
+/// Turns 16 kHz mono speech into a fixed-length voice signature.
+pub enum SpeakerEmbedder {
+    /// Mean and standard deviation of the utterance's liftered MFCCs. Needs
+    /// no model file, but only tells clearly different voices apart.
+    Statistics(Mfcc),
+    /// A speaker verification network run with ONNX Runtime (`speaker-model`
+    /// feature), taking `[1, frames, 80]` mean-normalised fbank features and
+    /// returning `[1, dimensions]`.
+    Model(Fbank, model::Model),
+}
+
+impl SpeakerEmbedder {
+    pub fn statistics() -> Self {
+        Self::Statistics(Mfcc::new())
+    }
+
+    pub fn load(model_path: &str) -> Result<Self, String> {
+        Ok(Self::Model(
+            Fbank::new(MODEL_BANDS),
+            model::Model::load(model_path)?,
+        ))
+    }
+
+    pub fn default_threshold(&self) -> f64 {
+        match self {
+            Self::Statistics(_) => DEFAULT_SIMILARITY_THRESHOLD,
+            Self::Model(..) => DEFAULT_MODEL_SIMILARITY_THRESHOLD,
+        }
+    }
+
+    /// L2-normalised embedding, or `None` if the audio holds too little
+    /// speech.
+    pub fn embed(&mut self, audio: &[i16]) -> Result<Option<Vec<f32>>, String> {
+        let samples: Vec<f32> = audio.iter().map(|&s| s as f32 / 32_768.0).collect();
+        let mut embedding = match self {
+            Self::Statistics(mfcc) => {
+                let frames = mfcc.frames(&samples);
+                let energies: Vec<f32> = frames.iter().map(|f| f.energy_db).collect();
+                match voiced(&energies, MIN_STATISTICS_FRAMES) {
+                    Some(keep) => statistics(keep.iter().map(|&i| &frames[i].cepstra)),
+                    None => return Ok(None),
+                }
+            }
+            Self::Model(fbank, model) => {
+                let frames = fbank.frames(&samples);
+                let energies: Vec<f32> = frames.iter().map(|f| f.energy_db).collect();
+                let keep = match voiced(&energies, MIN_MODEL_FRAMES) {
+                    Some(keep) => keep,
+                    None => return Ok(None),
+                };
+                let mut features: Vec<f32> = keep
+                    .iter()
+                    .flat_map(|&i| frames[i].log_mel.iter().copied())
+                    .collect();
+                subtract_mean(&mut features, MODEL_BANDS);
+                model.embed(features, keep.len(), MODEL_BANDS)?
+            }
+        };
+
+        normalize(&mut embedding);
+        Ok(Some(embedding))
+    }
+}
+
+/// Indices of the frames within `ENERGY_FLOOR_DB` of the loudest and above
+/// `SILENCE_DB`, if there are at least `min_frames` of them.
+fn voiced(energies_db: &[f32], min_frames: usize) -> Option<Vec<usize>> {
+    let loudest = energies_db.iter().copied().fold(f32::MIN, f32::max);
+    let floor = (loudest - ENERGY_FLOOR_DB).max(SILENCE_DB);
+    let keep: Vec<usize> = (0..energies_db.len())
+        .filter(|&i| energies_db[i] >= floor)
+        .collect();
+    (keep.len() >= min_frames).then_some(keep)
+}
+
+/// Per-coefficient mean followed by standard deviation.
+fn statistics<'a>(cepstra: impl Iterator<Item = &'a Vec<f32>> + Clone) -> Vec<f32> {
+    let n = cepstra.clone().count() as f32;
+    let mut embedding = vec![0.0f32; CEPSTRA * 2];
+    for frame in cepstra.clone() {
+        for (k, c) in frame.iter().enumerate() {
+            embedding[k] += c / n;
+        }
+    }
+    for frame in cepstra {
+        for (k, c) in frame.iter().enumerate() {
+            let d = c - embedding[k];
+            embedding[CEPSTRA + k] += d * d / n;
+        }
+    }
+    for v in embedding[CEPSTRA..].iter_mut() {
+        *v = v.sqrt();
+    }
+    embedding
+}
+
+/// Cepstral mean normalisation of row-major `[frames, bands]` features.
+fn subtract_mean(features: &mut [f32], bands: usize) {
+    let frames = (features.len() / bands) as f32;
+    for band in 0..bands {
+        let mean = features.iter().skip(band).step_by(bands).sum::<f32>() / frames;
+        features
+            .iter_mut()
+            .skip(band)
+            .step_by(bands)
+            .for_each(|x| *x -= mean);
+    }
+}
+
+fn normalize(v: &mut [f32]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm =
+        a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        dot / norm
+    } else {
+        0.0
+    }
+}
+
+struct Cluster {
+    centroid: Vec<f32>,
+    count: u32,
+}
+
+/// Online speaker clustering: each embedding joins the most similar known
+/// speaker if it is similar enough, otherwise starts a new one (until
+/// `max_speakers`, after which the closest speaker is used). Centroids are
+/// running means, so labels stay stable as a meeting goes on.
+pub struct SpeakerClusters {
+    threshold: f32,
+    max_speakers: usize,
+    clusters: Vec<Cluster>,
+}
+
+impl SpeakerClusters {
+    pub fn new(threshold: f64, max_speakers: u32) -> Self {
+        Self {
+            threshold: threshold as f32,
+            max_speakers: max_speakers.max(1) as usize,
+            clusters: Vec::new(),
+        }
+    }
+
+    /// Speaker id (0-based, in order of first appearance) for an embedding.
+    pub fn assign(&mut self, embedding: &[f32]) -> u32 {
+        let best = self
+            .clusters
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i, cosine(&c.centroid, embedding)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        let id = match best {
+            Some((i, similarity))
+                if similarity >= self.threshold || self.clusters.len() >= self.max_speakers =>
+            {
+                i
+            }
+            _ => {
+                self.clusters.push(Cluster {
+                    centroid: vec![0.0; embedding.len()],
+                    count: 0,
+                });
+                self.clusters.len() - 1
+            }
+        };
+
+        let cluster = &mut self.clusters[id];
+        cluster.count += 1;
+        let weight = 1.0 / cluster.count as f32;
+        for (c, e) in cluster.centroid.iter_mut().zip(embedding) {
+            *c += (e - *c) * weight;
+        }
+        id as u32
+    }
+}
+
+/// How utterances are told apart.
+#[derive(Clone, Default)]
+pub struct DiarizationConfig {
+    /// ONNX speaker model; MFCC statistics without one.
+    pub model_path: Option<String>,
+    /// Cosine similarity needed to match a known speaker; the embedder's
+    /// default if unset.
+    pub threshold: Option<f64>,
+    pub max_speakers: u32,
+}
+
+/// Embedder and clusters together: speaker ids for whole utterances.
+pub struct SpeakerLabeler {
+    embedder: SpeakerEmbedder,
+    clusters: SpeakerClusters,
+}
+
+impl SpeakerLabeler {
+    /// Loads the model, if any, so a bad path is reported to the caller.
+    pub fn new(config: &DiarizationConfig) -> Result<Self, String> {
+        let embedder = match config.model_path {
+            Some(ref path) => SpeakerEmbedder::load(path)?,
+            None => SpeakerEmbedder::statistics(),
+        };
+        let threshold = config
+            .threshold
+            .unwrap_or_else(|| embedder.default_threshold());
+        Ok(Self {
+            clusters: SpeakerClusters::new(threshold, config.max_speakers),
+            embedder,
+        })
+    }
+
+    /// Speaker id for 16 kHz mono audio; `None` if it had too little voice.
+    pub fn label(&mut self, audio: &[i16]) -> Result<Option<u32>, String> {
+        Ok(self
+            .embedder
+            .embed(audio)?
+            .map(|embedding| self.clusters.assign(&embedding)))
+    }
+}
+
+/// A labelled utterance, positions in 16 kHz samples as given to `submit`.
+pub struct SpeakerLabel {
+    pub start: usize,
+    pub end: usize,
+    pub speaker: Option<u32>,
+}
+
+enum WorkerInput {
+    Utterance {
+        start: usize,
+        end: usize,
+        audio: Vec<i16>,
+    },
+    /// Acknowledged once everything queued before it is labelled.
+    Flush(mpsc::SyncSender<()>),
+}
+
+/// Labels utterances on a worker thread that owns the embedder, so neither
+/// inference nor clustering runs on the capture thread. Dropping it lets the
+/// worker finish the queue and exit.
+pub struct SpeakerWorker {
+    utterances: mpsc::SyncSender<WorkerInput>,
+    labels: Arc<dyn Fn(SpeakerLabel) + Send + Sync>,
+}
+
+impl SpeakerWorker {
+    pub fn spawn(
+        mut labeler: SpeakerLabeler,
+        labels: Arc<dyn Fn(SpeakerLabel) + Send + Sync>,
+    ) -> Self {
+        let (utterances, queue) = mpsc::sync_channel(QUEUE_UTTERANCES);
+        let report = labels.clone();
+        thread::spawn(move || {
+            for input in queue {
+                match input {
+                    WorkerInput::Utterance { start, end, audio } => {
+                        let speaker = labeler.label(&audio).unwrap_or_else(|e| {
+                            eprintln!("Speaker embedding error: {}", e);
+                            None
+                        });
+                        report(SpeakerLabel {
+                            start,
+                            end,
+                            speaker,
+                        });
+                    }
+                    WorkerInput::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Self { utterances, labels }
+    }
+
+    /// Queues an utterance without blocking. While the worker is behind,
+    /// utterances are reported straight away without a speaker.
+    pub fn submit(&self, start: usize, end: usize, audio: Vec<i16>) {
+        if self
+            .utterances
+            .try_send(WorkerInput::Utterance { start, end, audio })
+            .is_err()
+        {
+            (self.labels)(SpeakerLabel {
+                start,
+                end,
+                speaker: None,
+            });
+        }
+    }
+
+    /// Waits until every queued utterance is labelled.
+    pub fn flush(&self) {
+        let (done, finished) = mpsc::sync_channel(1);
+        if self.utterances.send(WorkerInput::Flush(done)).is_ok() {
+            let _ = finished.recv();
+        }
+    }
+}
+
+#[cfg(feature = "speaker-model")]
+pub mod model {
+    use ort::session::Session;
+    use ort::value::Tensor;
+
+    pub struct Model {
+        session: Session,
+    }
+
+    impl Model {
+        pub fn load(path: &str) -> Result<Self, String> {
+            // `ort` panics if the ONNX Runtime library itself cannot be loaded.
+            let session = std::panic::catch_unwind(|| {
+                Session::builder()
+                    .and_then(|builder| builder.with_intra_threads(1))
+                    .and_then(|builder| builder.commit_from_file(path))
+            })
+            .map_err(|_| {
+                "Failed to load ONNX Runtime (set ORT_DYLIB_PATH to libonnxruntime)".to_string()
+            })?
+            .map_err(|e| format!("Failed to load speaker model {}: {}", path, e))?;
+            Ok(Self { session })
+        }
+
+        /// Embedding of row-major `[frames, bands]` features.
+        pub fn embed(
+            &mut self,
+            features: Vec<f32>,
+            frames: usize,
+            bands: usize,
+        ) -> Result<Vec<f32>, String> {
+            let input =
+                Tensor::from_array(([1, frames, bands], features)).map_err(|e| e.to_string())?;
+            let outputs = self
+                .session
+                .run(ort::inputs![input])
+                .map_err(|e| e.to_string())?;
+            let (_, embedding) = outputs[0]
+                .try_extract_tensor::<f32>()
+                .map_err(|e| e.to_string())?;
+            Ok(embedding.to_vec())
+        }
+    }
+}
+
+#[cfg(not(feature = "speaker-model"))]
+pub mod model {
+    pub struct Model;
+
+    impl Model {
+        pub fn load(_path: &str) -> Result<Self, String> {
+            Err(
+                "Speaker model support was not compiled in (enable the `speaker-model` feature)"
+                    .to_string(),
+            )
+        }
+
+        pub fn embed(
+            &mut self,
+            _features: Vec<f32>,
+            _frames: usize,
+            _bands: usize,
+        ) -> Result<Vec<f32>, String> {
+            Err("Speaker model support was not compiled in".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_config::SAMPLE_RATE;
+    use std::f32::consts::PI;
+    use std::sync::Mutex;
+
+    /// Harmonics of `f0` with a single formant at `formant` Hz: a crude but
+    /// stable stand-in for a voice. `seed` shifts pitch and phase slightly.
+    fn voice(f0: f32, formant: f32, seed: u32, seconds: f32) -> Vec<i16> {
+        let f0 = f0 * (1.0 + 0.01 * seed as f32);
+        let harmonics = (4_000.0 / f0) as usize;
+        (0..(seconds * SAMPLE_RATE as f32) as usize)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let s: f32 = (1..=harmonics)
+                    .map(|k| {
+                        let f = f0 * k as f32;
+                        let gain = 1.0 / (1.0 + ((f - formant) / 300.0).powi(2));
+                        gain * (2.0 * PI * f * t + seed as f32 * k as f32).sin()
+                    })
+                    .sum();
+                (s * 3_000.0).clamp(-32_768.0, 32_767.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clusters_by_similarity_in_order_of_appearance() {
+        let mut clusters = SpeakerClusters::new(0.9, 8);
+        assert_eq!(clusters.assign(&[1.0, 0.0, 0.0]), 0);
+        assert_eq!(clusters.assign(&[0.0, 1.0, 0.0]), 1);
+        assert_eq!(clusters.assign(&[0.95, 0.1, 0.0]), 0);
+        assert_eq!(clusters.assign(&[0.0, 0.0, 1.0]), 2);
+        assert_eq!(clusters.assign(&[0.1, 0.98, 0.0]), 1);
+    }
+
+    #[test]
+    fn centroids_follow_their_members() {
+        let mut clusters = SpeakerClusters::new(0.75, 8);
+        assert_eq!(clusters.assign(&[1.0, 0.0]), 0);
+        assert_eq!(clusters.assign(&[0.8, 0.6]), 0);
+        // 0.6 from the first member alone, but 0.82 from their mean.
+        assert_eq!(clusters.assign(&[0.6, 0.8]), 0);
+    }
+
+    #[test]
+    fn the_speaker_limit_reuses_the_closest() {
+        let mut clusters = SpeakerClusters::new(0.99, 2);
+        assert_eq!(clusters.assign(&[1.0, 0.0, 0.0]), 0);
+        assert_eq!(clusters.assign(&[0.0, 1.0, 0.0]), 1);
+        assert_eq!(clusters.assign(&[0.2, 0.9, 0.4]), 1);
+        assert_eq!(clusters.assign(&[0.9, 0.0, 0.4]), 0);
+    }
+
+    #[test]
+    fn statistics_separate_different_voices() {
+        let mut embedder = SpeakerEmbedder::statistics();
+        let mut embed = |audio: Vec<i16>| embedder.embed(&audio).unwrap().unwrap();
+        let low = embed(voice(110.0, 700.0, 0, 1.0));
+        let low_again = embed(voice(110.0, 700.0, 3, 1.0));
+        let high = embed(voice(220.0, 2_200.0, 0, 1.0));
+
+        assert!(cosine(&low, &low_again) > 0.95);
+        assert!(cosine(&low, &high) < cosine(&low, &low_again) - 0.1);
+        let norm: f32 = low.iter().map(|x| x * x).sum();
+        assert!((norm - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn too_little_voice_has_no_embedding() {
+        let mut embedder = SpeakerEmbedder::statistics();
+        assert!(embedder.embed(&[0; 16_000]).unwrap().is_none());
+        let blip = voice(110.0, 700.0, 0, 0.05);
+        assert!(embedder.embed(&blip).unwrap().is_none());
+    }
+
+    #[test]
+    fn models_need_the_feature_or_a_valid_file() {
+        let config = DiarizationConfig {
+            model_path: Some("/nonexistent/ecapa.onnx".to_string()),
+            ..DiarizationConfig::default()
+        };
+        assert!(SpeakerLabeler::new(&config).is_err());
+    }
+
+    #[test]
+    fn the_worker_labels_utterances_in_order() {
+        let labels = Arc::new(Mutex::new(Vec::new()));
+        let sink = labels.clone();
+        let labeler = SpeakerLabeler::new(&DiarizationConfig {
+            max_speakers: DEFAULT_MAX_SPEAKERS,
+            ..DiarizationConfig::default()
+        })
+        .unwrap();
+        let worker = SpeakerWorker::spawn(
+            labeler,
+            Arc::new(move |label: SpeakerLabel| {
+                sink.lock().unwrap().push((label.start, label.speaker));
+            }),
+        );
+
+        worker.submit(0, 1, voice(110.0, 700.0, 0, 1.0));
+        worker.submit(1, 2, vec![0; 1_600]);
+        worker.submit(2, 3, voice(110.0, 700.0, 2, 1.0));
+        worker.flush();
+        assert_eq!(
+            *labels.lock().unwrap(),
+            [(0, Some(0)), (1, None), (2, Some(0))]
+        );
+    }
+}
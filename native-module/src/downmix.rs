@@ -0,0 +1,466 @@
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+/// A mono mix inverts the right side once the front pair's correlation falls
+/// below `OUT_OF_PHASE_BELOW`, and restores it above `IN_PHASE_ABOVE`.
+const OUT_OF_PHASE_BELOW: f32 = -0.5;
+const IN_PHASE_ABOVE: f32 = 0.0;
+
+/// Speaker positions in `WAVEFORMATEXTENSIBLE` channel-mask order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Speaker {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    BackLeft,
+    BackRight,
+    FrontLeftOfCenter,
+    FrontRightOfCenter,
+    BackCenter,
+    SideLeft,
+    SideRight,
+    TopCenter,
+    TopFrontLeft,
+    TopFrontCenter,
+    TopFrontRight,
+    TopBackLeft,
+    TopBackCenter,
+    TopBackRight,
+    Unknown,
+}
+
+const MASK_ORDER: [Speaker; 18] = [
+    Speaker::FrontLeft,
+    Speaker::FrontRight,
+    Speaker::FrontCenter,
+    Speaker::LowFrequency,
+    Speaker::BackLeft,
+    Speaker::BackRight,
+    Speaker::FrontLeftOfCenter,
+    Speaker::FrontRightOfCenter,
+    Speaker::BackCenter,
+    Speaker::SideLeft,
+    Speaker::SideRight,
+    Speaker::TopCenter,
+    Speaker::TopFrontLeft,
+    Speaker::TopFrontCenter,
+    Speaker::TopFrontRight,
+    Speaker::TopBackLeft,
+    Speaker::TopBackCenter,
+    Speaker::TopBackRight,
+];
+
+impl Speaker {
+    /// ITU-R BS.775 style gains into a (left, right) stereo pair.
+    fn stereo_gains(self) -> (f32, f32) {
+        match self {
+            Speaker::FrontLeft | Speaker::FrontLeftOfCenter => (1.0, 0.0),
+            Speaker::FrontRight | Speaker::FrontRightOfCenter => (0.0, 1.0),
+            Speaker::FrontCenter => (MINUS_3DB, MINUS_3DB),
+            Speaker::LowFrequency => (0.0, 0.0),
+            Speaker::BackLeft
+            | Speaker::SideLeft
+            | Speaker::TopFrontLeft
+            | Speaker::TopBackLeft => (MINUS_3DB, 0.0),
+            Speaker::BackRight
+            | Speaker::SideRight
+            | Speaker::TopFrontRight
+            | Speaker::TopBackRight => (0.0, MINUS_3DB),
+            Speaker::BackCenter
+            | Speaker::TopCenter
+            | Speaker::TopFrontCenter
+            | Speaker::TopBackCenter
+            | Speaker::Unknown => (0.5, 0.5),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChannelLayout {
+    speakers: Vec<Speaker>,
+}
+
+impl ChannelLayout {
+    /// Conventional layouts for a bare channel count (mono, stereo, 2.1,
+    /// quad, 5.0, 5.1, 7.1, ...), used when the device reports no mask.
+    pub fn default_for(channels: usize) -> Self {
+        use Speaker::*;
+        let speakers = match channels {
+            0 => Vec::new(),
+            1 => vec![FrontCenter],
+            2 => vec![FrontLeft, FrontRight],
+            3 => vec![FrontLeft, FrontRight, LowFrequency],
+            4 => vec![FrontLeft, FrontRight, BackLeft, BackRight],
+            5 => vec![FrontLeft, FrontRight, FrontCenter, BackLeft, BackRight],
+            6 => vec![
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                BackLeft,
+                BackRight,
+            ],
+            7 => vec![
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                BackCenter,
+                SideLeft,
+                SideRight,
+            ],
+            n => {
+                let mut speakers = vec![
+                    FrontLeft,
+                    FrontRight,
+                    FrontCenter,
+                    LowFrequency,
+                    BackLeft,
+                    BackRight,
+                    SideLeft,
+                    SideRight,
+                ];
+                speakers.resize(n, Unknown);
+                speakers
+            }
+        };
+        Self { speakers }
+    }
+
+    /// Layout from a `dwChannelMask`. Channels beyond the mask's set bits are
+    /// `Unknown`; a zero mask falls back to `default_for`.
+    pub fn from_channel_mask(mask: u32, channels: usize) -> Self {
+        if mask == 0 {
+            return Self::default_for(channels);
+        }
+
+        let mut speakers: Vec<Speaker> = MASK_ORDER
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| mask & (1 << bit) != 0)
+            .map(|(_, &speaker)| speaker)
+            .take(channels)
+            .collect();
+        speakers.resize(channels, Speaker::Unknown);
+        Self { speakers }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.speakers.len()
+    }
+
+    pub fn speakers(&self) -> &[Speaker] {
+        &self.speakers
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DownmixMode {
+    /// Mono: equal-weight mean of every channel.
+    Average,
+    /// Mono from an ITU stereo downmix, with the centre (dialog) channel at
+    /// full level and LFE discarded.
+    Itu,
+    /// Mono: a single channel by index.
+    Channel(usize),
+    /// Stereo: ITU downmix of surround layouts, mono duplicated to both sides.
+    Stereo,
+}
+
+impl DownmixMode {
+    pub fn parse(mode: &str, channel: Option<u32>) -> Result<Self, String> {
+        match mode {
+            "average" => Ok(DownmixMode::Average),
+            "itu" => Ok(DownmixMode::Itu),
+            "stereo" => Ok(DownmixMode::Stereo),
+            "channel" => Ok(DownmixMode::Channel(channel.unwrap_or(0) as usize)),
+            other => Err(format!("Unknown downmix mode: {}", other)),
+        }
+    }
+
+    pub fn output_channels(self) -> usize {
+        match self {
+            DownmixMode::Stereo => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Mixes interleaved frames of a given layout down to mono or stereo with a
+/// fixed gain matrix.
+///
+/// Summing to mono cancels content the front pair carries out of phase
+/// (phase-inverted stereo, some widening effects). `Average` and `Itu`
+/// therefore track the pair's correlation and, while it is strongly
+/// negative, mix the right side inverted; switches crossfade over a block.
+pub struct Downmixer {
+    layout: ChannelLayout,
+    gains: Vec<Vec<f32>>,
+    /// The mono row with right-side speakers inverted, and the tracker that
+    /// decides when to use it.
+    inverted: Option<(Vec<f32>, PhaseTracker)>,
+    inverting: bool,
+}
+
+impl Downmixer {
+    pub fn new(mode: DownmixMode, layout: ChannelLayout) -> Result<Self, String> {
+        let speakers = layout.speakers();
+        let n = speakers.len();
+
+        let gains = match mode {
+            DownmixMode::Average => vec![vec![1.0 / n.max(1) as f32; n]],
+            DownmixMode::Itu => vec![speakers
+                .iter()
+                .map(|&s| match s {
+                    Speaker::FrontCenter => 1.0,
+                    s => {
+                        let (l, r) = s.stereo_gains();
+                        (l + r) / 2.0
+                    }
+                })
+                .collect()],
+            DownmixMode::Channel(index) if index >= n => {
+                return Err(format!(
+                    "Channel {} is out of range for a {}-channel device",
+                    index, n
+                ))
+            }
+            DownmixMode::Channel(index) => {
+                let mut row = vec![0.0; n];
+                row[index] = 1.0;
+                vec![row]
+            }
+            DownmixMode::Stereo if n == 1 => vec![vec![1.0], vec![1.0]],
+            DownmixMode::Stereo => {
+                let (left, right) = speakers.iter().map(|s| s.stereo_gains()).unzip();
+                vec![left, right]
+            }
+        };
+
+        let inverted = match mode {
+            DownmixMode::Average | DownmixMode::Itu => PhaseTracker::new(speakers).map(|tracker| {
+                let row = gains[0]
+                    .iter()
+                    .zip(speakers)
+                    .map(|(&gain, speaker)| match speaker.stereo_gains() {
+                        (l, r) if l == 0.0 && r > 0.0 => -gain,
+                        _ => gain,
+                    })
+                    .collect();
+                (row, tracker)
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            layout,
+            gains,
+            inverted,
+            inverting: false,
+        })
+    }
+
+    pub fn layout(&self) -> &ChannelLayout {
+        &self.layout
+    }
+
+    pub fn output_channels(&self) -> usize {
+        self.gains.len()
+    }
+
+    /// Mixes whole interleaved input frames; a trailing partial frame is
+    /// ignored.
+    pub fn process(&mut self, interleaved: &[f32]) -> Vec<f32> {
+        let channels = self.layout.channels();
+        if channels == 0 {
+            return Vec::new();
+        }
+
+        let was_inverting = self.inverting;
+        let (inverted_row, tracker) = match self.inverted {
+            Some((ref row, ref mut tracker)) => (row, tracker),
+            None => return mix(&self.gains, interleaved, channels),
+        };
+        self.inverting = tracker.update(interleaved, channels);
+        let (from, to) = match (was_inverting, self.inverting) {
+            (false, false) => return mix(&self.gains, interleaved, channels),
+            (true, true) => return mix(std::slice::from_ref(inverted_row), interleaved, channels),
+            (false, true) => (&self.gains[0], inverted_row),
+            (true, false) => (inverted_row, &self.gains[0]),
+        };
+
+        let frames = interleaved.len() / channels;
+        interleaved
+            .chunks_exact(channels)
+            .enumerate()
+            .map(|(i, frame)| {
+                let t = (i + 1) as f32 / frames as f32;
+                frame
+                    .iter()
+                    .zip(from.iter().zip(to))
+                    .map(|(s, (a, b))| s * (a + (b - a) * t))
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+fn mix(gains: &[Vec<f32>], interleaved: &[f32], channels: usize) -> Vec<f32> {
+    let mut output = Vec::with_capacity(interleaved.len() / channels * gains.len());
+    for frame in interleaved.chunks_exact(channels) {
+        for row in gains {
+            output.push(row.iter().zip(frame).map(|(g, s)| g * s).sum());
+        }
+    }
+    output
+}
+
+/// Follows the correlation of the front left/right pair block by block.
+struct PhaseTracker {
+    left: usize,
+    right: usize,
+    inverted: bool,
+}
+
+impl PhaseTracker {
+    /// `None` for layouts without a front pair.
+    fn new(speakers: &[Speaker]) -> Option<Self> {
+        let find = |target| speakers.iter().position(|&s| s == target);
+        Some(Self {
+            left: find(Speaker::FrontLeft)?,
+            right: find(Speaker::FrontRight)?,
+            inverted: false,
+        })
+    }
+
+    /// Whether the right side should be inverted for this block. Silence on
+    /// either side leaves the decision as it was.
+    fn update(&mut self, interleaved: &[f32], channels: usize) -> bool {
+        let (mut cross, mut left_energy, mut right_energy) = (0.0, 0.0, 0.0);
+        for frame in interleaved.chunks_exact(channels) {
+            let (l, r) = (frame[self.left], frame[self.right]);
+            cross += l * r;
+            left_energy += l * l;
+            right_energy += r * r;
+        }
+
+        let energy = (left_energy * right_energy).sqrt();
+        if energy > f32::MIN_POSITIVE {
+            let correlation = cross / energy;
+            if correlation < OUT_OF_PHASE_BELOW {
+                self.inverted = true;
+            } else if correlation > IN_PHASE_ABOVE {
+                self.inverted = false;
+            }
+        }
+        self.inverted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// 10 ms stereo blocks of a 440 Hz tone, the right side scaled by `right`.
+    fn stereo_blocks(right: f32, blocks: usize) -> Vec<Vec<f32>> {
+        let tone =
+            |n: usize| (2.0 * std::f32::consts::PI * 440.0 * n as f32 / 48_000.0).sin() * 0.5;
+        (0..blocks)
+            .map(|b| {
+                (0..480)
+                    .flat_map(|i| {
+                        let s = tone(b * 480 + i);
+                        [s, s * right]
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn layouts_follow_the_channel_mask() {
+        use Speaker::*;
+        assert_eq!(
+            ChannelLayout::from_channel_mask(0x3F, 6).speakers(),
+            [
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                BackLeft,
+                BackRight
+            ]
+        );
+        // Side pair only (0x600), with an extra unmapped channel.
+        assert_eq!(
+            ChannelLayout::from_channel_mask(0x600, 3).speakers(),
+            [SideLeft, SideRight, Unknown]
+        );
+        assert_eq!(
+            ChannelLayout::from_channel_mask(0, 2),
+            ChannelLayout::default_for(2)
+        );
+    }
+
+    #[test]
+    fn itu_keeps_dialog_and_drops_lfe() {
+        let mut mix = Downmixer::new(DownmixMode::Itu, ChannelLayout::default_for(6)).unwrap();
+        let centre = [0.0, 0.0, 0.5, 0.0, 0.0, 0.0];
+        let lfe = [0.0, 0.0, 0.0, 0.5, 0.0, 0.0];
+        assert_eq!(mix.process(&centre), [0.5]);
+        assert_eq!(mix.process(&lfe), [0.0]);
+    }
+
+    #[test]
+    fn stereo_folds_surrounds_and_duplicates_mono() {
+        let mut surround =
+            Downmixer::new(DownmixMode::Stereo, ChannelLayout::default_for(6)).unwrap();
+        let back_left = [0.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        assert_eq!(surround.process(&back_left), [MINUS_3DB, 0.0]);
+
+        let mut mono = Downmixer::new(DownmixMode::Stereo, ChannelLayout::default_for(1)).unwrap();
+        assert_eq!(mono.process(&[0.25, 0.5]), [0.25, 0.25, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn channel_picks_one_source_and_rejects_missing_ones() {
+        let mut right =
+            Downmixer::new(DownmixMode::Channel(1), ChannelLayout::default_for(2)).unwrap();
+        // The trailing partial frame is ignored.
+        assert_eq!(right.process(&[0.1, 0.2, 0.3, 0.4, 0.5]), [0.2, 0.4]);
+        assert!(Downmixer::new(DownmixMode::Channel(2), ChannelLayout::default_for(2)).is_err());
+    }
+
+    #[test]
+    fn mono_survives_phase_inverted_stereo() {
+        let mut mix = Downmixer::new(DownmixMode::Average, ChannelLayout::default_for(2)).unwrap();
+        let blocks = stereo_blocks(-1.0, 10);
+        let first = mix.process(&blocks[0]);
+        assert!(rms(&first) < 0.5 * 0.5, "the first block crossfades");
+        for block in &blocks[1..] {
+            let mono = mix.process(block);
+            assert!((rms(&mono) - rms(block)).abs() < 1e-3);
+        }
+
+        // Back in phase: the plain mix returns after one crossfaded block.
+        let blocks = stereo_blocks(1.0, 10);
+        mix.process(&blocks[0]);
+        for block in &blocks[1..] {
+            let mono = mix.process(block);
+            assert_eq!(mono[0], block[0]);
+        }
+    }
+
+    #[test]
+    fn in_phase_and_one_sided_audio_mix_plainly() {
+        let mut mix = Downmixer::new(DownmixMode::Average, ChannelLayout::default_for(2)).unwrap();
+        for block in stereo_blocks(0.0, 5).iter().chain(&stereo_blocks(0.3, 5)) {
+            let mono = mix.process(block);
+            let expected: Vec<f32> = block.chunks(2).map(|f| (f[0] + f[1]) / 2.0).collect();
+            assert_eq!(mono, expected);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// One (time, frames) observation per interval; jitter in packet arrival
+/// averages out in the fit.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+const WINDOW: Duration = Duration::from_secs(60);
+/// No estimate until this much uninterrupted audio has been observed.
+const MIN_SPAN: Duration = Duration::from_secs(10);
+/// Anything beyond this is a misreported rate, not crystal drift.
+const MAX_DRIFT_PPM: f64 = 2_000.0;
+
+/// Estimates how fast a device clock runs relative to the system clock, as
+/// the least-squares slope of delivered frames over wall time.
+pub struct DriftEstimator {
+    nominal_rate: f64,
+    origin: Instant,
+    frames: u64,
+    points: VecDeque<(f64, f64)>,
+    last_point: Option<Instant>,
+    drift_ppm: Option<f64>,
+}
+
+impl DriftEstimator {
+    pub fn new(nominal_rate: u32) -> Self {
+        Self {
+            nominal_rate: nominal_rate as f64,
+            origin: Instant::now(),
+            frames: 0,
+            points: VecDeque::new(),
+            last_point: None,
+            drift_ppm: None,
+        }
+    }
+
+    /// Drops the observations, e.g. after a gap in delivery. The last
+    /// estimate is kept until a new one is available.
+    pub fn reset(&mut self) {
+        self.points.clear();
+        self.last_point = None;
+    }
+
+    /// Records `frames` device frames delivered at `now`. Returns true when
+    /// the estimate changed.
+    pub fn push(&mut self, frames: u64, now: Instant) -> bool {
+        self.frames += frames;
+        if matches!(self.last_point, Some(last) if now.duration_since(last) < SAMPLE_INTERVAL) {
+            return false;
+        }
+
+        self.last_point = Some(now);
+        let t = now.duration_since(self.origin).as_secs_f64();
+        self.points.push_back((t, self.frames as f64));
+        while let Some(&(first, _)) = self.points.front() {
+            if t - first <= WINDOW.as_secs_f64() {
+                break;
+            }
+            self.points.pop_front();
+        }
+
+        let span = self.points.front().map_or(0.0, |&(first, _)| t - first);
+        if span < MIN_SPAN.as_secs_f64() {
+            return false;
+        }
+
+        let ppm = (self.slope() / self.nominal_rate - 1.0) * 1e6;
+        self.drift_ppm = Some(ppm.clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM));
+        true
+    }
+
+    /// Device clock rate relative to the system clock, in parts per million
+    /// (positive when the device runs fast).
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.drift_ppm
+    }
+
+    fn slope(&self) -> f64 {
+        let n = self.points.len() as f64;
+        let (t0, f0) = self.points[0];
+        // Centre on the first point to keep the sums well-conditioned.
+        let (mut st, mut sf, mut stt, mut stf) = (0.0, 0.0, 0.0, 0.0);
+        for &(t, f) in &self.points {
+            let (t, f) = (t - t0, f - f0);
+            st += t;
+            sf += f;
+            stt += t * t;
+            stf += t * f;
+        }
+        let denominator = n * stt - st * st;
+        if denominator <= 0.0 {
+            return self.nominal_rate;
+        }
+        (n * stf - st * sf) / denominator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds 10 ms packets from a device whose clock runs `ppm` fast, for
+    /// `seconds` of wall time; returns whether the estimate was updated.
+    fn feed(estimator: &mut DriftEstimator, start: Instant, ppm: f64, seconds: u64) -> bool {
+        let rate = 48_000.0 * (1.0 + ppm / 1e6);
+        let mut updated = false;
+        let mut delivered = 0u64;
+        for packet in 1..=seconds * 100 {
+            let due = (rate * packet as f64 / 100.0) as u64;
+            let now = start + Duration::from_millis(packet * 10);
+            updated |= estimator.push(due - delivered, now);
+            delivered = due;
+        }
+        updated
+    }
+
+    #[test]
+    fn recovers_a_known_offset() {
+        let mut estimator = DriftEstimator::new(48_000);
+        let start = Instant::now();
+        assert!(feed(&mut estimator, start, 120.0, 30));
+        let ppm = estimator.drift_ppm().unwrap();
+        assert!((ppm - 120.0).abs() < 1.0, "{} ppm", ppm);
+    }
+
+    #[test]
+    fn waits_for_enough_audio() {
+        let mut estimator = DriftEstimator::new(48_000);
+        assert!(!feed(&mut estimator, Instant::now(), -50.0, 9));
+        assert_eq!(estimator.drift_ppm(), None);
+    }
+
+    #[test]
+    fn reset_keeps_the_estimate_until_a_new_one() {
+        let mut estimator = DriftEstimator::new(48_000);
+        let start = Instant::now();
+        feed(&mut estimator, start, -80.0, 12);
+        estimator.reset();
+        let later = start + Duration::from_secs(12);
+        assert!(!feed(&mut estimator, later, 0.0, 5));
+        assert!((estimator.drift_ppm().unwrap() + 80.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn misreported_rates_are_clamped() {
+        // A 44.1 kHz device reported as 48 kHz.
+        let mut estimator = DriftEstimator::new(48_000);
+        feed(&mut estimator, Instant::now(), -81_250.0, 12);
+        assert_eq!(estimator.drift_ppm(), Some(-MAX_DRIFT_PPM));
+    }
+}
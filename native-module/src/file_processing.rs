@@ -0,0 +1,425 @@
+use napi::bindgen_prelude::{Buffer, Either};
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Env, JsFunction, Task};
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+use crate::decoder::AudioFileReader;
+use crate::downmix::{ChannelLayout, DownmixMode, Downmixer};
+use crate::loudness::LoudnessMeter;
+use crate::subscriber::{Output, Subscriber, SubscriberConfig, SubscriberOptions};
+use crate::utterance::{SegmenterConfig, UtteranceEvent, UtteranceOptions, UtteranceSubscriber};
+
+const DEFAULT_PROGRESS_INTERVAL_MS: u32 = 1_000;
+/// Events queued for JS at most; decoding waits for the event loop beyond
+/// this, so a long file cannot pile up unbounded memory.
+const MAX_QUEUED_EVENTS: usize = 256;
+
+#[napi(object)]
+pub struct FileProcessingOptions {
+    /// Frame format, as for `subscribe`. `paced` and `fillGaps` do not apply.
+    pub frames: Option<SubscriberOptions>,
+    /// Emit `frame` events, default true.
+    pub emit_frames: Option<bool>,
+    /// Also report utterances, as `subscribeUtterances` does.
+    pub utterances: Option<UtteranceOptions>,
+    /// Audio between `progress` events, default 1000 ms.
+    pub progress_interval_ms: Option<u32>,
+}
+
+#[napi(object)]
+pub struct FileEvent {
+    /// `"frame"`, `"utterance"` or `"progress"`.
+    pub kind: String,
+    /// Encoded chunk, as a subscriber callback would receive it: a `Buffer`,
+    /// or a string for the base64 and JSON formats.
+    pub frame: Option<Either<Buffer, String>>,
+    pub utterance: Option<UtteranceEvent>,
+    /// Fraction of the file processed (0-1) on `"progress"`; absent if the
+    /// file does not state its length.
+    pub progress: Option<f64>,
+    /// Milliseconds of the file decoded so far.
+    pub position_ms: f64,
+}
+
+impl FileEvent {
+    fn new(kind: &str, position_ms: f64) -> Self {
+        Self {
+            kind: kind.to_string(),
+            frame: None,
+            utterance: None,
+            progress: None,
+            position_ms,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct FileProcessingResult {
+    pub duration_ms: f64,
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// `frame` events emitted.
+    pub frames: u32,
+    /// `speechEnd` events emitted.
+    pub utterances: u32,
+    pub integrated_lufs: Option<f64>,
+    pub true_peak_dbtp: Option<f64>,
+}
+
+/// Decodes a file and feeds it through the same subscriber and utterance
+/// consumers as live capture, as fast as the CPU allows. Runs on the libuv
+/// thread pool; the returned promise settles when the file is done.
+pub struct FileProcessing {
+    path: PathBuf,
+    frames: Option<SubscriberConfig>,
+    utterances: Option<SegmenterConfig>,
+    progress_interval_ms: u32,
+    tsfn: ThreadsafeFunction<FileEvent, ErrorStrategy::Fatal>,
+}
+
+impl FileProcessing {
+    pub fn new(
+        path: String,
+        options: Option<FileProcessingOptions>,
+        callback: &JsFunction,
+    ) -> napi::Result<Self> {
+        let (frames, emit_frames, utterances, progress_interval_ms) = match options {
+            Some(o) => (
+                o.frames,
+                o.emit_frames.unwrap_or(true),
+                o.utterances
+                    .map(|u| SegmenterConfig::from_options(Some(u)))
+                    .transpose()?,
+                o.progress_interval_ms,
+            ),
+            None => (None, true, None, None),
+        };
+        let frames = if emit_frames {
+            let mut config = SubscriberConfig::from_options(frames)?;
+            config.paced = false;
+            Some(config)
+        } else {
+            None
+        };
+
+        let tsfn: ThreadsafeFunction<FileEvent, ErrorStrategy::Fatal> =
+            callback.create_threadsafe_function(MAX_QUEUED_EVENTS, |ctx| Ok(vec![ctx.value]))?;
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            frames,
+            utterances,
+            progress_interval_ms: progress_interval_ms
+                .unwrap_or(DEFAULT_PROGRESS_INTERVAL_MS)
+                .max(1),
+            tsfn,
+        })
+    }
+
+    fn run(&mut self) -> anyhow::Result<FileProcessingResult> {
+        let mut reader = AudioFileReader::open(&self.path)?;
+        let tsfn = &self.tsfn;
+        process_file(
+            &mut reader,
+            self.frames.take(),
+            self.utterances.take(),
+            self.progress_interval_ms,
+            |event| {
+                tsfn.call(event, ThreadsafeFunctionCallMode::Blocking);
+            },
+        )
+    }
+}
+
+/// Feeds a decoded file through a subscriber and an utterance segmenter (if
+/// configured) and reports their output, and progress every
+/// `progress_interval_ms` of audio, to `emit`.
+pub fn process_file(
+    reader: &mut AudioFileReader,
+    frames: Option<SubscriberConfig>,
+    utterances: Option<SegmenterConfig>,
+    progress_interval_ms: u32,
+    mut emit: impl FnMut(FileEvent),
+) -> anyhow::Result<FileProcessingResult> {
+    // Consumers report into channels so events carry the file position.
+    let (frame_tx, frame_rx) = mpsc::channel();
+    let mut subscriber = frames.map(|config| {
+        Subscriber::new(
+            0,
+            config,
+            Output::Sink(Box::new(move |chunk| {
+                let _ = frame_tx.send(chunk);
+            })),
+        )
+    });
+    let (utterance_tx, utterance_rx) = mpsc::channel();
+    let mut segmenter = utterances
+        .map(|config| {
+            UtteranceSubscriber::with_events(
+                0,
+                config,
+                Box::new(move |event| {
+                    let _ = utterance_tx.send(event);
+                }),
+            )
+        })
+        .transpose()
+        .map_err(|e| anyhow::Error::msg(e.reason))?;
+
+    let mut position = 0u64;
+    let mut next_progress = 0u64;
+    let mut mono_mix: Option<(usize, Downmixer)> = None;
+    let mut loudness: Option<(u32, usize, LoudnessMeter)> = None;
+    let (mut frames, mut utterances) = (0u32, 0u32);
+
+    loop {
+        let samples = reader.read()?;
+        let (rate, channels) = (reader.sample_rate(), reader.channels());
+        let position_ms = position as f64 * 1000.0 / rate as f64;
+
+        match samples {
+            Some(ref samples) => {
+                let layout = ChannelLayout::default_for(channels);
+                let mono = match mono_mix {
+                    Some((mix_channels, ref mut mix)) if mix_channels == channels => mix,
+                    _ => {
+                        let mix = Downmixer::new(DownmixMode::Average, layout.clone())
+                            .map_err(anyhow::Error::msg)?;
+                        &mut mono_mix.insert((channels, mix)).1
+                    }
+                }
+                .process(samples);
+                let meter = match loudness {
+                    Some((meter_rate, meter_channels, ref mut meter))
+                        if meter_rate == rate && meter_channels == channels =>
+                    {
+                        meter
+                    }
+                    _ => {
+                        let meter = LoudnessMeter::new(rate, &layout);
+                        &mut loudness.insert((rate, channels, meter)).2
+                    }
+                };
+                meter.push(samples);
+
+                if let Some(ref mut subscriber) = subscriber {
+                    subscriber
+                        .push(samples, rate, &layout)
+                        .map_err(anyhow::Error::msg)?;
+                }
+                if let Some(ref mut segmenter) = segmenter {
+                    segmenter.push(&mono, rate);
+                }
+                position += (samples.len() / channels.max(1)) as u64;
+            }
+            None => {
+                if let Some(ref mut subscriber) = subscriber {
+                    subscriber.finish();
+                }
+                if let Some(ref mut segmenter) = segmenter {
+                    segmenter.finish();
+                }
+            }
+        }
+
+        for chunk in frame_rx.try_iter() {
+            frames += 1;
+            emit(FileEvent {
+                frame: Some(chunk.into_js()),
+                ..FileEvent::new("frame", position_ms)
+            });
+        }
+        for event in utterance_rx.try_iter() {
+            if event.kind == "speechEnd" {
+                utterances += 1;
+            }
+            emit(FileEvent {
+                utterance: Some(event),
+                ..FileEvent::new("utterance", position_ms)
+            });
+        }
+
+        let position_ms = position as f64 * 1000.0 / rate as f64;
+        let progress = reader
+            .total_frames()
+            .filter(|&total| total > 0)
+            .map(|total| (position as f64 / total as f64).min(1.0));
+        if samples.is_none() {
+            emit(FileEvent {
+                progress: Some(1.0),
+                ..FileEvent::new("progress", position_ms)
+            });
+            let summary = loudness
+                .map(|(_, _, meter)| meter.summary())
+                .unwrap_or_default();
+            return Ok(FileProcessingResult {
+                duration_ms: position_ms,
+                sample_rate: rate,
+                channels: channels as u32,
+                frames,
+                utterances,
+                integrated_lufs: summary.integrated_lufs,
+                true_peak_dbtp: summary.true_peak_dbtp,
+            });
+        }
+        if position >= next_progress {
+            emit(FileEvent {
+                progress,
+                ..FileEvent::new("progress", position_ms)
+            });
+            next_progress = position + (rate as u64 * progress_interval_ms as u64) / 1000;
+        }
+    }
+}
+
+impl Task for FileProcessing {
+    type Output = FileProcessingResult;
+    type JsValue = FileProcessingResult;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        self.run()
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample_format::SampleEncoding;
+    use crate::wav::WavWriter;
+    use std::path::Path;
+
+    const RATE: u32 = 48_000;
+
+    /// 3 s of stereo: silence, 1.3 s of a tone from 0.5 s, silence.
+    fn speech_like() -> Vec<f32> {
+        (0..3 * RATE as usize)
+            .flat_map(|i| {
+                let t = i as f32 / RATE as f32;
+                let level = if (0.5..1.8).contains(&t) { 0.3 } else { 0.0 };
+                let sample = level * (2.0 * std::f32::consts::PI * 220.0 * t).sin();
+                [sample, 0.5 * sample]
+            })
+            .collect()
+    }
+
+    fn write_wav(path: &Path, samples: &[f32]) {
+        let mut writer = WavWriter::create(path, RATE, 2, SampleEncoding::F32).unwrap();
+        writer.write(samples).unwrap();
+        writer.finish().unwrap();
+    }
+
+    fn frame_config() -> SubscriberConfig {
+        SubscriberConfig {
+            suppress_silence: true,
+            ..SubscriberConfig::default()
+        }
+    }
+
+    fn bytes(frame: Either<Buffer, String>) -> Vec<u8> {
+        match frame {
+            Either::A(buffer) => buffer.to_vec(),
+            Either::B(text) => text.into_bytes(),
+        }
+    }
+
+    #[test]
+    fn matches_live_processing_and_reports_progress() {
+        let samples = speech_like();
+        let path = std::env::temp_dir().join(format!("nyx-audio-{}-file.wav", std::process::id()));
+        write_wav(&path, &samples);
+
+        let mut events = Vec::new();
+        let mut reader = AudioFileReader::open(&path).unwrap();
+        let result = process_file(
+            &mut reader,
+            Some(frame_config()),
+            Some(SegmenterConfig::default()),
+            250,
+            |event| events.push(event),
+        )
+        .unwrap();
+
+        // The decoder hands back exactly what was written.
+        let mut reader = AudioFileReader::open(&path).unwrap();
+        let mut blocks = Vec::new();
+        while let Some(block) = reader.read().unwrap() {
+            blocks.push(block);
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(blocks.concat(), samples);
+
+        assert_eq!((result.sample_rate, result.channels), (RATE, 2));
+        assert!((result.duration_ms - 3_000.0).abs() < 1e-6);
+        assert_eq!(result.utterances, 1);
+
+        let progress: Vec<f64> = events
+            .iter()
+            .filter(|e| e.kind == "progress")
+            .filter_map(|e| e.progress)
+            .collect();
+        assert!(progress.len() >= 12, "{} progress events", progress.len());
+        assert!(progress.windows(2).all(|p| p[0] <= p[1]));
+        assert_eq!(progress.last(), Some(&1.0));
+
+        // The same blocks pushed as live capture would. Resampling is not
+        // independent of block boundaries, so the decoder's blocks are kept.
+        let (frame_tx, frame_rx) = mpsc::channel();
+        let mut subscriber = Subscriber::new(
+            0,
+            frame_config(),
+            Output::Sink(Box::new(move |chunk| {
+                let _ = frame_tx.send(chunk);
+            })),
+        );
+        let (utterance_tx, utterance_rx) = mpsc::channel();
+        let mut segmenter = UtteranceSubscriber::with_events(
+            0,
+            SegmenterConfig::default(),
+            Box::new(move |event| {
+                let _ = utterance_tx.send(event);
+            }),
+        )
+        .unwrap();
+        let layout = ChannelLayout::default_for(2);
+        let mut mono_mix = Downmixer::new(DownmixMode::Average, layout.clone()).unwrap();
+        for block in &blocks {
+            subscriber.push(block, RATE, &layout).unwrap();
+            segmenter.push(&mono_mix.process(block), RATE);
+        }
+        subscriber.finish();
+        segmenter.finish();
+
+        let live: Vec<u8> = frame_rx
+            .try_iter()
+            .flat_map(|chunk| bytes(chunk.into_js()))
+            .collect();
+        let mut file_frames = 0;
+        let mut from_file = Vec::new();
+        let mut file_utterances = Vec::new();
+        for event in events {
+            if let Some(frame) = event.frame {
+                file_frames += 1;
+                from_file.extend(bytes(frame));
+            }
+            if let Some(utterance) = event.utterance {
+                file_utterances.push((utterance.kind, utterance.start_ms, utterance.end_ms));
+            }
+        }
+        assert_eq!(file_frames, result.frames);
+        assert!(!live.is_empty());
+        assert_eq!(from_file, live);
+
+        let live_utterances: Vec<_> = utterance_rx
+            .try_iter()
+            .map(|u| (u.kind, u.start_ms, u.end_ms))
+            .collect();
+        assert_eq!(file_utterances.len(), 2);
+        assert_eq!(file_utterances, live_utterances);
+    }
+}
@@ -0,0 +1,550 @@
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+use crate::pipeline::{Flow, Frame, Stage};
+
+/// High-pass corner unless an option sets one: off, so audio is passed on
+/// untouched. Around 20 Hz removes DC offset and rumble well below speech.
+pub const DEFAULT_HIGH_PASS_HZ: f64 = 0.0;
+
+#[napi(object)]
+pub struct FilterOptions {
+    /// `"highPass"`, `"lowPass"`, `"bandPass"`, `"notch"`, `"peaking"`,
+    /// `"lowShelf"` or `"highShelf"`.
+    pub kind: String,
+    /// Corner or centre frequency in Hz.
+    pub frequency: f64,
+    /// Default 0.707 (Butterworth for the pass filters).
+    pub q: Option<f64>,
+    /// Boost (positive) or cut in dB; required for peaking and shelves.
+    pub gain_db: Option<f64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+    BandPass,
+    Notch,
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+impl FilterKind {
+    fn parse(value: &str) -> napi::Result<Self> {
+        match value {
+            "highPass" => Ok(FilterKind::HighPass),
+            "lowPass" => Ok(FilterKind::LowPass),
+            "bandPass" => Ok(FilterKind::BandPass),
+            "notch" => Ok(FilterKind::Notch),
+            "peaking" => Ok(FilterKind::Peaking),
+            "lowShelf" => Ok(FilterKind::LowShelf),
+            "highShelf" => Ok(FilterKind::HighShelf),
+            other => Err(napi::Error::from_reason(format!(
+                "Unknown filter kind: {}",
+                other
+            ))),
+        }
+    }
+
+    fn has_gain(self) -> bool {
+        matches!(
+            self,
+            FilterKind::Peaking | FilterKind::LowShelf | FilterKind::HighShelf
+        )
+    }
+}
+
+/// One EQ band, independent of sample rate.
+#[derive(Clone, Copy, Debug)]
+pub struct FilterSpec {
+    pub kind: FilterKind,
+    pub frequency: f64,
+    pub q: f64,
+    pub gain_db: f64,
+}
+
+impl FilterSpec {
+    pub fn from_options(options: FilterOptions) -> napi::Result<Self> {
+        let kind = FilterKind::parse(&options.kind)?;
+        if !options.frequency.is_finite() || options.frequency <= 0.0 {
+            return Err(napi::Error::from_reason(format!(
+                "Unsupported filter frequency: {} Hz",
+                options.frequency
+            )));
+        }
+        let q = options.q.unwrap_or(FRAC_1_SQRT_2);
+        if !q.is_finite() || q <= 0.0 {
+            return Err(napi::Error::from_reason(format!(
+                "Unsupported filter Q: {}",
+                q
+            )));
+        }
+        let gain_db = match options.gain_db {
+            Some(gain) if gain.is_finite() && gain.abs() <= 48.0 => gain,
+            Some(gain) => {
+                return Err(napi::Error::from_reason(format!(
+                    "Unsupported filter gain: {} dB",
+                    gain
+                )))
+            }
+            None if kind.has_gain() => {
+                return Err(napi::Error::from_reason(format!(
+                    "{} filter needs gainDb",
+                    options.kind
+                )))
+            }
+            None => 0.0,
+        };
+
+        Ok(Self {
+            kind,
+            frequency: options.frequency,
+            q,
+            gain_db,
+        })
+    }
+
+    /// Coefficients for `sample_rate`. A frequency at or above Nyquist is
+    /// pulled just below it.
+    pub fn biquad(&self, sample_rate: u32) -> Biquad {
+        let fs = sample_rate as f64;
+        let f0 = self.frequency.min(fs * 0.49);
+        match self.kind {
+            FilterKind::HighPass => Biquad::high_pass(fs, f0, self.q),
+            FilterKind::LowPass => Biquad::low_pass(fs, f0, self.q),
+            FilterKind::BandPass => Biquad::band_pass(fs, f0, self.q),
+            FilterKind::Notch => Biquad::notch(fs, f0, self.q),
+            FilterKind::Peaking => Biquad::peaking(fs, f0, self.q, self.gain_db),
+            FilterKind::LowShelf => Biquad::low_shelf(fs, f0, self.q, self.gain_db),
+            FilterKind::HighShelf => Biquad::high_shelf(fs, f0, self.q, self.gain_db),
+        }
+    }
+}
+
+/// The filtering a consumer applies ahead of silence suppression: an
+/// optional high-pass against DC offset and rumble, then any EQ bands in
+/// order.
+#[derive(Clone, Debug)]
+pub struct FilterConfig {
+    /// 0 disables the high-pass.
+    pub high_pass_hz: f64,
+    pub eq: Vec<FilterSpec>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            high_pass_hz: DEFAULT_HIGH_PASS_HZ,
+            eq: Vec::new(),
+        }
+    }
+}
+
+impl FilterConfig {
+    pub fn from_options(
+        high_pass_hz: Option<f64>,
+        eq: Option<Vec<FilterOptions>>,
+    ) -> napi::Result<Self> {
+        let mut config = Self::default();
+        if let Some(hz) = high_pass_hz {
+            config.high_pass_hz = parse_high_pass_hz(hz)?;
+        }
+        if let Some(bands) = eq {
+            config.eq = bands
+                .into_iter()
+                .map(FilterSpec::from_options)
+                .collect::<napi::Result<_>>()?;
+        }
+        Ok(config)
+    }
+
+    /// The EQ bands in series; the high-pass is a stage of its own.
+    pub fn eq_chain(&self, sample_rate: u32) -> FilterChain {
+        let mut chain = FilterChain::default();
+        for band in &self.eq {
+            chain.push(band.biquad(sample_rate));
+        }
+        chain
+    }
+}
+
+/// Validates a `highPassHz` option; 0 turns the filter off.
+pub fn parse_high_pass_hz(hz: f64) -> napi::Result<f64> {
+    if !hz.is_finite() || !(0.0..=1_000.0).contains(&hz) {
+        return Err(napi::Error::from_reason(format!(
+            "Unsupported high-pass frequency: {} Hz",
+            hz
+        )));
+    }
+    Ok(hz)
+}
+
+/// Biquads run in series over one channel, keeping state across blocks.
+#[derive(Clone, Default)]
+pub struct FilterChain {
+    stages: Vec<Biquad>,
+}
+
+impl FilterChain {
+    /// A second-order Butterworth high-pass at `hz`, or no filtering at 0.
+    pub fn high_pass(sample_rate: u32, hz: f64) -> Self {
+        let mut chain = Self::default();
+        if hz > 0.0 {
+            let fs = sample_rate as f64;
+            chain.push(Biquad::high_pass(fs, hz.min(fs * 0.49), FRAC_1_SQRT_2));
+        }
+        chain
+    }
+
+    pub fn push(&mut self, stage: Biquad) {
+        self.stages.push(stage);
+    }
+
+    /// Filters a block of mono samples in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for stage in self.stages.iter_mut() {
+            for sample in samples.iter_mut() {
+                *sample = stage.process(*sample as f64) as f32;
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset();
+        }
+    }
+}
+
+/// A filter chain as a pipeline stage, run separately on each channel.
+pub struct FilterStage {
+    name: &'static str,
+    channels: Vec<FilterChain>,
+}
+
+impl FilterStage {
+    pub fn new(name: &'static str, chain: FilterChain, channels: usize) -> Self {
+        Self {
+            name,
+            channels: vec![chain; channels.max(1)],
+        }
+    }
+}
+
+impl Stage for FilterStage {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn process(&mut self, frame: &mut Frame) -> Flow {
+        let channels = self.channels.len();
+        if channels == 1 {
+            self.channels[0].process(&mut frame.samples);
+            return Flow::Continue;
+        }
+        for (ch, chain) in self.channels.iter_mut().enumerate() {
+            let mut plane: Vec<f32> = frame
+                .samples
+                .iter()
+                .skip(ch)
+                .step_by(channels)
+                .copied()
+                .collect();
+            chain.process(&mut plane);
+            for (sample, filtered) in frame
+                .samples
+                .iter_mut()
+                .skip(ch)
+                .step_by(channels)
+                .zip(plane)
+            {
+                *sample = filtered;
+            }
+        }
+        Flow::Continue
+    }
+
+    fn reset(&mut self) {
+        for chain in self.channels.iter_mut() {
+            chain.reset();
+        }
+    }
+}
+
+/// Second-order IIR section (transposed direct form II). Designs follow the
+/// RBJ audio EQ cookbook.
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    /// Coefficients normalized so that a0 is 1.
+    pub fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn high_pass(fs: f64, f0: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prototype(fs, f0, q);
+        Self::normalized(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn low_pass(fs: f64, f0: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prototype(fs, f0, q);
+        Self::normalized(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Band-pass with 0 dB peak gain.
+    pub fn band_pass(fs: f64, f0: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prototype(fs, f0, q);
+        Self::normalized([alpha, 0.0, -alpha], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+    }
+
+    pub fn notch(fs: f64, f0: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prototype(fs, f0, q);
+        Self::normalized(
+            [1.0, -2.0 * cos, 1.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn peaking(fs: f64, f0: f64, q: f64, gain_db: f64) -> Self {
+        let (cos, alpha) = Self::prototype(fs, f0, q);
+        let a = 10f64.powf(gain_db / 40.0);
+        Self::normalized(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    pub fn low_shelf(fs: f64, f0: f64, q: f64, gain_db: f64) -> Self {
+        let (cos, alpha) = Self::prototype(fs, f0, q);
+        let a = 10f64.powf(gain_db / 40.0);
+        let root = 2.0 * a.sqrt() * alpha;
+        Self::normalized(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + root),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - root),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + root,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - root,
+            ],
+        )
+    }
+
+    pub fn high_shelf(fs: f64, f0: f64, q: f64, gain_db: f64) -> Self {
+        let (cos, alpha) = Self::prototype(fs, f0, q);
+        let a = 10f64.powf(gain_db / 40.0);
+        let root = 2.0 * a.sqrt() * alpha;
+        Self::normalized(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + root),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - root),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + root,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - root,
+            ],
+        )
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    /// cos(w0) and alpha for the cookbook designs.
+    fn prototype(fs: f64, f0: f64, q: f64) -> (f64, f64) {
+        let w0 = 2.0 * PI * f0 / fs;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    fn normalized(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self::new(
+            b[0] / a[0],
+            b[1] / a[0],
+            b[2] / a[0],
+            a[1] / a[0],
+            a[2] / a[0],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48_000.0;
+
+    fn coefficients(biquad: &Biquad) -> [f64; 5] {
+        [biquad.b0, biquad.b1, biquad.b2, biquad.a1, biquad.a2]
+    }
+
+    /// Gain in dB of a steady sine at `hz` after the filter has settled.
+    fn measured_db(mut biquad: Biquad, hz: f64) -> f64 {
+        let sine = |i: usize| (2.0 * PI * hz * i as f64 / FS).sin();
+        let settle = FS as usize;
+        for i in 0..settle {
+            biquad.process(sine(i));
+        }
+        let (mut input, mut output) = (0.0, 0.0);
+        for i in settle..settle * 2 {
+            let x = sine(i);
+            let y = biquad.process(x);
+            input += x * x;
+            output += y * y;
+        }
+        10.0 * (output / input).log10()
+    }
+
+    #[test]
+    fn designs_match_the_cookbook() {
+        let expected_high = [
+            0.911_586_668_012_831_5,
+            -1.823_173_336_025_663,
+            0.911_586_668_012_831_5,
+            -1.815_341_082_704_568,
+            0.831_005_589_346_757_6,
+        ];
+        let expected_low = [
+            0.003_916_126_660_547_383,
+            0.007_832_253_321_094_766,
+            0.003_916_126_660_547_383,
+            -1.815_341_082_704_568,
+            0.831_005_589_346_757_6,
+        ];
+        let pairs = [
+            (Biquad::high_pass(FS, 1_000.0, FRAC_1_SQRT_2), expected_high),
+            (Biquad::low_pass(FS, 1_000.0, FRAC_1_SQRT_2), expected_low),
+        ];
+        for (biquad, expected) in pairs {
+            for (actual, expected) in coefficients(&biquad).iter().zip(expected) {
+                assert!(
+                    (actual - expected).abs() < 1e-12,
+                    "{} vs {}",
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn butterworth_corners_are_3_db_down() {
+        let corner = -10.0 * 2f64.log10();
+        for hz in [20.0, 1_000.0] {
+            let high = measured_db(Biquad::high_pass(FS, hz, FRAC_1_SQRT_2), hz);
+            let low = measured_db(Biquad::low_pass(FS, hz, FRAC_1_SQRT_2), hz);
+            assert!(
+                (high - corner).abs() < 0.05,
+                "{} Hz high-pass: {} dB",
+                hz,
+                high
+            );
+            assert!(
+                (low - corner).abs() < 0.05,
+                "{} Hz low-pass: {} dB",
+                hz,
+                low
+            );
+        }
+        // Second order: 12 dB per octave away from the corner.
+        let stop = measured_db(Biquad::high_pass(FS, 1_000.0, FRAC_1_SQRT_2), 250.0);
+        assert!((stop + 24.1).abs() < 0.5, "{} dB", stop);
+        let pass = measured_db(Biquad::high_pass(FS, 1_000.0, FRAC_1_SQRT_2), 8_000.0);
+        assert!(pass.abs() < 0.05, "{} dB", pass);
+    }
+
+    #[test]
+    fn high_pass_rejects_dc() {
+        let mut chain = FilterChain::high_pass(FS as u32, 20.0);
+        let mut samples = vec![0.5f32; FS as usize];
+        chain.process(&mut samples);
+        assert!(samples[samples.len() - 100..]
+            .iter()
+            .all(|s| s.abs() < 1e-4));
+
+        chain.reset();
+        let mut step = [0.5f32];
+        chain.process(&mut step);
+        assert!((step[0] - 0.5).abs() < 0.01);
+
+        let mut untouched = vec![0.5f32; 64];
+        FilterChain::high_pass(FS as u32, 0.0).process(&mut untouched);
+        assert!(untouched.iter().all(|&s| s == 0.5));
+    }
+
+    #[test]
+    fn gain_filters_reach_their_gain() {
+        let peak = measured_db(Biquad::peaking(FS, 2_000.0, 1.0, 6.0), 2_000.0);
+        assert!((peak - 6.0).abs() < 0.05, "{} dB", peak);
+        let notch = measured_db(Biquad::notch(FS, 2_000.0, 1.0), 2_000.0);
+        assert!(notch < -40.0, "{} dB", notch);
+
+        let low_shelf = Biquad::low_shelf(FS, 200.0, FRAC_1_SQRT_2, -6.0);
+        assert!((measured_db(low_shelf.clone(), 20.0) + 6.0).abs() < 0.2);
+        assert!(measured_db(low_shelf, 10_000.0).abs() < 0.1);
+        let high_shelf = Biquad::high_shelf(FS, 5_000.0, FRAC_1_SQRT_2, 6.0);
+        assert!((measured_db(high_shelf.clone(), 20_000.0) - 6.0).abs() < 0.2);
+        assert!(measured_db(high_shelf, 100.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn stages_filter_each_channel_separately() {
+        let mut stage = FilterStage::new("highPass", FilterChain::high_pass(FS as u32, 20.0), 2);
+        let mut last = Frame::new(Vec::new(), 2);
+        for block in 0..50 {
+            // Left is DC, right a 1 kHz sine.
+            let samples = (0..960)
+                .flat_map(|i| {
+                    let t = (block * 960 + i) as f64 / FS;
+                    [0.5, (2.0 * PI * 1_000.0 * t).sin() as f32]
+                })
+                .collect();
+            last = Frame::new(samples, 2);
+            assert_eq!(stage.process(&mut last), Flow::Continue);
+        }
+        let left = last.samples.iter().step_by(2);
+        let right_peak = last
+            .samples
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .fold(0f32, |m, s| m.max(s.abs()));
+        assert!(left.clone().all(|s| s.abs() < 1e-3));
+        assert!((right_peak - 1.0).abs() < 0.01);
+    }
+}
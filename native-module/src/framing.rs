@@ -0,0 +1,477 @@
+use napi::bindgen_prelude::{Buffer, Either};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_AUDIO_FIELD: &str = "audio";
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// What a subscriber delivers: raw bytes, or text for the base64 and JSON
+/// formats.
+pub enum Payload {
+    Binary(Vec<u8>),
+    Text(String),
+}
+
+impl Payload {
+    /// A `Buffer` or a string on the JS side.
+    pub fn into_js(self) -> Either<Buffer, String> {
+        match self {
+            Payload::Binary(bytes) => Either::A(bytes.into()),
+            Payload::Text(text) => Either::B(text),
+        }
+    }
+}
+
+/// A rendered delivery and the audio it holds.
+pub struct Chunk {
+    pub payload: Payload,
+    pub duration_ms: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    Binary,
+    /// The chunk's bytes as one base64 string.
+    Base64,
+    /// A JSON object carrying the base64 audio.
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> napi::Result<Self> {
+        match value {
+            "binary" => Ok(OutputFormat::Binary),
+            "base64" => Ok(OutputFormat::Base64),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(napi::Error::from_reason(format!(
+                "Unknown output format: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FramingConfig {
+    pub format: OutputFormat,
+    /// Audio per delivery; `None` delivers each processing frame as is.
+    pub chunk_ms: Option<u32>,
+    /// Longest a partly filled chunk is held before it is sent anyway.
+    pub max_latency: Option<Duration>,
+    /// Value of a `"type"` field leading each JSON envelope.
+    pub json_type: Option<String>,
+    /// Name of the JSON field holding the audio.
+    pub audio_field: String,
+    /// Include sequence number, timestamps and format in JSON envelopes.
+    pub metadata: bool,
+}
+
+impl Default for FramingConfig {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Binary,
+            chunk_ms: None,
+            max_latency: None,
+            json_type: None,
+            audio_field: DEFAULT_AUDIO_FIELD.to_string(),
+            metadata: true,
+        }
+    }
+}
+
+/// Layout of the encoded bytes, also repeated in each JSON envelope.
+#[derive(Clone, Debug)]
+pub struct StreamFormat {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub sample_type: &'static str,
+    pub bytes_per_sample: usize,
+    /// Byte value of encoded silence.
+    pub silence: u8,
+}
+
+impl StreamFormat {
+    /// Bytes of one sample across all channels.
+    fn bytes_per_frame(&self) -> usize {
+        self.channels * self.bytes_per_sample
+    }
+
+    fn bytes_for(&self, duration: Duration) -> usize {
+        let samples = (self.sample_rate as u128 * duration.as_millis() / 1000).max(1);
+        samples as usize * self.bytes_per_frame()
+    }
+
+    fn ms(&self, samples: u64) -> f64 {
+        samples as f64 * 1000.0 / self.sample_rate as f64
+    }
+}
+
+/// Cuts the stream of encoded frames into delivery chunks of a fixed
+/// duration, independent of the processing frame size, and renders them in
+/// the configured format. Chunks only ever hold contiguous audio: a skipped
+/// frame closes the chunk in progress, except while holding, when it is
+/// filled with silence.
+pub struct Framer {
+    config: FramingConfig,
+    stream: StreamFormat,
+    /// Chunk size in bytes, whole samples; 0 passes frames through.
+    chunk_bytes: usize,
+    pending: Vec<u8>,
+    /// When the oldest pending audio arrived.
+    pending_since: Option<Instant>,
+    /// Samples per channel of audio time passed, delivered or skipped.
+    position: u64,
+    sequence: u64,
+    /// While set, audio gathers into one chunk of at most this many bytes
+    /// instead of being cut, the oldest dropped beyond it.
+    hold_limit: Option<usize>,
+    /// Samples per channel dropped while holding, not yet reported.
+    dropped: u64,
+}
+
+impl Framer {
+    pub fn new(config: FramingConfig, stream: StreamFormat) -> Self {
+        let chunk_bytes = config
+            .chunk_ms
+            .map_or(0, |ms| stream.bytes_for(Duration::from_millis(ms as u64)));
+        Self {
+            config,
+            stream,
+            chunk_bytes,
+            pending: Vec::new(),
+            pending_since: None,
+            position: 0,
+            sequence: 0,
+            hold_limit: None,
+            dropped: 0,
+        }
+    }
+
+    /// Adds one encoded frame, returning every chunk it completes.
+    pub fn push(&mut self, frame: Vec<u8>, now: Instant) -> Vec<Chunk> {
+        self.position += (frame.len() / self.stream.bytes_per_frame()) as u64;
+        if let Some(limit) = self.hold_limit {
+            if self.pending.is_empty() {
+                self.pending_since = Some(now);
+            }
+            self.pending.extend_from_slice(&frame);
+            if self.pending.len() > limit {
+                let excess = self.pending.len() - limit;
+                self.pending.drain(..excess);
+                self.dropped += (excess / self.stream.bytes_per_frame()) as u64;
+            }
+            return Vec::new();
+        }
+        if self.chunk_bytes == 0 {
+            self.pending = frame;
+            return self.flush().into_iter().collect();
+        }
+
+        if self.pending.is_empty() {
+            self.pending_since = Some(now);
+        }
+        self.pending.extend_from_slice(&frame);
+        let mut chunks = Vec::new();
+        while self.pending.len() >= self.chunk_bytes {
+            let rest = self.pending.split_off(self.chunk_bytes);
+            let bytes = std::mem::replace(&mut self.pending, rest);
+            chunks.push(self.render(bytes));
+        }
+        if self.pending.is_empty() {
+            self.pending_since = None;
+        }
+        chunks
+    }
+
+    /// Accounts for `samples` per channel that were not delivered, e.g.
+    /// suppressed silence. Returns the chunk they interrupted, if any.
+    pub fn skip(&mut self, samples: usize, now: Instant) -> Option<Chunk> {
+        if self.hold_limit.is_some() {
+            let silence = vec![self.stream.silence; samples * self.stream.bytes_per_frame()];
+            self.push(silence, now);
+            return None;
+        }
+        let chunk = self.flush();
+        self.position += samples as u64;
+        chunk
+    }
+
+    /// Emits whatever is pending as a short chunk.
+    pub fn flush(&mut self) -> Option<Chunk> {
+        self.pending_since = None;
+        if self.pending.is_empty() {
+            return None;
+        }
+        let bytes = std::mem::take(&mut self.pending);
+        Some(self.render(bytes))
+    }
+
+    /// Flushes the pending chunk once its oldest audio has waited the
+    /// maximum latency.
+    pub fn due(&mut self, now: Instant) -> Option<Chunk> {
+        match (self.pending_since, self.config.max_latency) {
+            (Some(since), Some(max))
+                if self.hold_limit.is_none() && now.duration_since(since) >= max =>
+            {
+                self.flush()
+            }
+            _ => None,
+        }
+    }
+
+    /// Stops cutting chunks, gathering at most `limit` of the newest audio
+    /// until `release`, e.g. while the consumer cannot keep up.
+    pub fn hold(&mut self, limit: Duration) {
+        if self.hold_limit.is_none() {
+            self.hold_limit = Some(self.stream.bytes_for(limit).max(self.chunk_bytes));
+        }
+    }
+
+    /// Ends holding, returning the gathered audio as one chunk.
+    pub fn release(&mut self) -> Option<Chunk> {
+        self.hold_limit.take()?;
+        self.flush()
+    }
+
+    /// Milliseconds of audio dropped while holding since the last call.
+    pub fn take_dropped_ms(&mut self) -> f64 {
+        self.stream.ms(std::mem::take(&mut self.dropped))
+    }
+
+    /// Renders the bytes ending at the current position and advances the
+    /// sequence number.
+    fn render(&mut self, bytes: Vec<u8>) -> Chunk {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        let samples = (bytes.len() / self.stream.bytes_per_frame()) as u64;
+        let duration_ms = self.stream.ms(samples);
+        let payload = match self.config.format {
+            OutputFormat::Binary => Payload::Binary(bytes),
+            OutputFormat::Base64 => Payload::Text(base64(&bytes)),
+            OutputFormat::Json => {
+                let pending = (self.pending.len() / self.stream.bytes_per_frame()) as u64;
+                let start = self.position - pending - samples;
+
+                let mut json = String::with_capacity(bytes.len() * 4 / 3 + 200);
+                json.push('{');
+                if let Some(ref kind) = self.config.json_type {
+                    json.push_str(&format!("\"type\":{},", json_string(kind)));
+                }
+                if self.config.metadata {
+                    json.push_str(&format!(
+                        "\"seq\":{},\"timestampMs\":{},\"durationMs\":{},\"wallClockMs\":{},\
+                         \"sampleRate\":{},\"channels\":{},\"sampleType\":\"{}\",",
+                        sequence,
+                        self.stream.ms(start),
+                        duration_ms,
+                        wall_clock_ms(),
+                        self.stream.sample_rate,
+                        self.stream.channels,
+                        self.stream.sample_type,
+                    ));
+                }
+                json.push_str(&json_string(&self.config.audio_field));
+                json.push(':');
+                json.push('"');
+                json.push_str(&base64(&bytes));
+                json.push_str("\"}");
+                Payload::Text(json)
+            }
+        };
+        Chunk {
+            payload,
+            duration_ms,
+        }
+    }
+}
+
+fn wall_clock_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Standard base64 with padding (RFC 4648).
+pub fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// A quoted JSON string literal.
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One byte per millisecond keeps the arithmetic readable.
+    fn stream() -> StreamFormat {
+        StreamFormat {
+            sample_rate: 1_000,
+            channels: 1,
+            sample_type: "mulaw",
+            bytes_per_sample: 1,
+            silence: 0xFF,
+        }
+    }
+
+    fn framer_with(config: FramingConfig) -> Framer {
+        Framer::new(config, stream())
+    }
+
+    fn text(chunk: Option<Chunk>) -> String {
+        match chunk.map(|c| c.payload) {
+            Some(Payload::Text(text)) => text,
+            _ => panic!("expected a text payload"),
+        }
+    }
+
+    fn binary(chunks: impl IntoIterator<Item = Chunk>) -> Vec<Vec<u8>> {
+        chunks
+            .into_iter()
+            .map(|c| match c.payload {
+                Payload::Binary(bytes) => bytes,
+                Payload::Text(_) => panic!("expected binary"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn encodes_rfc4648_vectors() {
+        let cases = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(base64(input.as_bytes()), expected);
+        }
+        assert_eq!(base64(&[0xFB, 0xFF, 0xBF]), "+/+/");
+    }
+
+    #[test]
+    fn cuts_chunks_independently_of_frames() {
+        let now = Instant::now();
+        let mut framer = framer_with(FramingConfig {
+            chunk_ms: Some(5),
+            ..FramingConfig::default()
+        });
+        assert!(framer.push(vec![1, 2, 3], now).is_empty());
+        assert_eq!(
+            binary(framer.push(vec![4, 5, 6, 7, 8, 9, 10, 11, 12], now)),
+            vec![vec![1, 2, 3, 4, 5], vec![6, 7, 8, 9, 10]]
+        );
+        assert_eq!(binary(framer.flush()), vec![vec![11, 12]]);
+        assert!(framer.flush().is_none());
+
+        // Without a chunk duration frames pass through whole.
+        let mut framer = framer_with(FramingConfig::default());
+        assert_eq!(binary(framer.push(vec![1, 2, 3], now)), vec![vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn flushes_partial_chunks_after_max_latency() {
+        let start = Instant::now();
+        let mut framer = framer_with(FramingConfig {
+            chunk_ms: Some(100),
+            max_latency: Some(Duration::from_millis(50)),
+            ..FramingConfig::default()
+        });
+        framer.push(vec![0; 20], start);
+        framer.push(vec![0; 20], start + Duration::from_millis(20));
+        assert!(framer.due(start + Duration::from_millis(49)).is_none());
+        let flushed = framer.due(start + Duration::from_millis(50));
+        assert_eq!(flushed.map(|c| c.duration_ms), Some(40.0));
+        // The timer restarts with the next audio.
+        framer.push(vec![0; 20], start + Duration::from_millis(60));
+        assert!(framer.due(start + Duration::from_millis(100)).is_none());
+    }
+
+    #[test]
+    fn json_envelopes_carry_sequence_and_stream_time() {
+        let now = Instant::now();
+        let mut framer = framer_with(FramingConfig {
+            format: OutputFormat::Json,
+            chunk_ms: Some(4),
+            json_type: Some("input_audio_buffer.append".to_string()),
+            ..FramingConfig::default()
+        });
+        let first = text(framer.push(b"abcdef".to_vec(), now).pop());
+        assert!(first.starts_with(
+            r#"{"type":"input_audio_buffer.append","seq":0,"timestampMs":0,"durationMs":4,"#
+        ));
+        assert!(first.ends_with(r#""sampleType":"mulaw","audio":"YWJjZA=="}"#));
+
+        // A skipped frame closes the chunk and moves the clock on.
+        let second = text(framer.skip(10, now));
+        assert!(second.contains(r#""seq":1,"timestampMs":4,"durationMs":2,"#));
+        let third = text(framer.push(b"ghij".to_vec(), now).pop());
+        assert!(third.contains(r#""seq":2,"timestampMs":16,"#));
+    }
+
+    #[test]
+    fn holding_gathers_one_chunk_and_keeps_the_newest_audio() {
+        let now = Instant::now();
+        let mut framer = framer_with(FramingConfig {
+            chunk_ms: Some(2),
+            ..FramingConfig::default()
+        });
+        framer.hold(Duration::from_millis(6));
+        assert!(framer.push(vec![1, 2, 3], now).is_empty());
+        assert!(framer.skip(2, now).is_none());
+        assert!(framer.push(vec![4, 5, 6], now).is_empty());
+        assert_eq!(framer.take_dropped_ms(), 2.0);
+
+        assert_eq!(binary(framer.release()), vec![vec![3, 0xFF, 0xFF, 4, 5, 6]]);
+        assert!(framer.release().is_none());
+        assert_eq!(binary(framer.push(vec![7, 8], now)), vec![vec![7, 8]]);
+    }
+
+    #[test]
+    fn json_without_metadata_is_minimal() {
+        let mut framer = framer_with(FramingConfig {
+            format: OutputFormat::Json,
+            audio_field: "pay\"load".to_string(),
+            metadata: false,
+            ..FramingConfig::default()
+        });
+        let json = text(framer.push(vec![0, 0], Instant::now()).pop());
+        assert_eq!(json, r#"{"pay\"load":"AAA="}"#);
+    }
+}
@@ -0,0 +1,46 @@
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::JsFunction;
+
+#[napi(object)]
+pub struct GapEvent {
+    /// Position of the gap in the capture timeline, in milliseconds since
+    /// `start`.
+    pub start_ms: f64,
+    pub duration_ms: f64,
+}
+
+/// Receives an explicit marker for every stretch of audio the device did not
+/// deliver, in addition to (or instead of) subscribers' silence fill.
+pub struct GapSubscriber {
+    id: u32,
+    events: Box<dyn Fn(GapEvent) + Send>,
+}
+
+impl GapSubscriber {
+    pub fn new(id: u32, callback: &JsFunction) -> napi::Result<Self> {
+        let tsfn: ThreadsafeFunction<GapEvent, ErrorStrategy::Fatal> =
+            callback.create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))?;
+        Ok(Self::with_events(
+            id,
+            Box::new(move |event| {
+                tsfn.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+            }),
+        ))
+    }
+
+    /// Reports gaps to native code instead of a JS callback.
+    pub fn with_events(id: u32, events: Box<dyn Fn(GapEvent) + Send>) -> Self {
+        Self { id, events }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn notify(&self, start_ms: f64, duration_ms: f64) {
+        (self.events)(GapEvent {
+            start_ms,
+            duration_ms,
+        });
+    }
+}
@@ -0,0 +1,533 @@
+use anyhow::Result;
+use napi::bindgen_prelude::Buffer;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::audio_config::SAMPLE_RATE;
+use crate::conversion::f32_to_i16;
+use crate::downmix::ChannelLayout;
+use crate::loudness::LoudnessMeter;
+use crate::sample_format::SampleEncoding;
+use crate::streaming_resampler::StreamingResampler;
+use crate::wav::WavWriter;
+
+const DEFAULT_SECONDS: u32 = 120;
+const MAX_SECONDS: u32 = 3_600;
+const DEFAULT_MAX_MEMORY_MB: u32 = 64;
+
+#[napi(object)]
+pub struct HistoryOptions {
+    /// Audio kept, default 120 s (at most 3600).
+    pub seconds: Option<u32>,
+    /// Also keep the device audio as captured, at its own rate and channel
+    /// count. Default false.
+    pub include_raw: Option<bool>,
+    /// Memory for all kept audio, default 64 MB. The history is shortened
+    /// rather than exceed it.
+    pub max_memory_mb: Option<u32>,
+}
+
+#[napi(object)]
+pub struct RecentAudio {
+    pub audio: Buffer,
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// `"i16"` for processed audio, `"f32"` for raw; both little-endian.
+    pub sample_type: String,
+    pub duration_ms: f64,
+}
+
+pub struct HistoryConfig {
+    pub seconds: u32,
+    pub include_raw: bool,
+    pub max_bytes: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            seconds: DEFAULT_SECONDS,
+            include_raw: false,
+            max_bytes: DEFAULT_MAX_MEMORY_MB as usize * 1024 * 1024,
+        }
+    }
+}
+
+impl HistoryConfig {
+    pub fn from_options(options: Option<HistoryOptions>) -> napi::Result<Self> {
+        let mut config = Self::default();
+        let options = match options {
+            Some(o) => o,
+            None => return Ok(config),
+        };
+
+        if let Some(seconds) = options.seconds {
+            if seconds == 0 || seconds > MAX_SECONDS {
+                return Err(napi::Error::from_reason(format!(
+                    "Unsupported history length: {} s",
+                    seconds
+                )));
+            }
+            config.seconds = seconds;
+        }
+        if let Some(mb) = options.max_memory_mb {
+            if mb == 0 {
+                return Err(napi::Error::from_reason(
+                    "History memory cap must be positive",
+                ));
+            }
+            config.max_bytes = mb as usize * 1024 * 1024;
+        }
+        config.include_raw = options.include_raw.unwrap_or(false);
+        Ok(config)
+    }
+}
+
+/// Samples kept as blocks shared with the history, so a snapshot taken
+/// under the consumer lock clones handles rather than audio.
+#[derive(Clone)]
+pub struct SharedSamples<T> {
+    chunks: VecDeque<Arc<[T]>>,
+    /// Leading samples of the first block that have been trimmed away.
+    offset: usize,
+    len: usize,
+}
+
+impl<T: Copy> SharedSamples<T> {
+    fn new() -> Self {
+        Self {
+            chunks: VecDeque::new(),
+            offset: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The samples in order, block by block.
+    pub fn chunks(&self) -> impl Iterator<Item = &[T]> {
+        let offset = self.offset;
+        self.chunks
+            .iter()
+            .enumerate()
+            .map(move |(i, chunk)| if i == 0 { &chunk[offset..] } else { &chunk[..] })
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        let mut samples = Vec::with_capacity(self.len);
+        for chunk in self.chunks() {
+            samples.extend_from_slice(chunk);
+        }
+        samples
+    }
+
+    fn push(&mut self, samples: Vec<T>) {
+        if !samples.is_empty() {
+            self.len += samples.len();
+            self.chunks.push_back(samples.into());
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Drops the oldest samples beyond `capacity`.
+    fn trim_front(&mut self, capacity: usize) {
+        while self.len > capacity {
+            let excess = self.len - capacity;
+            let first = self.chunks.front().map_or(0, |c| c.len()) - self.offset;
+            if first <= excess {
+                self.chunks.pop_front();
+                self.offset = 0;
+                self.len -= first;
+            } else {
+                self.offset += excess;
+                self.len -= excess;
+            }
+        }
+    }
+
+    /// The last `count` samples, sharing this history's blocks.
+    fn tail(&self, count: usize) -> Self {
+        let mut tail = self.clone();
+        tail.trim_front(count);
+        tail
+    }
+}
+
+/// The most recent history, taken under the consumer lock and copied,
+/// encoded or written to disk after it is released.
+pub enum HistorySnapshot {
+    /// 16 kHz mono, as the speech consumers see it.
+    Processed(SharedSamples<i16>),
+    /// Interleaved device audio.
+    Raw {
+        sample_rate: u32,
+        channels: usize,
+        samples: SharedSamples<f32>,
+    },
+}
+
+impl HistorySnapshot {
+    pub fn sample_rate(&self) -> u32 {
+        match *self {
+            HistorySnapshot::Processed(_) => SAMPLE_RATE,
+            HistorySnapshot::Raw { sample_rate, .. } => sample_rate,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match *self {
+            HistorySnapshot::Processed(_) => 1,
+            HistorySnapshot::Raw { channels, .. } => channels,
+        }
+    }
+
+    pub fn duration_ms(&self) -> f64 {
+        let samples = match *self {
+            HistorySnapshot::Processed(ref samples) => samples.len(),
+            HistorySnapshot::Raw { ref samples, .. } => samples.len(),
+        };
+        (samples / self.channels()) as f64 * 1000.0 / self.sample_rate() as f64
+    }
+
+    pub fn into_recent_audio(self) -> RecentAudio {
+        let (sample_rate, channels, duration_ms) = (
+            self.sample_rate(),
+            self.channels() as u32,
+            self.duration_ms(),
+        );
+        let (bytes, sample_type) = match self {
+            HistorySnapshot::Processed(samples) => (
+                samples
+                    .chunks()
+                    .flatten()
+                    .flat_map(|s| s.to_le_bytes())
+                    .collect::<Vec<u8>>(),
+                "i16",
+            ),
+            HistorySnapshot::Raw { samples, .. } => (
+                samples
+                    .chunks()
+                    .flatten()
+                    .flat_map(|s| s.to_le_bytes())
+                    .collect::<Vec<u8>>(),
+                "f32",
+            ),
+        };
+        RecentAudio {
+            audio: bytes.into(),
+            sample_rate,
+            channels,
+            sample_type: sample_type.to_string(),
+            duration_ms,
+        }
+    }
+
+    /// Writes the audio as a WAV file (16-bit for processed, float for raw)
+    /// with its loudness in the `bext` chunk.
+    pub fn write_wav(&self, path: &Path) -> Result<()> {
+        let channels = self.channels();
+        let mut meter =
+            LoudnessMeter::new(self.sample_rate(), &ChannelLayout::default_for(channels));
+        let mut writer = match *self {
+            HistorySnapshot::Processed(ref samples) => {
+                let mut writer = WavWriter::create(path, SAMPLE_RATE, 1, SampleEncoding::I16)?;
+                for chunk in samples.chunks() {
+                    writer.write_i16(chunk)?;
+                    let mono: Vec<f32> = chunk.iter().map(|&s| s as f32 / 32768.0).collect();
+                    meter.push(&mono);
+                }
+                writer
+            }
+            HistorySnapshot::Raw {
+                sample_rate,
+                ref samples,
+                ..
+            } => {
+                let mut writer =
+                    WavWriter::create(path, sample_rate, channels as u16, SampleEncoding::F32)?;
+                for chunk in samples.chunks() {
+                    writer.write(chunk)?;
+                    meter.push(chunk);
+                }
+                writer
+            }
+        };
+        writer.set_loudness(meter.summary());
+        writer.finish()
+    }
+}
+
+/// Rolling record of the last few minutes of captured audio, so a recent
+/// moment can be fetched or saved after the fact.
+pub struct AudioHistory {
+    config: HistoryConfig,
+    resampler: Option<(u32, StreamingResampler)>,
+    drift_ppm: f64,
+    processed: SharedSamples<i16>,
+    raw: SharedSamples<f32>,
+    /// Rate and channel count of `raw`; it restarts when the device changes.
+    raw_format: Option<(u32, usize)>,
+}
+
+impl AudioHistory {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self {
+            config,
+            resampler: None,
+            drift_ppm: 0.0,
+            processed: SharedSamples::new(),
+            raw: SharedSamples::new(),
+            raw_format: None,
+        }
+    }
+
+    pub fn set_drift_ppm(&mut self, ppm: f64) {
+        self.drift_ppm = ppm;
+        if let Some((_, ref mut resampler)) = self.resampler {
+            resampler.set_drift_ppm(ppm);
+        }
+    }
+
+    /// Restarts resampling for a new capture run. The kept audio stays, so
+    /// recent audio can still be fetched after a restart.
+    pub fn reset(&mut self) {
+        self.resampler = None;
+    }
+
+    /// Appends device audio: `samples` interleaved, `mono` its mix.
+    pub fn push(&mut self, samples: &[f32], mono: &[f32], input_sample_rate: u32, channels: usize) {
+        let resampler = match self.resampler {
+            Some((rate, ref mut resampler)) if rate == input_sample_rate => resampler,
+            _ => {
+                let mut resampler =
+                    StreamingResampler::new(input_sample_rate as f64, SAMPLE_RATE as f64);
+                resampler.set_drift_ppm(self.drift_ppm);
+                &mut self.resampler.insert((input_sample_rate, resampler)).1
+            }
+        };
+        self.processed.push(
+            resampler
+                .process(mono)
+                .into_iter()
+                .map(f32_to_i16)
+                .collect(),
+        );
+        let processed_cap = self.processed_capacity();
+        self.processed.trim_front(processed_cap);
+
+        if self.config.include_raw {
+            if self.raw_format != Some((input_sample_rate, channels)) {
+                self.raw.clear();
+                self.raw_format = Some((input_sample_rate, channels));
+            }
+            self.raw.push(samples.to_vec());
+            let raw_cap = self.raw_capacity(input_sample_rate, channels);
+            self.raw.trim_front(raw_cap);
+        }
+    }
+
+    /// The last `seconds` of history, or as much as is kept. Only block
+    /// handles are cloned; the audio is copied when the snapshot is used.
+    pub fn snapshot(&self, seconds: f64, raw: bool) -> napi::Result<HistorySnapshot> {
+        if !seconds.is_finite() || seconds <= 0.0 {
+            return Err(napi::Error::from_reason(format!(
+                "Invalid duration: {} s",
+                seconds
+            )));
+        }
+        if !raw {
+            let wanted = (seconds * SAMPLE_RATE as f64) as usize;
+            return Ok(HistorySnapshot::Processed(self.processed.tail(wanted)));
+        }
+
+        if !self.config.include_raw {
+            return Err(napi::Error::from_reason(
+                "Raw audio is not kept; enable history with includeRaw",
+            ));
+        }
+        let (sample_rate, channels) = self.raw_format.unwrap_or((SAMPLE_RATE, 1));
+        let wanted = (seconds * sample_rate as f64) as usize * channels;
+        Ok(HistorySnapshot::Raw {
+            sample_rate,
+            channels,
+            samples: self.raw.tail(wanted),
+        })
+    }
+
+    fn processed_capacity(&self) -> usize {
+        let by_time = self.config.seconds as usize * SAMPLE_RATE as usize;
+        by_time.min(self.processed_budget() / 2)
+    }
+
+    /// Processed audio is small and always kept, so raw audio gets what
+    /// remains of the memory cap.
+    fn raw_capacity(&self, sample_rate: u32, channels: usize) -> usize {
+        let by_time = self.config.seconds as usize * sample_rate as usize * channels;
+        let budget = self.config.max_bytes - self.processed_budget();
+        // Whole frames only, so the ring never starts mid-frame.
+        by_time.min(budget / 4 / channels.max(1) * channels)
+    }
+
+    fn processed_budget(&self) -> usize {
+        if self.config.include_raw {
+            self.config.max_bytes / 8
+        } else {
+            self.config.max_bytes
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: usize = 1024 * 1024;
+
+    fn config(seconds: u32, include_raw: bool, max_bytes: usize) -> HistoryConfig {
+        HistoryConfig {
+            seconds,
+            include_raw,
+            max_bytes,
+        }
+    }
+
+    /// Pushes `blocks` of 100 ms stereo at `rate`, block `i` holding the
+    /// value `i / 100`. Returns the value of the last block.
+    fn push_blocks(history: &mut AudioHistory, rate: u32, blocks: usize) -> f32 {
+        let frames = rate as usize / 10;
+        let mut value = 0.0;
+        for i in 0..blocks {
+            value = i as f32 / 100.0;
+            history.push(&vec![value; frames * 2], &vec![value; frames], rate, 2);
+        }
+        value
+    }
+
+    fn raw_samples(snapshot: HistorySnapshot) -> (u32, usize, Vec<f32>) {
+        match snapshot {
+            HistorySnapshot::Raw {
+                sample_rate,
+                channels,
+                samples,
+            } => (sample_rate, channels, samples.to_vec()),
+            HistorySnapshot::Processed(_) => panic!("processed audio for a raw snapshot"),
+        }
+    }
+
+    fn processed_samples(snapshot: HistorySnapshot) -> Vec<i16> {
+        match snapshot {
+            HistorySnapshot::Processed(samples) => samples.to_vec(),
+            HistorySnapshot::Raw { .. } => panic!("raw audio for a processed snapshot"),
+        }
+    }
+
+    #[test]
+    fn keeps_the_last_seconds() {
+        let mut history = AudioHistory::new(config(1, true, 64 * MB));
+        let last = push_blocks(&mut history, 48_000, 25);
+        assert_eq!(history.processed.len(), 16_000);
+        assert_eq!(history.raw.len(), 48_000 * 2);
+
+        // The oldest kept raw audio is block 15, in whole frames.
+        let (_, _, raw) = raw_samples(history.snapshot(10.0, true).unwrap());
+        assert_eq!(raw.len(), 96_000);
+        assert_eq!(raw[0], 0.15);
+        assert_eq!(raw[raw.len() - 1], last);
+
+        let (rate, channels, raw) = raw_samples(history.snapshot(0.25, true).unwrap());
+        assert_eq!((rate, channels, raw.len()), (48_000, 2, 24_000));
+        assert_eq!(raw[0], 0.22);
+        assert_eq!(raw[12_000], 0.23);
+
+        let processed = processed_samples(history.snapshot(0.05, false).unwrap());
+        assert_eq!(processed, vec![f32_to_i16(last); 800]);
+    }
+
+    #[test]
+    fn splits_the_memory_budget() {
+        // An eighth of the cap for processed audio, the rest for raw.
+        let mut history = AudioHistory::new(config(MAX_SECONDS, true, MB));
+        push_blocks(&mut history, 48_000, 100);
+        assert_eq!(history.processed.len(), MB / 8 / 2);
+        assert_eq!(history.raw.len(), (MB - MB / 8) / 4);
+        assert_eq!(history.raw.len() % 2, 0);
+
+        let mut history = AudioHistory::new(config(MAX_SECONDS, false, MB));
+        push_blocks(&mut history, 48_000, 400);
+        assert_eq!(history.processed.len(), MB / 2);
+        assert!(history.raw.is_empty());
+    }
+
+    #[test]
+    fn restarts_raw_audio_when_the_format_changes() {
+        let mut history = AudioHistory::new(config(10, true, 64 * MB));
+        push_blocks(&mut history, 48_000, 5);
+        history.push(&[0.5; 4_410], &[0.5; 4_410], 44_100, 1);
+
+        let (rate, channels, raw) = raw_samples(history.snapshot(10.0, true).unwrap());
+        assert_eq!((rate, channels), (44_100, 1));
+        assert_eq!(raw, vec![0.5; 4_410]);
+        // Processed audio carries on across the change.
+        let processed = history.snapshot(10.0, false).unwrap();
+        assert!((processed.duration_ms() - 600.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn returns_what_is_kept_when_asked_for_more() {
+        let mut history = AudioHistory::new(config(10, false, 64 * MB));
+        push_blocks(&mut history, 16_000, 3);
+        let snapshot = history.snapshot(60.0, false).unwrap();
+        assert_eq!(snapshot.duration_ms(), 300.0);
+
+        let recent = snapshot.into_recent_audio();
+        assert_eq!((recent.sample_rate, recent.channels), (16_000, 1));
+        assert_eq!(recent.sample_type, "i16");
+        assert_eq!(recent.audio.len(), 4_800 * 2);
+    }
+
+    #[test]
+    fn snapshots_are_unaffected_by_later_audio() {
+        let mut history = AudioHistory::new(config(1, true, 64 * MB));
+        push_blocks(&mut history, 48_000, 10);
+        let (_, _, before) = raw_samples(history.snapshot(0.5, true).unwrap());
+        let shared = history.snapshot(0.5, true).unwrap();
+
+        // Trims every block the snapshot shares.
+        push_blocks(&mut history, 48_000, 20);
+        assert_eq!(raw_samples(shared).2, before);
+    }
+
+    #[test]
+    fn rejects_raw_requests_without_include_raw_and_bad_durations() {
+        let mut history = AudioHistory::new(HistoryConfig::default());
+        push_blocks(&mut history, 48_000, 1);
+        let error = history.snapshot(1.0, true).err().unwrap();
+        assert!(error.reason.contains("includeRaw"), "{}", error.reason);
+
+        for seconds in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(history.snapshot(seconds, false).is_err(), "{}", seconds);
+        }
+
+        let options = |seconds, max_memory_mb| {
+            HistoryConfig::from_options(Some(HistoryOptions {
+                seconds,
+                include_raw: None,
+                max_memory_mb,
+            }))
+        };
+        assert!(options(Some(0), None).is_err());
+        assert!(options(Some(MAX_SECONDS + 1), None).is_err());
+        assert!(options(None, Some(0)).is_err());
+        assert_eq!(options(Some(30), Some(8)).unwrap().max_bytes, 8 * MB);
+    }
+}
@@ -0,0 +1,610 @@
+use napi::bindgen_prelude::Buffer;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::JsFunction;
+
+use crate::audio_config::SAMPLE_RATE;
+use crate::diarization::cosine;
+use crate::mfcc::{Mfcc, MfccFrame, CEPSTRA, HOP_SAMPLES, WINDOW_SAMPLES};
+use crate::streaming_resampler::StreamingResampler;
+
+const DEFAULT_THRESHOLD: f64 = 0.6;
+/// Example frames quieter than this (relative to the loudest) are trimmed
+/// from both ends of a template.
+const TRIM_FLOOR_DB: f32 = 30.0;
+const MIN_TEMPLATE_FRAMES: usize = 15;
+const MAX_TEMPLATE_FRAMES: usize = 200;
+/// Matches without a frame this loud are ignored.
+const SPEECH_FLOOR_DB: f32 = -45.0;
+/// Frames between decisions; each takes the best match ending since the
+/// previous one.
+const SEARCH_HOP_FRAMES: usize = 5;
+/// A match may be this much shorter or longer than its template.
+const MAX_TIME_WARP: f32 = 2.0;
+/// Weight of each voiced frame in the running cepstral mean (about 2 s).
+const MEAN_ADAPTATION: f32 = 0.005;
+
+#[napi(object)]
+pub struct KeywordDefinition {
+    /// Reported back on detection, e.g. `"Nyx"`.
+    pub phrase: String,
+    /// One or more recordings of the phrase, 16 kHz mono i16 LE, each
+    /// 150 ms to 2 s of speech. More examples (different voices, pace) make
+    /// detection more robust.
+    pub examples: Vec<Buffer>,
+    /// Confidence (0-1) needed to report a detection, default 0.6. Higher
+    /// means fewer false alarms and more misses.
+    pub threshold: Option<f64>,
+}
+
+#[napi(object)]
+pub struct KeywordEvent {
+    pub phrase: String,
+    /// Match quality, 0-1.
+    pub confidence: f64,
+    /// Bounds of the match in milliseconds of audio since the subscription
+    /// began.
+    pub start_ms: f64,
+    pub end_ms: f64,
+}
+
+struct Keyword {
+    phrase: String,
+    threshold: f32,
+    /// One per example.
+    matchers: Vec<Matcher>,
+}
+
+impl Keyword {
+    fn from_definition(definition: KeywordDefinition, mfcc: &Mfcc) -> napi::Result<Self> {
+        let threshold = definition.threshold.unwrap_or(DEFAULT_THRESHOLD);
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(napi::Error::from_reason(format!(
+                "Unsupported keyword threshold for \"{}\": {}",
+                definition.phrase, threshold
+            )));
+        }
+        if definition.examples.is_empty() {
+            return Err(napi::Error::from_reason(format!(
+                "Keyword \"{}\" needs at least one example recording",
+                definition.phrase
+            )));
+        }
+
+        let templates = definition
+            .examples
+            .iter()
+            .map(|example| {
+                if example.len() % 2 != 0 {
+                    return Err(napi::Error::from_reason(format!(
+                        "Example for \"{}\" is not 16-bit PCM",
+                        definition.phrase
+                    )));
+                }
+                let samples: Vec<f32> = example
+                    .chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0)
+                    .collect();
+                let template = template(&mfcc.frames(&samples));
+                if !(MIN_TEMPLATE_FRAMES..=MAX_TEMPLATE_FRAMES).contains(&template.len()) {
+                    return Err(napi::Error::from_reason(format!(
+                        "Example for \"{}\" holds {} ms of speech; expected 150-2000 ms",
+                        definition.phrase,
+                        template.len() * HOP_SAMPLES * 1000 / SAMPLE_RATE as usize
+                    )));
+                }
+                Ok(template)
+            })
+            .collect::<napi::Result<_>>()?;
+
+        Ok(Self::new(definition.phrase, threshold as f32, templates))
+    }
+
+    fn new(phrase: String, threshold: f32, templates: Vec<Vec<Vec<f32>>>) -> Self {
+        Self {
+            phrase,
+            threshold,
+            matchers: templates.into_iter().map(Matcher::new).collect(),
+        }
+    }
+}
+
+/// Trims the quiet ends off an example's frames and removes its cepstral
+/// mean, so the recording channel matters less than the phonetic content.
+fn template(frames: &[MfccFrame]) -> Vec<Vec<f32>> {
+    let loudest = frames.iter().map(|f| f.energy_db).fold(f32::MIN, f32::max);
+    let voiced = |f: &&MfccFrame| f.energy_db >= loudest - TRIM_FLOOR_DB;
+    let first = frames.iter().position(|f| voiced(&f));
+    let last = frames.iter().rposition(|f| voiced(&f));
+    let frames = match (first, last) {
+        (Some(first), Some(last)) => &frames[first..=last],
+        _ => return Vec::new(),
+    };
+
+    let mut mean = vec![0.0f32; CEPSTRA];
+    for frame in frames {
+        for (m, c) in mean.iter_mut().zip(&frame.cepstra) {
+            *m += c / frames.len() as f32;
+        }
+    }
+    frames
+        .iter()
+        .map(|f| f.cepstra.iter().zip(&mean).map(|(c, m)| c - m).collect())
+        .collect()
+}
+
+#[derive(Clone, Copy)]
+struct Cell {
+    /// Accumulated cosine distance along the path.
+    cost: f32,
+    length: u32,
+    /// Stream frame the path starts at.
+    start: u64,
+}
+
+impl Cell {
+    fn mean(&self) -> f32 {
+        self.cost / self.length as f32
+    }
+}
+
+/// Subsequence DTW of one template against the stream, advanced by one
+/// column per stream frame, so matching costs the same whatever the history.
+struct Matcher {
+    /// Mean-normalised cepstra.
+    template: Vec<Vec<f32>>,
+    /// Per template frame, the cheapest path (by mean cost, so long and short
+    /// paths compare fairly) ending at the latest stream frame.
+    column: Vec<Cell>,
+}
+
+impl Matcher {
+    fn new(template: Vec<Vec<f32>>) -> Self {
+        Self {
+            column: Vec::with_capacity(template.len()),
+            template,
+        }
+    }
+
+    /// Adds stream frame `position` and returns the best alignment of the
+    /// whole template ending there, as `(score, start)` with the score being
+    /// 1 minus the mean cosine distance along the path. `None` while the
+    /// path is too warped to count.
+    fn step(&mut self, frame: &[f32], position: u64) -> Option<(f32, u64)> {
+        // Cells (row - 1) of the previous and of this column.
+        let (mut diagonal, mut below): (Option<Cell>, Option<Cell>) = (None, None);
+        for (row, expected) in self.template.iter().enumerate() {
+            let left = self.column.get(row).copied();
+            let best = match below {
+                // Free start anywhere in the stream.
+                None => Cell {
+                    cost: 0.0,
+                    length: 0,
+                    start: position,
+                },
+                Some(below) => [diagonal, left, Some(below)]
+                    .into_iter()
+                    .flatten()
+                    .min_by(|a, b| a.mean().total_cmp(&b.mean()))
+                    .unwrap_or(below),
+            };
+            let cell = Cell {
+                cost: best.cost + 1.0 - cosine(expected, frame),
+                length: best.length + 1,
+                start: best.start,
+            };
+
+            match self.column.get_mut(row) {
+                Some(slot) => *slot = cell,
+                None => self.column.push(cell),
+            }
+            diagonal = left;
+            below = Some(cell);
+        }
+
+        let end = below?;
+        let (rows, length) = (
+            self.template.len() as f32,
+            (position + 1 - end.start) as f32,
+        );
+        (length >= rows / MAX_TIME_WARP && length <= rows * MAX_TIME_WARP)
+            .then(|| (1.0 - end.mean(), end.start))
+    }
+}
+
+/// Prepared templates for a keyword list, built before taking the consumer
+/// lock so enrolment never stalls capture.
+pub struct KeywordSet(Vec<Keyword>);
+
+impl KeywordSet {
+    pub fn from_definitions(definitions: Vec<KeywordDefinition>) -> napi::Result<Self> {
+        let mfcc = Mfcc::new();
+        definitions
+            .into_iter()
+            .map(|d| Keyword::from_definition(d, &mfcc))
+            .collect::<napi::Result<_>>()
+            .map(Self)
+    }
+}
+
+struct Detection {
+    confidence: f32,
+    /// Frame indices since creation.
+    start: u64,
+    end: u64,
+}
+
+/// Template-matching keyword spotter over 16 kHz audio. Examples of each
+/// phrase are aligned with the incoming cepstra by subsequence DTW, one frame
+/// at a time; every 50 ms the best match since the last decision is
+/// considered, and a detection is reported once its score stops improving.
+pub struct KeywordSpotter {
+    mfcc: Mfcc,
+    keywords: Vec<Keyword>,
+    samples: Vec<f32>,
+    /// Index of the next frame.
+    position: u64,
+    mean: Vec<f32>,
+    last_loud: Option<u64>,
+    since_search: usize,
+    /// Per keyword: the best match since the last decision, the best one not
+    /// reported yet, and where the last reported one ended.
+    candidates: Vec<Option<Detection>>,
+    pending: Vec<Option<Detection>>,
+    reported_until: Vec<u64>,
+}
+
+impl KeywordSpotter {
+    pub fn new() -> Self {
+        Self {
+            mfcc: Mfcc::new(),
+            keywords: Vec::new(),
+            samples: Vec::new(),
+            position: 0,
+            mean: vec![0.0; CEPSTRA],
+            last_loud: None,
+            since_search: 0,
+            candidates: Vec::new(),
+            pending: Vec::new(),
+            reported_until: Vec::new(),
+        }
+    }
+
+    /// Replaces the keyword list; the stream's cepstral mean is kept.
+    pub fn set_keywords(&mut self, keywords: KeywordSet) {
+        self.candidates = keywords.0.iter().map(|_| None).collect();
+        self.pending = keywords.0.iter().map(|_| None).collect();
+        self.reported_until = vec![0; keywords.0.len()];
+        self.keywords = keywords.0;
+    }
+
+    /// Drops buffered audio and matches in progress, so a phrase is not
+    /// joined across capture runs. Positions and the cepstral mean carry on.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.last_loud = None;
+        self.since_search = 0;
+        for matcher in self.keywords.iter_mut().flat_map(|k| k.matchers.iter_mut()) {
+            matcher.column.clear();
+        }
+        self.candidates.fill_with(|| None);
+        self.pending.fill_with(|| None);
+    }
+
+    /// Feeds 16 kHz mono audio and returns the detections completed by it.
+    pub fn push(&mut self, audio: &[f32]) -> Vec<KeywordEvent> {
+        self.samples.extend_from_slice(audio);
+        let frames = self.mfcc.frames(&self.samples);
+        self.samples.drain(..frames.len() * HOP_SAMPLES);
+
+        let mut events = Vec::new();
+        for frame in frames {
+            self.push_frame(frame);
+            self.since_search += 1;
+            if self.since_search >= SEARCH_HOP_FRAMES {
+                self.since_search = 0;
+                self.decide(&mut events);
+            }
+        }
+        events
+    }
+
+    fn push_frame(&mut self, frame: MfccFrame) {
+        if frame.energy_db >= SPEECH_FLOOR_DB {
+            self.last_loud = Some(self.position);
+            for (m, c) in self.mean.iter_mut().zip(&frame.cepstra) {
+                *m += (c - *m) * MEAN_ADAPTATION;
+            }
+        }
+        let normalized: Vec<f32> = frame
+            .cepstra
+            .iter()
+            .zip(&self.mean)
+            .map(|(c, m)| c - m)
+            .collect();
+
+        let position = self.position;
+        for (k, keyword) in self.keywords.iter_mut().enumerate() {
+            for matcher in keyword.matchers.iter_mut() {
+                let (confidence, start) = match matcher.step(&normalized, position) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                let usable = confidence >= keyword.threshold
+                    && start >= self.reported_until[k]
+                    && self.last_loud.is_some_and(|loud| loud >= start);
+                let better = self.candidates[k]
+                    .as_ref()
+                    .is_none_or(|c| confidence > c.confidence);
+                if usable && better {
+                    self.candidates[k] = Some(Detection {
+                        confidence,
+                        start,
+                        end: position,
+                    });
+                }
+            }
+        }
+        self.position += 1;
+    }
+
+    fn decide(&mut self, events: &mut Vec<KeywordEvent>) {
+        for (k, keyword) in self.keywords.iter().enumerate() {
+            let found = self.candidates[k]
+                .take()
+                .filter(|d| d.start >= self.reported_until[k]);
+
+            let improved = match (&found, &self.pending[k]) {
+                (Some(found), Some(pending)) => found.confidence > pending.confidence,
+                (Some(_), None) => true,
+                _ => false,
+            };
+            if improved {
+                self.pending[k] = found;
+            } else if let Some(detection) = self.pending[k].take() {
+                self.reported_until[k] = detection.end + 1;
+                events.push(KeywordEvent {
+                    phrase: keyword.phrase.clone(),
+                    confidence: detection.confidence as f64,
+                    start_ms: frame_start_ms(detection.start),
+                    end_ms: frame_start_ms(detection.end)
+                        + WINDOW_SAMPLES as f64 * 1000.0 / SAMPLE_RATE as f64,
+                });
+            }
+        }
+    }
+}
+
+impl Default for KeywordSpotter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn frame_start_ms(frame: u64) -> f64 {
+    (frame * HOP_SAMPLES as u64) as f64 * 1000.0 / SAMPLE_RATE as f64
+}
+
+/// A keyword consumer of a capture stream: resamples the mono mix to 16 kHz
+/// and reports each spotted phrase as a `KeywordEvent`.
+pub struct KeywordSubscriber {
+    id: u32,
+    resampler: Option<(u32, StreamingResampler)>,
+    drift_ppm: f64,
+    spotter: KeywordSpotter,
+    tsfn: ThreadsafeFunction<KeywordEvent, ErrorStrategy::Fatal>,
+}
+
+impl KeywordSubscriber {
+    pub fn new(id: u32, spotter: KeywordSpotter, callback: &JsFunction) -> napi::Result<Self> {
+        let tsfn: ThreadsafeFunction<KeywordEvent, ErrorStrategy::Fatal> =
+            callback.create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))?;
+
+        Ok(Self {
+            id,
+            resampler: None,
+            drift_ppm: 0.0,
+            spotter,
+            tsfn,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn spotter_mut(&mut self) -> &mut KeywordSpotter {
+        &mut self.spotter
+    }
+
+    pub fn set_drift_ppm(&mut self, ppm: f64) {
+        self.drift_ppm = ppm;
+        if let Some((_, ref mut resampler)) = self.resampler {
+            resampler.set_drift_ppm(ppm);
+        }
+    }
+
+    /// Forgets the audio of a previous capture run.
+    pub fn reset(&mut self) {
+        self.resampler = None;
+        self.spotter.reset();
+    }
+
+    pub fn push(&mut self, mono: &[f32], input_sample_rate: u32) {
+        if mono.is_empty() {
+            return;
+        }
+
+        let resampler = match self.resampler {
+            Some((rate, ref mut resampler)) if rate == input_sample_rate => resampler,
+            _ => {
+                let mut resampler =
+                    StreamingResampler::new(input_sample_rate as f64, SAMPLE_RATE as f64);
+                resampler.set_drift_ppm(self.drift_ppm);
+                &mut self.resampler.insert((input_sample_rate, resampler)).1
+            }
+        };
+
+        for event in self.spotter.push(&resampler.process(mono)) {
+            self.tsfn
+                .call(event, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const MS: usize = SAMPLE_RATE as usize / 1000;
+
+    /// Voiced segments of 150 ms, one formant (Hz) each: a stand-in for a
+    /// spoken phrase.
+    fn phrase(formants: &[f32]) -> Vec<f32> {
+        let mut samples = Vec::new();
+        for &formant in formants {
+            samples.extend((0..150 * MS).map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                (1..=30)
+                    .map(|k| {
+                        let f = 130.0 * k as f32;
+                        0.05 / (1.0 + ((f - formant) / 250.0).powi(2)) * (2.0 * PI * f * t).sin()
+                    })
+                    .sum::<f32>()
+            }));
+        }
+        samples
+    }
+
+    fn spotter(formants: &[f32]) -> KeywordSpotter {
+        let template = template(&Mfcc::new().frames(&phrase(formants)));
+        let mut spotter = KeywordSpotter::new();
+        spotter.set_keywords(KeywordSet(vec![Keyword::new(
+            "nyx".to_string(),
+            DEFAULT_THRESHOLD as f32,
+            vec![template],
+        )]));
+        spotter
+    }
+
+    fn frame(values: &[f32]) -> Vec<f32> {
+        let mut frame = vec![0.0; CEPSTRA];
+        frame[..values.len()].copy_from_slice(values);
+        frame
+    }
+
+    #[test]
+    fn dtw_finds_the_template_in_the_stream() {
+        let template = vec![frame(&[1.0]), frame(&[0.0, 1.0]), frame(&[0.0, 0.0, 1.0])];
+        let mut matcher = Matcher::new(template.clone());
+        let noise = frame(&[-1.0, -1.0, -1.0, 1.0]);
+
+        let mut stream = vec![noise.clone(); 4];
+        stream.extend(template);
+        let found: Vec<_> = stream
+            .iter()
+            .enumerate()
+            .map(|(i, f)| matcher.step(f, i as u64))
+            .collect();
+        let (score, start) = found[6].unwrap();
+        assert!((score - 1.0).abs() < 1e-6);
+        assert_eq!(start, 4);
+        assert!(found[..6].iter().flatten().all(|m| m.0 < 0.9));
+    }
+
+    #[test]
+    fn dtw_allows_warping_up_to_the_limit() {
+        let template: Vec<Vec<f32>> = (0..4).map(|i| frame(&[i as f32, 1.0])).collect();
+        let stretched = |factor: usize| -> Vec<Vec<f32>> {
+            template
+                .iter()
+                .flat_map(|f| std::iter::repeat_n(f.clone(), factor))
+                .collect()
+        };
+        let last = |stream: Vec<Vec<f32>>| {
+            let mut matcher = Matcher::new(template.clone());
+            let end = stream.len() as u64 - 1;
+            (0..=end)
+                .map(|i| matcher.step(&stream[i as usize], 100 + i))
+                .last()
+                .flatten()
+        };
+
+        // Twice as long: a perfect match from the last repeat of the first
+        // frame, where every path through the top row starts.
+        let (score, start) = last(stretched(2)).unwrap();
+        assert!((score - 1.0).abs() < 1e-6);
+        assert_eq!(start, 101);
+        assert!(last(stretched(3)).is_none());
+    }
+
+    #[test]
+    fn detects_the_phrase_once_where_it_was_said() {
+        let mut spotter = spotter(&[600.0, 1_800.0, 900.0, 2_400.0]);
+        let mut audio = vec![0.0; 500 * MS];
+        audio.extend(phrase(&[600.0, 1_800.0, 900.0, 2_400.0]));
+        audio.extend(vec![0.0; 500 * MS]);
+
+        let events: Vec<KeywordEvent> = audio
+            .chunks(10 * MS)
+            .flat_map(|c| spotter.push(c))
+            .collect();
+        assert_eq!(
+            events.len(),
+            1,
+            "{:?}",
+            events.iter().map(|e| e.confidence).collect::<Vec<_>>()
+        );
+        assert_eq!(events[0].phrase, "nyx");
+        assert!(
+            (events[0].start_ms - 500.0).abs() < 30.0,
+            "{}",
+            events[0].start_ms
+        );
+        assert!(
+            (events[0].end_ms - 1_100.0).abs() < 30.0,
+            "{}",
+            events[0].end_ms
+        );
+    }
+
+    #[test]
+    fn reset_does_not_join_a_phrase_across_runs() {
+        let mut spotter = spotter(&[600.0, 1_800.0, 900.0, 2_400.0]);
+        let detections = |audio: Vec<f32>, spotter: &mut KeywordSpotter| {
+            audio.chunks(10 * MS).flat_map(|c| spotter.push(c)).count()
+        };
+
+        let mut audio = vec![0.0; 500 * MS];
+        audio.extend(phrase(&[600.0, 1_800.0]));
+        assert_eq!(detections(audio, &mut spotter), 0);
+        spotter.reset();
+        let mut audio = phrase(&[900.0, 2_400.0]);
+        audio.extend(vec![0.0; 500 * MS]);
+        assert_eq!(detections(audio, &mut spotter), 0);
+
+        spotter.reset();
+        let mut audio = phrase(&[600.0, 1_800.0, 900.0, 2_400.0]);
+        audio.extend(vec![0.0; 500 * MS]);
+        assert_eq!(detections(audio, &mut spotter), 1);
+    }
+
+    #[test]
+    fn ignores_other_phrases_and_silence() {
+        let mut spotter = spotter(&[600.0, 1_800.0, 900.0, 2_400.0]);
+        let mut audio = vec![0.0; 500 * MS];
+        audio.extend(phrase(&[2_400.0, 900.0, 1_800.0, 600.0]));
+        audio.extend(phrase(&[1_200.0, 1_200.0, 3_000.0, 300.0]));
+        audio.extend(vec![0.0; 500 * MS]);
+
+        let events: Vec<KeywordEvent> = audio
+            .chunks(10 * MS)
+            .flat_map(|c| spotter.push(c))
+            .collect();
+        assert!(
+            events.is_empty(),
+            "{:?}",
+            events.iter().map(|e| e.confidence).collect::<Vec<_>>()
+        );
+    }
+}
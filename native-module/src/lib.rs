@@ -5,118 +5,775 @@ extern crate napi_derive;
 use napi::bindgen_prelude::*;
 use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+pub mod agc;
 pub mod audio_config;
+pub mod conversion;
+pub mod decoder;
+pub mod delivery;
+pub mod denoise;
+pub mod diarization;
+pub mod downmix;
+pub mod drift;
+pub mod file_processing;
+pub mod filters;
+pub mod framing;
+pub mod gap_events;
+pub mod history;
+pub mod keywords;
+pub mod loudness;
+pub mod metering;
+pub mod mfcc;
 pub mod microphone;
+pub mod pacer;
+pub mod pipeline;
 pub mod resampler;
+pub mod sample_format;
 pub mod silence_suppression;
 pub mod speaker;
+pub mod stats;
 pub mod streaming_resampler;
+pub mod subscriber;
 pub mod system_audio;
+pub mod transcription;
+pub mod utterance;
 pub mod vad;
+pub mod wav;
+pub mod websocket;
 
-use audio_config::{DSP_POLL_MS, FRAME_SAMPLES};
-use silence_suppression::{
-    generate_silence_frame, FrameAction, SilenceSuppressionConfig, SilenceSuppressor,
-};
-use streaming_resampler::StreamingResampler;
+use audio_config::DSP_POLL_MS;
+use delivery::DeliveryStats;
+use downmix::{ChannelLayout, DownmixMode, Downmixer};
+use drift::DriftEstimator;
+use file_processing::{FileProcessing, FileProcessingOptions};
+use gap_events::GapSubscriber;
+use history::{AudioHistory, HistoryConfig, HistoryOptions, RecentAudio};
+use keywords::{KeywordDefinition, KeywordSet, KeywordSpotter, KeywordSubscriber};
+use loudness::LoudnessMeter;
+use metering::{LevelOptions, LevelSubscriber, MeterConfig};
+use pipeline::PipelineStats;
+use stats::CaptureStats;
+use subscriber::{Output, Subscriber, SubscriberConfig, SubscriberOptions};
+use system_audio::{AudioDevice, StreamWaker};
+use transcription::{TranscriptionConfig, TranscriptionOptions, TranscriptionSubscriber};
+use utterance::{SegmenterConfig, UtteranceOptions, UtteranceSubscriber};
+use websocket::{WebSocketConfig, WebSocketEvent, WebSocketOptions, WebSocketSink};
+
+/// Everything fed from one device stream, shared with the capture thread.
+#[derive(Default)]
+struct Consumers {
+    subscribers: Vec<Subscriber>,
+    meters: Vec<LevelSubscriber>,
+    transcribers: Vec<TranscriptionSubscriber>,
+    utterances: Vec<UtteranceSubscriber>,
+    keyword_spotters: Vec<KeywordSubscriber>,
+    gap_listeners: Vec<GapSubscriber>,
+    history: Option<AudioHistory>,
+}
+
+impl Consumers {
+    fn len(&self) -> usize {
+        self.subscribers.len()
+            + self.meters.len()
+            + self.transcribers.len()
+            + self.utterances.len()
+            + self.keyword_spotters.len()
+            + self.gap_listeners.len()
+    }
+
+    /// Clears per-run state ahead of a new capture run. Gap listeners keep
+    /// none; the history keeps its audio.
+    fn reset(&mut self) {
+        for subscriber in self.subscribers.iter_mut() {
+            subscriber.reset();
+        }
+        for meter in self.meters.iter_mut() {
+            meter.reset();
+        }
+        for transcriber in self.transcribers.iter_mut() {
+            transcriber.reset();
+        }
+        for segmenter in self.utterances.iter_mut() {
+            segmenter.reset();
+        }
+        for spotter in self.keyword_spotters.iter_mut() {
+            spotter.reset();
+        }
+        if let Some(ref mut history) = self.history {
+            history.reset();
+        }
+    }
+
+    fn remove(&mut self, id: u32) -> bool {
+        let before = self.len();
+        self.subscribers.retain(|s| s.id() != id);
+        self.meters.retain(|m| m.id() != id);
+        self.transcribers.retain(|t| t.id() != id);
+        self.utterances.retain(|u| u.id() != id);
+        self.keyword_spotters.retain(|k| k.id() != id);
+        self.gap_listeners.retain(|g| g.id() != id);
+        self.len() != before
+    }
+}
+
+type SharedConsumers = Arc<Mutex<Consumers>>;
+type SharedStats = Arc<Mutex<CaptureStats>>;
+
+/// Longest stretch of silence synthesized at once; a longer device gap is
+/// still reported in full but shortens the timeline.
+const MAX_GAP_FILL_FRAMES: u64 = 192_000 * 10;
+/// Gap fill is handed to consumers in blocks of this much audio, so a long
+/// gap needs no allocation of its own size.
+const GAP_FILL_BLOCK_MS: u32 = 20;
+
+/// How long `stop` waits for the capture thread unless told otherwise.
+const DEFAULT_STOP_TIMEOUT_MS: u32 = 2_000;
+
+type SharedWaker = Arc<Mutex<Option<StreamWaker>>>;
+type AbandonedThread = Arc<Mutex<Option<thread::JoinHandle<()>>>>;
+
+struct CaptureThread {
+    handle: thread::JoinHandle<()>,
+    /// Disconnects when the thread exits, so its end can be awaited with a
+    /// timeout.
+    exited: mpsc::Receiver<()>,
+}
 
 #[napi]
 pub struct SystemAudioCapture {
+    device_id: Option<String>,
     stop_signal: Arc<AtomicBool>,
-    capture_thread: Option<thread::JoinHandle<()>>,
+    consumers: SharedConsumers,
+    stats: SharedStats,
+    next_subscriber_id: u32,
+    start_subscriber: Option<u32>,
+    /// Set by the capture thread once its stream exists, to cut a pending
+    /// device wait short on `stop`.
+    waker: SharedWaker,
+    capture_thread: Option<CaptureThread>,
+    /// A `stop` is still waiting for the previous capture thread.
+    stopping: Arc<AtomicBool>,
+    /// A thread left behind by a timed-out `stop`. It still feeds and
+    /// finishes the shared consumers, so no new run starts before it exits.
+    abandoned: AbandonedThread,
 }
 
 #[napi]
 impl SystemAudioCapture {
     #[napi(constructor)]
     pub fn new(device_id: Option<String>) -> napi::Result<Self> {
-        let _ = device_id;
         Ok(Self {
+            device_id,
             stop_signal: Arc::new(AtomicBool::new(false)),
+            consumers: Arc::new(Mutex::new(Consumers::default())),
+            stats: Arc::new(Mutex::new(CaptureStats::default())),
+            next_subscriber_id: 1,
+            start_subscriber: None,
+            waker: Arc::new(Mutex::new(None)),
             capture_thread: None,
+            stopping: Arc::new(AtomicBool::new(false)),
+            abandoned: Arc::new(Mutex::new(None)),
         })
     }
 
+    /// Registers an additional consumer with its own output format. Returns
+    /// an id for `unsubscribe`. Subscribers can be added while capturing.
+    #[napi]
+    pub fn subscribe(
+        &mut self,
+        env: Env,
+        options: Option<SubscriberOptions>,
+        callback: JsFunction,
+    ) -> napi::Result<u32> {
+        let config = SubscriberConfig::from_options(options)?;
+        let id = self.next_subscriber_id;
+        let output = Output::callback(env, &callback, &config)?;
+        let subscriber = Subscriber::new(id, config, output);
+
+        self.lock_consumers()?.subscribers.push(subscriber);
+        self.next_subscriber_id += 1;
+        Ok(id)
+    }
+
+    /// Streams frames in the given format straight to a WebSocket server
+    /// from native code, one message per chunk, reconnecting with
+    /// backoff. The callback receives only `WebSocketEvent` control events.
+    /// Closed with `unsubscribe`.
+    #[napi]
+    pub fn subscribe_web_socket(
+        &mut self,
+        socket: WebSocketOptions,
+        options: Option<SubscriberOptions>,
+        callback: JsFunction,
+    ) -> napi::Result<u32> {
+        let config = SubscriberConfig::from_options(options)?;
+        let socket = WebSocketConfig::from_options(socket, config.chunk_ms())?;
+        let tsfn: ThreadsafeFunction<WebSocketEvent, ErrorStrategy::Fatal> =
+            callback.create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))?;
+        let sink = WebSocketSink::new(
+            socket,
+            Box::new(move |event| {
+                tsfn.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+            }),
+        );
+
+        let id = self.next_subscriber_id;
+        let subscriber = Subscriber::new(id, config, Output::WebSocket(sink));
+        self.lock_consumers()?.subscribers.push(subscriber);
+        self.next_subscriber_id += 1;
+        Ok(id)
+    }
+
+    /// Registers a level meter delivering peak/RMS/dBFS readings (and
+    /// optional waveform and spectrum summaries) at a low, fixed rate, so
+    /// the UI never handles raw PCM. Removed with `unsubscribe`.
+    #[napi]
+    pub fn subscribe_levels(
+        &mut self,
+        options: Option<LevelOptions>,
+        callback: JsFunction,
+    ) -> napi::Result<u32> {
+        let config = MeterConfig::from_options(options)?;
+        let id = self.next_subscriber_id;
+        let meter = LevelSubscriber::new(id, config, &callback)?;
+
+        self.lock_consumers()?.meters.push(meter);
+        self.next_subscriber_id += 1;
+        Ok(id)
+    }
+
+    /// Transcribes the captured audio on-device with a Whisper model,
+    /// delivering partial and final `TranscriptEvent`s. Requires the
+    /// `transcription` build feature; removed with `unsubscribe`.
+    #[napi]
+    pub fn subscribe_transcript(
+        &mut self,
+        options: TranscriptionOptions,
+        callback: JsFunction,
+    ) -> napi::Result<u32> {
+        let config = TranscriptionConfig::from_options(options)?;
+        let id = self.next_subscriber_id;
+        let transcriber = TranscriptionSubscriber::new(id, config, &callback)?;
+
+        self.lock_consumers()?.transcribers.push(transcriber);
+        self.next_subscriber_id += 1;
+        Ok(id)
+    }
+
+    /// Reports `speechStart`/`speechEnd` events for the captured audio,
+    /// optionally with each complete utterance as one 16 kHz i16 buffer.
+    /// Removed with `unsubscribe`.
+    #[napi]
+    pub fn subscribe_utterances(
+        &mut self,
+        options: Option<UtteranceOptions>,
+        callback: JsFunction,
+    ) -> napi::Result<u32> {
+        let config = SegmenterConfig::from_options(options)?;
+        let id = self.next_subscriber_id;
+        let segmenter = UtteranceSubscriber::new(id, config, &callback)?;
+
+        self.lock_consumers()?.utterances.push(segmenter);
+        self.next_subscriber_id += 1;
+        Ok(id)
+    }
+
+    /// Spots the given phrases in the captured audio by matching against
+    /// their example recordings, reporting each as a `KeywordEvent`. The
+    /// list can be replaced later with `setKeywords`. Removed with
+    /// `unsubscribe`.
+    #[napi]
+    pub fn subscribe_keywords(
+        &mut self,
+        keywords: Vec<KeywordDefinition>,
+        callback: JsFunction,
+    ) -> napi::Result<u32> {
+        let mut spotter = KeywordSpotter::new();
+        spotter.set_keywords(KeywordSet::from_definitions(keywords)?);
+        let id = self.next_subscriber_id;
+        let subscriber = KeywordSubscriber::new(id, spotter, &callback)?;
+
+        self.lock_consumers()?.keyword_spotters.push(subscriber);
+        self.next_subscriber_id += 1;
+        Ok(id)
+    }
+
+    /// Replaces the phrases of a keyword subscription while capture runs.
+    /// Returns false if `id` is not a keyword subscription.
+    #[napi]
+    pub fn set_keywords(&self, id: u32, keywords: Vec<KeywordDefinition>) -> napi::Result<bool> {
+        let keywords = KeywordSet::from_definitions(keywords)?;
+        let mut consumers = self.lock_consumers()?;
+        match consumers.keyword_spotters.iter_mut().find(|k| k.id() == id) {
+            Some(subscriber) => {
+                subscriber.spotter_mut().set_keywords(keywords);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Reports each stretch the device delivered no audio for (loopback
+    /// pauses while nothing plays) as a `GapEvent`, once audio resumes.
+    /// Subscribers fill such gaps with silence unless `fillGaps` is false.
+    #[napi]
+    pub fn subscribe_gaps(&mut self, callback: JsFunction) -> napi::Result<u32> {
+        let id = self.next_subscriber_id;
+        let listener = GapSubscriber::new(id, &callback)?;
+
+        self.lock_consumers()?.gap_listeners.push(listener);
+        self.next_subscriber_id += 1;
+        Ok(id)
+    }
+
+    #[napi]
+    pub fn unsubscribe(&mut self, id: u32) -> bool {
+        match self.consumers.lock() {
+            Ok(mut consumers) => consumers.remove(id),
+            Err(_) => false,
+        }
+    }
+
+    /// Starts the device stream. A callback passed here is registered as a
+    /// subscriber with `options` (default 16 kHz i16 with silence
+    /// suppression) and removed again on `stop`.
     #[napi]
-    pub fn start(&mut self, callback: JsFunction) -> napi::Result<()> {
-        let tsfn: ThreadsafeFunction<Vec<i16>, ErrorStrategy::Fatal> = callback
-            .create_threadsafe_function(0, |ctx| {
-                let vec: Vec<i16> = ctx.value;
-                let mut pcm_bytes = Vec::with_capacity(vec.len() * 2);
-                for sample in vec {
-                    pcm_bytes.extend_from_slice(&sample.to_le_bytes());
+    pub fn start(
+        &mut self,
+        env: Env,
+        callback: Option<JsFunction>,
+        options: Option<SubscriberOptions>,
+    ) -> napi::Result<()> {
+        if self.capture_thread.is_some() {
+            return Err(napi::Error::from_reason("Capture already running"));
+        }
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err(napi::Error::from_reason(
+                "Capture is still stopping; await stop() first",
+            ));
+        }
+        if let Ok(mut abandoned) = self.abandoned.lock() {
+            match abandoned.take() {
+                Some(handle) if handle.is_finished() => {
+                    let _ = handle.join();
+                }
+                Some(handle) => {
+                    *abandoned = Some(handle);
+                    return Err(napi::Error::from_reason(
+                        "The previous capture thread has not exited yet",
+                    ));
                 }
-                Ok(vec![pcm_bytes])
-            })?;
+                None => {}
+            }
+        }
 
-        self.stop_signal.store(false, Ordering::SeqCst);
+        if let Some(callback) = callback {
+            self.start_subscriber = Some(self.subscribe(env, options, callback)?);
+        }
+
+        // Fresh per run: a thread abandoned by a timed-out stop keeps its
+        // own flag set and its own waker.
+        self.stop_signal = Arc::new(AtomicBool::new(false));
+        self.waker = Arc::new(Mutex::new(None));
         let stop_signal = self.stop_signal.clone();
+        let waker = self.waker.clone();
+        let device_id = self.device_id.clone();
+        let consumers = self.consumers.clone();
+        if let Ok(mut consumers) = consumers.lock() {
+            consumers.reset();
+        }
+        let stats = self.stats.clone();
+        if let Ok(mut s) = stats.lock() {
+            *s = CaptureStats::default();
+        }
 
-        self.capture_thread = Some(thread::spawn(move || {
-            if let Err(e) = run_capture_loop(stop_signal, tsfn) {
+        let (exited_tx, exited) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let _exited = exited_tx;
+            if let Err(e) = run_capture_loop(device_id, stop_signal, waker, consumers, stats) {
                 eprintln!("Capture loop error: {:?}", e);
             }
-        }));
+        });
+        self.capture_thread = Some(CaptureThread { handle, exited });
 
         Ok(())
     }
 
+    /// Stops the device stream without blocking the JS thread. Audio the
+    /// consumers still buffer (the partial frame, paced frames, the chunk
+    /// being cut, an open utterance) is delivered before the thread exits.
+    /// Resolves once it has, or after `timeoutMs` (default 2000) with
+    /// `timedOut` set if the device does not let go; the thread is then
+    /// abandoned. `start` fails until the promise resolves and, after a
+    /// timeout, until the abandoned thread has exited.
     #[napi]
-    pub fn stop(&mut self) {
+    pub fn stop(&mut self, timeout_ms: Option<u32>) -> AsyncTask<StopCapture> {
         self.stop_signal.store(true, Ordering::SeqCst);
-        if let Some(h) = self.capture_thread.take() {
-            let _ = h.join();
+        if let Ok(waker) = self.waker.lock() {
+            if let Some(waker) = waker.as_ref() {
+                waker.wake();
+            }
+        }
+
+        let thread = self.capture_thread.take();
+        if thread.is_some() {
+            self.stopping.store(true, Ordering::SeqCst);
         }
+        AsyncTask::new(StopCapture {
+            thread,
+            timeout: Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_STOP_TIMEOUT_MS) as u64),
+            start_subscriber: self.start_subscriber.take(),
+            consumers: self.consumers.clone(),
+            stopping: self.stopping.clone(),
+            abandoned: self.abandoned.clone(),
+        })
+    }
+
+    /// Keeps a rolling history of the captured audio (16 kHz mono, and
+    /// optionally the raw device stream) for `getRecent`/`exportRecent`.
+    /// Calling it again replaces the history, discarding what was kept.
+    #[napi]
+    pub fn enable_history(&self, options: Option<HistoryOptions>) -> napi::Result<()> {
+        let config = HistoryConfig::from_options(options)?;
+        self.lock_consumers()?.history = Some(AudioHistory::new(config));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn disable_history(&self) -> napi::Result<()> {
+        self.lock_consumers()?.history = None;
+        Ok(())
+    }
+
+    /// The last `seconds` of history (less if not that much is kept), as
+    /// 16 kHz i16 mono or, with `raw`, the device's own f32 audio.
+    #[napi]
+    pub fn get_recent(&self, seconds: f64, raw: Option<bool>) -> napi::Result<RecentAudio> {
+        let snapshot = self.history_snapshot(seconds, raw.unwrap_or(false))?;
+        Ok(snapshot.into_recent_audio())
+    }
+
+    /// Saves the last `seconds` of history as a WAV file with its loudness
+    /// in a broadcast-wave `bext` chunk. Returns the duration written in ms.
+    #[napi]
+    pub fn export_recent(
+        &self,
+        seconds: f64,
+        path: String,
+        raw: Option<bool>,
+    ) -> napi::Result<f64> {
+        let snapshot = self.history_snapshot(seconds, raw.unwrap_or(false))?;
+        snapshot
+            .write_wav(std::path::Path::new(&path))
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        Ok(snapshot.duration_ms())
+    }
+
+    /// Per-stage frame counts, timing and latency of a subscriber's
+    /// processing pipeline; null if `id` is not a frame subscriber.
+    #[napi]
+    pub fn get_pipeline_stats(&self, id: u32) -> napi::Result<Option<PipelineStats>> {
+        let consumers = self.lock_consumers()?;
+        Ok(consumers
+            .subscribers
+            .iter()
+            .find(|s| s.id() == id)
+            .map(Subscriber::pipeline_stats))
+    }
+
+    /// Queue depth, dropped audio and callback latency of a subscriber's
+    /// delivery to JS; null if `id` is not a callback subscriber.
+    #[napi]
+    pub fn get_delivery_stats(&self, id: u32) -> napi::Result<Option<DeliveryStats>> {
+        let consumers = self.lock_consumers()?;
+        Ok(consumers
+            .subscribers
+            .iter()
+            .find(|s| s.id() == id)
+            .and_then(Subscriber::delivery_stats))
+    }
+
+    /// Loudness of the captured stream since the last `start` (EBU R128).
+    #[napi]
+    pub fn get_stats(&self) -> CaptureStats {
+        self.stats.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+
+    /// Takes shared handles to the history under the consumer lock; the
+    /// audio is copied, encoded and written after it is released, without
+    /// holding up the capture thread.
+    fn history_snapshot(&self, seconds: f64, raw: bool) -> napi::Result<history::HistorySnapshot> {
+        match self.lock_consumers()?.history {
+            Some(ref history) => history.snapshot(seconds, raw),
+            None => Err(napi::Error::from_reason(
+                "History is not enabled; call enableHistory first",
+            )),
+        }
+    }
+
+    fn lock_consumers(&self) -> napi::Result<std::sync::MutexGuard<'_, Consumers>> {
+        self.consumers
+            .lock()
+            .map_err(|_| napi::Error::from_reason("Consumer list poisoned"))
+    }
+}
+
+/// Devices (and, where supported, individual applications) whose output
+/// can be captured. Each entry's `id` can be passed to `SystemAudioCapture`.
+#[napi]
+pub fn list_audio_devices() -> napi::Result<Vec<AudioDevice>> {
+    system_audio::list_devices().map_err(|e| napi::Error::from_reason(e.to_string()))
+}
+
+/// Decodes an audio file (WAV, MP3, M4A/AAC, FLAC, Ogg Vorbis) and runs it
+/// through the capture pipeline on a worker thread, faster than real time.
+/// Frames, utterances and progress arrive as `FileEvent`s; the promise
+/// resolves with a summary once the whole file has been processed.
+#[napi]
+pub fn process_file(
+    path: String,
+    options: Option<FileProcessingOptions>,
+    callback: JsFunction,
+) -> napi::Result<AsyncTask<FileProcessing>> {
+    Ok(AsyncTask::new(FileProcessing::new(
+        path, options, &callback,
+    )?))
+}
+
+#[napi(object)]
+pub struct StopResult {
+    /// The capture thread did not exit within the timeout and was left
+    /// behind.
+    pub timed_out: bool,
+    pub elapsed_ms: f64,
+}
+
+/// Waits for the capture thread off the JS thread, then drops the
+/// subscriber registered by `start`.
+pub struct StopCapture {
+    thread: Option<CaptureThread>,
+    timeout: Duration,
+    start_subscriber: Option<u32>,
+    consumers: SharedConsumers,
+    stopping: Arc<AtomicBool>,
+    abandoned: AbandonedThread,
+}
+
+impl Task for StopCapture {
+    type Output = StopResult;
+    type JsValue = StopResult;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        let started = Instant::now();
+        let mut timed_out = false;
+        if let Some(thread) = self.thread.take() {
+            match thread.exited.recv_timeout(self.timeout) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    timed_out = true;
+                    if let Ok(mut abandoned) = self.abandoned.lock() {
+                        *abandoned = Some(thread.handle);
+                    }
+                }
+                _ => {
+                    let _ = thread.handle.join();
+                }
+            }
+        }
+
+        if let Some(id) = self.start_subscriber.take() {
+            if let Ok(mut consumers) = self.consumers.lock() {
+                consumers.remove(id);
+            }
+        }
+        self.stopping.store(false, Ordering::SeqCst);
+
+        Ok(StopResult {
+            timed_out,
+            elapsed_ms: started.elapsed().as_secs_f64() * 1000.0,
+        })
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output)
     }
 }
 
 fn run_capture_loop(
+    device_id: Option<String>,
     stop_signal: Arc<AtomicBool>,
-    tsfn: ThreadsafeFunction<Vec<i16>, ErrorStrategy::Fatal>,
+    waker: SharedWaker,
+    consumers: SharedConsumers,
+    stats: SharedStats,
 ) -> napi::Result<()> {
-    let mut input = system_audio::SystemAudioStream::new(None)
+    let mut input = system_audio::SystemAudioStream::new(device_id)
         .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+    if let Ok(mut slot) = waker.lock() {
+        *slot = Some(input.waker());
+    }
 
-    let input_sample_rate = input.sample_rate() as f64;
+    let input_sample_rate = input.sample_rate();
+    let layout = input.channel_layout();
+    let channels = layout.channels().max(1);
+    // Level meters and most consumers work on a plain mono mix.
+    let mut mono_mix =
+        Downmixer::new(DownmixMode::Average, layout.clone()).map_err(napi::Error::from_reason)?;
 
     input
         .play()
         .map_err(|e| napi::Error::from_reason(e.to_string()))?;
 
-    let mut resampler = StreamingResampler::new(input_sample_rate, 16000.0);
-    let mut frame_buffer: Vec<i16> = Vec::with_capacity(FRAME_SAMPLES * 4);
-    let mut suppressor = SilenceSuppressor::new(SilenceSuppressionConfig::default());
+    let mut loudness = LoudnessMeter::new(input_sample_rate, &layout);
+    let mut drift = DriftEstimator::new(input_sample_rate);
+    // Frames on the capture timeline (delivered plus synthesized), and the
+    // gap currently being filled as (start frame, length).
+    let mut timeline_frames: u64 = 0;
+    let mut open_gap: Option<(u64, u64)> = None;
+    let fill_block_frames = (input_sample_rate * GAP_FILL_BLOCK_MS / 1000).max(1) as usize;
+    let silence = vec![0.0f32; fill_block_frames * channels];
+    let silent_mono = vec![0.0f32; fill_block_frames];
 
     while !stop_signal.load(Ordering::Relaxed) {
-        let samples = input.poll_audio();
+        let packet = input.poll_audio();
+        let mono = mono_mix.process(&packet.samples);
+
+        let fill_frames = packet.gap_frames.min(MAX_GAP_FILL_FRAMES) as usize;
 
-        if !samples.is_empty() {
-            let resampled = resampler.resample(&samples);
-            frame_buffer.extend(resampled);
+        if packet.gap_frames > 0 {
+            let gap = open_gap.get_or_insert((timeline_frames, 0));
+            gap.1 += packet.gap_frames;
+            timeline_frames += packet.gap_frames;
         }
+        let finished_gap = if mono.is_empty() {
+            None
+        } else {
+            open_gap.take()
+        };
+        let delivered_frames = (packet.samples.len() / channels) as u64;
+        timeline_frames += delivered_frames;
 
-        while frame_buffer.len() >= FRAME_SAMPLES {
-            let frame: Vec<i16> = frame_buffer.drain(0..FRAME_SAMPLES).collect();
-            match suppressor.process(&frame) {
-                FrameAction::Send(audio) => {
-                    tsfn.call(audio, ThreadsafeFunctionCallMode::NonBlocking);
+        // Synthesized frames say nothing about the device clock.
+        if packet.gap_frames > 0 {
+            drift.reset();
+        }
+        let drift_update = if delivered_frames > 0 && drift.push(delivered_frames, Instant::now()) {
+            drift.drift_ppm()
+        } else {
+            None
+        };
+
+        if let Some(ppm) = drift_update {
+            if let Ok(mut stats) = stats.lock() {
+                stats.drift_ppm = Some(ppm);
+            }
+        }
+
+        if fill_frames > 0 || !mono.is_empty() {
+            for block in silence_blocks(&silence, fill_frames, channels) {
+                loudness.push(block);
+            }
+            loudness.push(&packet.samples);
+            if let Ok(mut stats) = stats.lock() {
+                stats.set_loudness(loudness.summary());
+                if let Some((_, frames)) = finished_gap {
+                    stats.add_gap(frames_to_ms(frames, input_sample_rate));
+                }
+            }
+        }
+
+        if let Ok(mut consumers) = consumers.lock() {
+            if let Some(ppm) = drift_update {
+                for subscriber in consumers.subscribers.iter_mut() {
+                    subscriber.set_drift_ppm(ppm);
+                }
+                for transcriber in consumers.transcribers.iter_mut() {
+                    transcriber.set_drift_ppm(ppm);
+                }
+                for segmenter in consumers.utterances.iter_mut() {
+                    segmenter.set_drift_ppm(ppm);
                 }
-                FrameAction::SendSilence => {
-                    tsfn.call(
-                        generate_silence_frame(FRAME_SAMPLES),
-                        ThreadsafeFunctionCallMode::NonBlocking,
+                for spotter in consumers.keyword_spotters.iter_mut() {
+                    spotter.set_drift_ppm(ppm);
+                }
+                if let Some(ref mut history) = consumers.history {
+                    history.set_drift_ppm(ppm);
+                }
+            }
+
+            if let Some((start, frames)) = finished_gap {
+                for listener in consumers.gap_listeners.iter() {
+                    listener.notify(
+                        frames_to_ms(start, input_sample_rate),
+                        frames_to_ms(frames, input_sample_rate),
                     );
                 }
-                FrameAction::Suppress => {}
+            }
+
+            let fill = silence_blocks(&silence, fill_frames, channels).zip(silence_blocks(
+                &silent_mono,
+                fill_frames,
+                1,
+            ));
+            for (block, mono_block) in fill {
+                feed_consumers(
+                    &mut consumers,
+                    block,
+                    mono_block,
+                    input_sample_rate,
+                    &layout,
+                    true,
+                );
+            }
+            if mono.is_empty() && fill_frames == 0 {
+                for meter in consumers.meters.iter_mut() {
+                    meter.idle();
+                }
+            } else {
+                feed_consumers(
+                    &mut consumers,
+                    &packet.samples,
+                    &mono,
+                    input_sample_rate,
+                    &layout,
+                    false,
+                );
+            }
+
+            let now = Instant::now();
+            for subscriber in consumers.subscribers.iter_mut() {
+                subscriber.tick(now);
             }
         }
 
-        if frame_buffer.len() < FRAME_SAMPLES {
-            thread::sleep(Duration::from_millis(DSP_POLL_MS));
+        thread::sleep(Duration::from_millis(DSP_POLL_MS));
+    }
+
+    if let Some((start, frames)) = open_gap {
+        if let Ok(mut stats) = stats.lock() {
+            stats.add_gap(frames_to_ms(frames, input_sample_rate));
+        }
+        if let Ok(consumers) = consumers.lock() {
+            for listener in consumers.gap_listeners.iter() {
+                listener.notify(
+                    frames_to_ms(start, input_sample_rate),
+                    frames_to_ms(frames, input_sample_rate),
+                );
+            }
+        }
+    }
+
+    // Hand out what is still buffered before the stream goes away.
+    if let Ok(mut consumers) = consumers.lock() {
+        for subscriber in consumers.subscribers.iter_mut() {
+            subscriber.finish();
+        }
+        for segmenter in consumers.utterances.iter_mut() {
+            segmenter.finish();
+        }
+        for transcriber in consumers.transcribers.iter_mut() {
+            transcriber.finish();
         }
     }
 
@@ -124,6 +781,63 @@ fn run_capture_loop(
     Ok(())
 }
 
+/// Hands one block of interleaved device audio (and its mono mix) to every
+/// consumer. `gap_fill` marks silence synthesized for a device gap, which
+/// subscribers may opt out of.
+fn feed_consumers(
+    consumers: &mut Consumers,
+    samples: &[f32],
+    mono: &[f32],
+    input_sample_rate: u32,
+    layout: &ChannelLayout,
+    gap_fill: bool,
+) {
+    if mono.is_empty() {
+        return;
+    }
+
+    consumers.subscribers.retain_mut(|subscriber| {
+        if gap_fill && !subscriber.fills_gaps() {
+            return true;
+        }
+        match subscriber.push(samples, input_sample_rate, layout) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Subscriber {} removed: {}", subscriber.id(), e);
+                false
+            }
+        }
+    });
+    for meter in consumers.meters.iter_mut() {
+        meter.push(mono, input_sample_rate);
+    }
+    for transcriber in consumers.transcribers.iter_mut() {
+        transcriber.push(mono, input_sample_rate);
+    }
+    for segmenter in consumers.utterances.iter_mut() {
+        segmenter.push(mono, input_sample_rate);
+    }
+    for spotter in consumers.keyword_spotters.iter_mut() {
+        spotter.push(mono, input_sample_rate);
+    }
+    if let Some(ref mut history) = consumers.history {
+        history.push(samples, mono, input_sample_rate, layout.channels().max(1));
+    }
+}
+
+/// `frames` frames of silence as slices of `block`, a whole number of
+/// `channels`-wide frames.
+fn silence_blocks(block: &[f32], frames: usize, channels: usize) -> impl Iterator<Item = &[f32]> {
+    let block_frames = block.len() / channels;
+    (0..frames)
+        .step_by(block_frames.max(1))
+        .map(move |first| &block[..(frames - first).min(block_frames) * channels])
+}
+
+fn frames_to_ms(frames: u64, sample_rate: u32) -> f64 {
+    frames as f64 * 1000.0 / sample_rate as f64
+}
+
 #[napi]
 pub struct MicrophoneCapture {}
 
@@ -144,3 +858,43 @@ impl MicrophoneCapture {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscriber(id: u32) -> Subscriber {
+        Subscriber::new(
+            id,
+            SubscriberConfig::default(),
+            Output::Sink(Box::new(|_| {})),
+        )
+    }
+
+    #[test]
+    fn consumers_are_removed_by_id() {
+        let mut consumers = Consumers::default();
+        consumers.subscribers.extend([subscriber(1), subscriber(2)]);
+
+        assert!(consumers.remove(2));
+        assert!(!consumers.remove(2));
+        assert!(!consumers.remove(7));
+        let ids: Vec<u32> = consumers.subscribers.iter().map(Subscriber::id).collect();
+        assert_eq!(ids, [1]);
+
+        consumers
+            .gap_listeners
+            .push(GapSubscriber::with_events(3, Box::new(|_| {})));
+        assert_eq!(consumers.len(), 2);
+        assert!(consumers.remove(3));
+        assert_eq!(consumers.len(), 1);
+    }
+
+    #[test]
+    fn gap_fill_comes_in_whole_blocks() {
+        let block = [0.0; 8];
+        let lengths: Vec<usize> = silence_blocks(&block, 9, 2).map(<[f32]>::len).collect();
+        assert_eq!(lengths, [8, 8, 2]);
+        assert_eq!(silence_blocks(&block, 0, 2).count(), 0);
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use crate::downmix::{ChannelLayout, Speaker};
+use crate::filters::Biquad;
+
+const SUB_BLOCKS_MOMENTARY: usize = 4;
+const SUB_BLOCKS_SHORT_TERM: usize = 30;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+const HISTOGRAM_MAX_LUFS: f64 = 5.0;
+const HISTOGRAM_STEP_LU: f64 = 0.1;
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS_PER_PHASE: usize = 12;
+/// BS.1770 weight of the surround channels, +1.5 dB.
+const SURROUND_WEIGHT: f64 = 1.41;
+
+/// EBU R128 / ITU-R BS.1770-4 loudness of an interleaved stream: momentary
+/// (400 ms), short-term (3 s) and gated integrated loudness in LUFS, plus
+/// true peak from 4x oversampling. Each channel is K-weighted on its own and
+/// the mean squares are summed with the BS.1770 channel weights.
+pub struct LoudnessMeter {
+    channels: Vec<ChannelFilter>,
+    sub_block_samples: usize,
+    sub_block_energy: f64,
+    sub_block_count: usize,
+    recent: VecDeque<f64>,
+    histogram: Vec<(u64, f64)>,
+    /// Loudest momentary and short-term mean squares seen.
+    max_momentary: Option<f64>,
+    max_short_term: Option<f64>,
+}
+
+/// K-weighting and true-peak state of one input channel.
+struct ChannelFilter {
+    shelf: Biquad,
+    high_pass: Biquad,
+    weight: f64,
+    true_peak: TruePeak,
+}
+
+/// Weight of a channel's energy in the loudness sum. LFE is left out.
+fn channel_weight(speaker: Speaker) -> f64 {
+    match speaker {
+        Speaker::LowFrequency => 0.0,
+        Speaker::BackLeft | Speaker::BackRight | Speaker::SideLeft | Speaker::SideRight => {
+            SURROUND_WEIGHT
+        }
+        _ => 1.0,
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct LoudnessSummary {
+    pub momentary_lufs: Option<f64>,
+    pub short_term_lufs: Option<f64>,
+    pub integrated_lufs: Option<f64>,
+    pub true_peak_dbtp: Option<f64>,
+    pub max_momentary_lufs: Option<f64>,
+    pub max_short_term_lufs: Option<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, layout: &ChannelLayout) -> Self {
+        let fs = sample_rate as f64;
+        let histogram_bins =
+            ((HISTOGRAM_MAX_LUFS - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU).ceil() as usize;
+
+        Self {
+            channels: layout
+                .speakers()
+                .iter()
+                .map(|&speaker| ChannelFilter {
+                    shelf: k_weighting_shelf(fs),
+                    high_pass: k_weighting_high_pass(fs),
+                    weight: channel_weight(speaker),
+                    true_peak: TruePeak::new(),
+                })
+                .collect(),
+            sub_block_samples: (sample_rate as usize / 10).max(1),
+            sub_block_energy: 0.0,
+            sub_block_count: 0,
+            recent: VecDeque::with_capacity(SUB_BLOCKS_SHORT_TERM),
+            histogram: vec![(0, 0.0); histogram_bins],
+            max_momentary: None,
+            max_short_term: None,
+        }
+    }
+
+    /// Measures whole interleaved frames in the layout given to `new`.
+    pub fn push(&mut self, interleaved: &[f32]) {
+        if self.channels.is_empty() {
+            return;
+        }
+
+        for frame in interleaved.chunks_exact(self.channels.len()) {
+            for (channel, &sample) in self.channels.iter_mut().zip(frame) {
+                channel.true_peak.push(sample);
+
+                let weighted = channel
+                    .high_pass
+                    .process(channel.shelf.process(sample as f64));
+                self.sub_block_energy += channel.weight * weighted * weighted;
+            }
+            self.sub_block_count += 1;
+
+            if self.sub_block_count == self.sub_block_samples {
+                self.finish_sub_block();
+            }
+        }
+    }
+
+    fn finish_sub_block(&mut self) {
+        let mean_square = self.sub_block_energy / self.sub_block_count as f64;
+        self.sub_block_energy = 0.0;
+        self.sub_block_count = 0;
+
+        if self.recent.len() == SUB_BLOCKS_SHORT_TERM {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(mean_square);
+
+        if let Some(energy) = self.window_energy(SUB_BLOCKS_SHORT_TERM) {
+            self.max_short_term = Some(self.max_short_term.map_or(energy, |m| m.max(energy)));
+        }
+
+        // Gating blocks are 400 ms long with 75% overlap, i.e. one per
+        // 100 ms sub-block once enough audio has been seen.
+        if let Some(energy) = self.window_energy(SUB_BLOCKS_MOMENTARY) {
+            self.max_momentary = Some(self.max_momentary.map_or(energy, |m| m.max(energy)));
+            let lufs = energy_to_lufs(energy);
+            if lufs > ABSOLUTE_GATE_LUFS {
+                let bin = self.histogram_bin(lufs);
+                self.histogram[bin].0 += 1;
+                self.histogram[bin].1 += energy;
+            }
+        }
+    }
+
+    fn window_energy(&self, sub_blocks: usize) -> Option<f64> {
+        if self.recent.len() < sub_blocks {
+            return None;
+        }
+        let sum: f64 = self.recent.iter().rev().take(sub_blocks).sum();
+        Some(sum / sub_blocks as f64)
+    }
+
+    fn histogram_bin(&self, lufs: f64) -> usize {
+        let bin = ((lufs - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU) as usize;
+        bin.min(self.histogram.len() - 1)
+    }
+
+    pub fn momentary_lufs(&self) -> Option<f64> {
+        self.window_energy(SUB_BLOCKS_MOMENTARY).map(energy_to_lufs)
+    }
+
+    pub fn short_term_lufs(&self) -> Option<f64> {
+        self.window_energy(SUB_BLOCKS_SHORT_TERM)
+            .map(energy_to_lufs)
+    }
+
+    pub fn integrated_lufs(&self) -> Option<f64> {
+        let (count, energy) = self
+            .histogram
+            .iter()
+            .fold((0u64, 0.0), |(c, e), &(bc, be)| (c + bc, e + be));
+        if count == 0 {
+            return None;
+        }
+
+        let relative_gate = energy_to_lufs(energy / count as f64) + RELATIVE_GATE_LU;
+        let first_bin = if relative_gate > ABSOLUTE_GATE_LUFS {
+            self.histogram_bin(relative_gate)
+        } else {
+            0
+        };
+
+        let (count, energy) = self.histogram[first_bin..]
+            .iter()
+            .fold((0u64, 0.0), |(c, e), &(bc, be)| (c + bc, e + be));
+        if count == 0 {
+            return None;
+        }
+        Some(energy_to_lufs(energy / count as f64))
+    }
+
+    pub fn true_peak_dbtp(&self) -> Option<f64> {
+        let peak =
+            self.channels
+                .iter()
+                .fold(0.0f32, |peak, channel| peak.max(channel.true_peak.max)) as f64;
+        if peak > 0.0 {
+            Some(20.0 * peak.log10())
+        } else {
+            None
+        }
+    }
+
+    pub fn summary(&self) -> LoudnessSummary {
+        LoudnessSummary {
+            momentary_lufs: self.momentary_lufs(),
+            short_term_lufs: self.short_term_lufs(),
+            integrated_lufs: self.integrated_lufs(),
+            true_peak_dbtp: self.true_peak_dbtp(),
+            max_momentary_lufs: self.max_momentary.map(energy_to_lufs),
+            max_short_term_lufs: self.max_short_term.map(energy_to_lufs),
+        }
+    }
+}
+
+fn energy_to_lufs(mean_square: f64) -> f64 {
+    if mean_square <= 0.0 {
+        f64::NEG_INFINITY
+    } else {
+        -0.691 + 10.0 * mean_square.log10()
+    }
+}
+
+/// Pre-filter stage 1 of BS.1770: a high shelf modelling the head.
+/// Coefficients are derived for any sample rate, not just 48 kHz.
+fn k_weighting_shelf(fs: f64) -> Biquad {
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+
+    Biquad::new(
+        (vh + vb * k / q + k * k) / a0,
+        2.0 * (k * k - vh) / a0,
+        (vh - vb * k / q + k * k) / a0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    )
+}
+
+/// Pre-filter stage 2 of BS.1770: the RLB high-pass.
+fn k_weighting_high_pass(fs: f64) -> Biquad {
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+
+    Biquad::new(
+        1.0,
+        -2.0,
+        1.0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    )
+}
+
+/// Inter-sample peak detector: polyphase windowed-sinc interpolation at 4x.
+struct TruePeak {
+    phases: Vec<[f32; TRUE_PEAK_TAPS_PER_PHASE]>,
+    history: [f32; TRUE_PEAK_TAPS_PER_PHASE],
+    max: f32,
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        let taps = TRUE_PEAK_OVERSAMPLING * TRUE_PEAK_TAPS_PER_PHASE;
+        let centre = (taps - 1) as f64 / 2.0;
+
+        let mut phases = vec![[0.0f32; TRUE_PEAK_TAPS_PER_PHASE]; TRUE_PEAK_OVERSAMPLING];
+        for (phase, coeffs) in phases.iter_mut().enumerate() {
+            let mut sum = 0.0;
+            for (k, coeff) in coeffs.iter_mut().enumerate() {
+                let n = phase + k * TRUE_PEAK_OVERSAMPLING;
+                let x = (n as f64 - centre) / TRUE_PEAK_OVERSAMPLING as f64;
+                let sinc = if x.abs() < 1e-12 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window = 0.5 - 0.5 * (2.0 * PI * (n as f64 + 0.5) / taps as f64).cos();
+                *coeff = (sinc * window) as f32;
+                sum += sinc * window;
+            }
+            for coeff in coeffs.iter_mut() {
+                *coeff /= sum as f32;
+            }
+        }
+
+        Self {
+            phases,
+            history: [0.0; TRUE_PEAK_TAPS_PER_PHASE],
+            max: 0.0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.max = self.max.max(sample.abs());
+        self.history.rotate_right(1);
+        self.history[0] = sample;
+
+        for coeffs in &self.phases {
+            let y: f32 = coeffs
+                .iter()
+                .zip(self.history.iter())
+                .map(|(c, x)| c * x)
+                .sum();
+            self.max = self.max.max(y.abs());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    /// `seconds` of a sine at `dbfs` on every channel, interleaved.
+    fn sine(freq: f64, dbfs: f64, seconds: f64, channels: usize) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        (0..(seconds * RATE as f64) as usize)
+            .flat_map(|i| {
+                let s = amplitude * (2.0 * PI * freq * i as f64 / RATE as f64).sin();
+                std::iter::repeat_n(s as f32, channels)
+            })
+            .collect()
+    }
+
+    fn stereo_meter() -> LoudnessMeter {
+        LoudnessMeter::new(RATE, &ChannelLayout::default_for(2))
+    }
+
+    #[test]
+    fn full_scale_reference_tone() {
+        // BS.1770: a 1 kHz tone at -20 dBFS on both stereo channels reads
+        // -20 LUFS; on one channel (or mono) 3 dB less.
+        let mut meter = stereo_meter();
+        meter.push(&sine(1_000.0, -20.0, 5.0, 2));
+        let summary = meter.summary();
+        for lufs in [
+            summary.momentary_lufs,
+            summary.short_term_lufs,
+            summary.integrated_lufs,
+        ] {
+            let lufs = lufs.unwrap();
+            assert!((lufs + 20.0).abs() < 0.1, "{} LUFS", lufs);
+        }
+
+        let mut mono = LoudnessMeter::new(RATE, &ChannelLayout::default_for(1));
+        mono.push(&sine(1_000.0, -20.0, 5.0, 1));
+        let lufs = mono.integrated_lufs().unwrap();
+        assert!((lufs + 23.01).abs() < 0.1, "{} LUFS", lufs);
+    }
+
+    #[test]
+    fn surrounds_are_weighted_and_lfe_ignored() {
+        let layout = ChannelLayout::default_for(6);
+        let tone = sine(1_000.0, -20.0, 3.0, 1);
+        let only = |index: usize| -> Vec<f32> {
+            tone.iter()
+                .flat_map(|&s| (0..6).map(move |ch| if ch == index { s } else { 0.0 }))
+                .collect()
+        };
+
+        let mut front = LoudnessMeter::new(RATE, &layout);
+        front.push(&only(0));
+        let mut lfe = LoudnessMeter::new(RATE, &layout);
+        lfe.push(&only(3));
+        let mut surround = LoudnessMeter::new(RATE, &layout);
+        surround.push(&only(4));
+
+        let front = front.integrated_lufs().unwrap();
+        assert!((front + 23.01).abs() < 0.1, "{} LUFS", front);
+        assert_eq!(lfe.integrated_lufs(), None);
+        let boost = surround.integrated_lufs().unwrap() - front;
+        assert!((boost - 1.49).abs() < 0.05, "{} LU", boost);
+    }
+
+    #[test]
+    fn absolute_gate_ignores_near_silence() {
+        let mut meter = stereo_meter();
+        meter.push(&sine(1_000.0, -75.0, 3.0, 2));
+        assert_eq!(meter.integrated_lufs(), None);
+        // Momentary loudness is not gated.
+        assert!(meter.momentary_lufs().unwrap() < -70.0);
+
+        // The blocks straddling the change read a little quieter.
+        meter.push(&sine(1_000.0, -20.0, 3.0, 2));
+        let lufs = meter.integrated_lufs().unwrap();
+        assert!((lufs + 20.0).abs() < 0.3, "{} LUFS", lufs);
+    }
+
+    #[test]
+    fn relative_gate_drops_blocks_ten_lu_below() {
+        // -40 is more than 10 LU under the -23 ungated mean: gated out.
+        let mut meter = stereo_meter();
+        meter.push(&sine(1_000.0, -20.0, 5.0, 2));
+        meter.push(&sine(1_000.0, -40.0, 5.0, 2));
+        let lufs = meter.integrated_lufs().unwrap();
+        assert!((lufs + 20.0).abs() < 0.2, "{} LUFS", lufs);
+
+        // -26 is within 10 LU, so the energies are averaged.
+        let mut meter = stereo_meter();
+        meter.push(&sine(1_000.0, -20.0, 5.0, 2));
+        meter.push(&sine(1_000.0, -26.0, 5.0, 2));
+        let expected = 10.0 * ((10f64.powf(-2.0) + 10f64.powf(-2.6)) / 2.0).log10();
+        let lufs = meter.integrated_lufs().unwrap();
+        assert!((lufs - expected).abs() < 0.2, "{} LUFS, {}", lufs, expected);
+    }
+
+    #[test]
+    fn true_peak_finds_inter_sample_peaks() {
+        // fs/4 at 45 degrees: every sample is at 0.707 of the peak, which
+        // lies exactly between them.
+        let samples: Vec<f32> = (0..4_800)
+            .map(|i| 0.5 * (PI / 2.0 * i as f64 + PI / 4.0).sin() as f32)
+            .collect();
+        let sample_peak = samples.iter().fold(0.0f32, |m, &s| m.max(s.abs()));
+        assert!((20.0 * (sample_peak as f64).log10() + 9.03).abs() < 0.01);
+
+        let mut meter = LoudnessMeter::new(RATE, &ChannelLayout::default_for(1));
+        meter.push(&samples);
+        let dbtp = meter.true_peak_dbtp().unwrap();
+        assert!((dbtp + 6.02).abs() < 0.3, "{} dBTP", dbtp);
+    }
+}
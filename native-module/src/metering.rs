@@ -0,0 +1,392 @@
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::JsFunction;
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+const DEFAULT_RATE_HZ: u32 = 30;
+const SILENCE_DBFS: f32 = -100.0;
+const SPECTRUM_MIN_HZ: f32 = 80.0;
+const SPECTRUM_MAX_HZ: f32 = 8_000.0;
+/// Summary sizes are bounded: every band is a Goertzel pass over the window,
+/// run on the capture thread.
+const MAX_WAVEFORM_POINTS: u32 = 4_096;
+const MAX_SPECTRUM_BANDS: u32 = 256;
+
+#[napi(object)]
+pub struct LevelOptions {
+    /// Readings per second, default 30.
+    pub rate_hz: Option<u32>,
+    /// Number of peak buckets in the waveform summary, at most 4096; omitted
+    /// or 0 disables it.
+    pub waveform_points: Option<u32>,
+    /// Number of log-spaced bands in the spectrum summary, at most 256;
+    /// omitted or 0 disables it.
+    pub spectrum_bands: Option<u32>,
+}
+
+#[napi(object)]
+pub struct AudioLevel {
+    pub peak: f64,
+    pub rms: f64,
+    pub peak_dbfs: f64,
+    pub dbfs: f64,
+    pub waveform: Option<Vec<f64>>,
+    /// Band magnitudes in dBFS, lowest band first.
+    pub spectrum: Option<Vec<f64>>,
+}
+
+#[derive(Clone)]
+pub struct MeterConfig {
+    pub rate_hz: u32,
+    pub waveform_points: usize,
+    pub spectrum_bands: usize,
+}
+
+impl Default for MeterConfig {
+    fn default() -> Self {
+        Self {
+            rate_hz: DEFAULT_RATE_HZ,
+            waveform_points: 0,
+            spectrum_bands: 0,
+        }
+    }
+}
+
+impl MeterConfig {
+    pub fn from_options(options: Option<LevelOptions>) -> napi::Result<Self> {
+        let mut config = Self::default();
+        let options = match options {
+            Some(o) => o,
+            None => return Ok(config),
+        };
+
+        if let Some(rate) = options.rate_hz {
+            if rate == 0 || rate > 100 {
+                return Err(napi::Error::from_reason(format!(
+                    "Unsupported level rate: {} Hz",
+                    rate
+                )));
+            }
+            config.rate_hz = rate;
+        }
+        let waveform_points = options.waveform_points.unwrap_or(0);
+        if waveform_points > MAX_WAVEFORM_POINTS {
+            return Err(napi::Error::from_reason(format!(
+                "Too many waveform points: {} (at most {})",
+                waveform_points, MAX_WAVEFORM_POINTS
+            )));
+        }
+        let spectrum_bands = options.spectrum_bands.unwrap_or(0);
+        if spectrum_bands > MAX_SPECTRUM_BANDS {
+            return Err(napi::Error::from_reason(format!(
+                "Too many spectrum bands: {} (at most {})",
+                spectrum_bands, MAX_SPECTRUM_BANDS
+            )));
+        }
+        config.waveform_points = waveform_points as usize;
+        config.spectrum_bands = spectrum_bands as usize;
+        Ok(config)
+    }
+}
+
+pub struct LevelReading {
+    pub peak: f32,
+    pub rms: f32,
+    pub waveform: Vec<f32>,
+    pub spectrum: Vec<f32>,
+}
+
+impl LevelReading {
+    fn silent(config: &MeterConfig) -> Self {
+        Self {
+            peak: 0.0,
+            rms: 0.0,
+            waveform: vec![0.0; config.waveform_points],
+            spectrum: vec![SILENCE_DBFS; config.spectrum_bands],
+        }
+    }
+}
+
+/// Computes peak/RMS readings over fixed windows of mono samples, one window
+/// per `1 / rate_hz` seconds of audio.
+pub struct LevelMeter {
+    config: MeterConfig,
+    sample_rate: u32,
+    window: Vec<f32>,
+    window_samples: usize,
+}
+
+impl LevelMeter {
+    pub fn new(config: MeterConfig, sample_rate: u32) -> Self {
+        let window_samples = (sample_rate / config.rate_hz).max(1) as usize;
+        Self {
+            config,
+            sample_rate,
+            window: Vec::with_capacity(window_samples),
+            window_samples,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn push(&mut self, samples: &[f32]) -> Vec<LevelReading> {
+        let mut readings = Vec::new();
+        let mut rest = samples;
+
+        while !rest.is_empty() {
+            let take = (self.window_samples - self.window.len()).min(rest.len());
+            self.window.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+
+            if self.window.len() == self.window_samples {
+                readings.push(self.measure());
+                self.window.clear();
+            }
+        }
+
+        readings
+    }
+
+    fn measure(&self) -> LevelReading {
+        let samples = &self.window;
+        let peak = samples.iter().fold(0.0f32, |acc, &s| acc.max(s.abs()));
+        let sum: f64 = samples.iter().map(|&s| (s as f64) * (s as f64)).sum();
+        let rms = (sum / samples.len() as f64).sqrt() as f32;
+
+        LevelReading {
+            peak,
+            rms,
+            waveform: waveform_summary(samples, self.config.waveform_points),
+            spectrum: spectrum_summary(samples, self.sample_rate, self.config.spectrum_bands),
+        }
+    }
+}
+
+pub fn to_dbfs(amplitude: f32) -> f32 {
+    if amplitude <= 1e-5 {
+        SILENCE_DBFS
+    } else {
+        20.0 * amplitude.log10()
+    }
+}
+
+fn waveform_summary(samples: &[f32], points: usize) -> Vec<f32> {
+    if points == 0 || samples.is_empty() {
+        return Vec::new();
+    }
+
+    (0..points)
+        .map(|i| {
+            let start = i * samples.len() / points;
+            let end = ((i + 1) * samples.len() / points).max(start + 1);
+            samples[start..end.min(samples.len())]
+                .iter()
+                .fold(0.0f32, |acc, &s| acc.max(s.abs()))
+        })
+        .collect()
+}
+
+/// Log-spaced band magnitudes via the Goertzel algorithm at each band's
+/// centre frequency. Cheap enough for a few dozen bands at UI rates.
+fn spectrum_summary(samples: &[f32], sample_rate: u32, bands: usize) -> Vec<f32> {
+    if bands == 0 || samples.is_empty() {
+        return Vec::new();
+    }
+
+    let max_hz = SPECTRUM_MAX_HZ.min(sample_rate as f32 / 2.0);
+    let ratio = (max_hz / SPECTRUM_MIN_HZ).powf(1.0 / bands as f32);
+    let n = samples.len() as f32;
+
+    (0..bands)
+        .map(|band| {
+            let centre = SPECTRUM_MIN_HZ * ratio.powf(band as f32 + 0.5);
+            let coeff = 2.0 * (2.0 * PI * centre / sample_rate as f32).cos();
+            let (mut s1, mut s2) = (0.0f32, 0.0f32);
+
+            for (i, &sample) in samples.iter().enumerate() {
+                let hann = 0.5 - 0.5 * (2.0 * PI * i as f32 / n).cos();
+                let s0 = sample * hann + coeff * s1 - s2;
+                s2 = s1;
+                s1 = s0;
+            }
+
+            let power = (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0);
+            // Hann window halves the coherent gain.
+            to_dbfs(power.sqrt() * 4.0 / n)
+        })
+        .collect()
+}
+
+/// A level-meter consumer of a capture stream, delivering `AudioLevel`
+/// readings to its own low-rate callback.
+pub struct LevelSubscriber {
+    id: u32,
+    config: MeterConfig,
+    meter: Option<LevelMeter>,
+    last_reading: Instant,
+    tsfn: ThreadsafeFunction<AudioLevel, ErrorStrategy::Fatal>,
+}
+
+impl LevelSubscriber {
+    pub fn new(id: u32, config: MeterConfig, callback: &JsFunction) -> napi::Result<Self> {
+        let tsfn: ThreadsafeFunction<AudioLevel, ErrorStrategy::Fatal> =
+            callback.create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))?;
+
+        Ok(Self {
+            id,
+            config,
+            meter: None,
+            last_reading: Instant::now(),
+            tsfn,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn push(&mut self, samples: &[f32], input_sample_rate: u32) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let meter = match self.meter {
+            Some(ref mut meter) if meter.sample_rate() == input_sample_rate => meter,
+            _ => self
+                .meter
+                .insert(LevelMeter::new(self.config.clone(), input_sample_rate)),
+        };
+
+        let readings = meter.push(samples);
+        if !readings.is_empty() {
+            self.last_reading = Instant::now();
+        }
+        for reading in readings {
+            self.deliver(reading);
+        }
+    }
+
+    /// Drops the partly filled reading of a previous capture run.
+    pub fn reset(&mut self) {
+        self.meter = None;
+        self.last_reading = Instant::now();
+    }
+
+    /// Called when the device delivered nothing. Loopback streams go quiet
+    /// while nothing plays, so report silence instead of freezing the meter.
+    pub fn idle(&mut self) {
+        let period = Duration::from_secs_f64(2.0 / self.config.rate_hz as f64);
+        if self.last_reading.elapsed() >= period {
+            self.last_reading = Instant::now();
+            self.deliver(LevelReading::silent(&self.config));
+        }
+    }
+
+    fn deliver(&self, reading: LevelReading) {
+        let level = AudioLevel {
+            peak: reading.peak as f64,
+            rms: reading.rms as f64,
+            peak_dbfs: to_dbfs(reading.peak) as f64,
+            dbfs: to_dbfs(reading.rms) as f64,
+            waveform: if self.config.waveform_points > 0 {
+                Some(reading.waveform.into_iter().map(|v| v as f64).collect())
+            } else {
+                None
+            },
+            spectrum: if self.config.spectrum_bands > 0 {
+                Some(reading.spectrum.into_iter().map(|v| v as f64).collect())
+            } else {
+                None
+            },
+        };
+        self.tsfn
+            .call(level, ThreadsafeFunctionCallMode::NonBlocking);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, amplitude: f32, sample_rate: u32, samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|i| amplitude * (2.0 * PI * freq * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    fn options(waveform_points: u32, spectrum_bands: u32) -> Option<LevelOptions> {
+        Some(LevelOptions {
+            rate_hz: None,
+            waveform_points: Some(waveform_points),
+            spectrum_bands: Some(spectrum_bands),
+        })
+    }
+
+    #[test]
+    fn bounds_summary_sizes() {
+        assert!(MeterConfig::from_options(options(4_096, 256)).is_ok());
+        assert!(MeterConfig::from_options(options(4_097, 0)).is_err());
+        assert!(MeterConfig::from_options(options(0, 257)).is_err());
+        assert!(MeterConfig::from_options(options(u32::MAX, u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn reads_one_window_per_period() {
+        let mut meter = LevelMeter::new(MeterConfig::default(), 48_000);
+        // 30 Hz: 1600-sample windows; the remainder waits for more audio.
+        let readings = meter.push(&sine(1_000.0, 0.5, 48_000, 4_000));
+        assert_eq!(readings.len(), 2);
+        assert_eq!(meter.push(&[0.0; 800]).len(), 1);
+
+        let reading = &readings[0];
+        assert!((reading.peak - 0.5).abs() < 1e-3, "peak {}", reading.peak);
+        let expected_rms = 0.5 / 2f32.sqrt();
+        assert!(
+            (reading.rms - expected_rms).abs() < 1e-3,
+            "rms {}",
+            reading.rms
+        );
+        assert!((to_dbfs(reading.rms) + 9.03).abs() < 0.05);
+    }
+
+    #[test]
+    fn summaries_follow_the_signal() {
+        let config = MeterConfig {
+            waveform_points: 4,
+            spectrum_bands: 16,
+            ..MeterConfig::default()
+        };
+        let mut meter = LevelMeter::new(config, 48_000);
+        let mut samples = sine(1_000.0, 0.5, 48_000, 1_600);
+        // Silence in the last quarter.
+        samples[1_200..].fill(0.0);
+        let reading = meter.push(&samples).remove(0);
+
+        assert_eq!(reading.waveform.len(), 4);
+        assert!(reading.waveform[..3].iter().all(|&p| p > 0.45));
+        assert_eq!(reading.waveform[3], 0.0);
+
+        let loudest = reading
+            .spectrum
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(band, _)| band)
+            .unwrap();
+        let ratio = (SPECTRUM_MAX_HZ / SPECTRUM_MIN_HZ).powf(1.0 / 16.0);
+        let low = SPECTRUM_MIN_HZ * ratio.powf(loudest as f32);
+        assert!(low <= 1_000.0 && 1_000.0 < low * ratio, "band {}", loudest);
+    }
+
+    #[test]
+    fn silence_reads_as_the_floor() {
+        let reading = LevelReading::silent(&MeterConfig {
+            spectrum_bands: 3,
+            ..MeterConfig::default()
+        });
+        assert_eq!(to_dbfs(reading.rms), SILENCE_DBFS);
+        assert_eq!(reading.spectrum, vec![SILENCE_DBFS; 3]);
+    }
+}
@@ -0,0 +1,256 @@
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::audio_config::SAMPLE_RATE;
+
+pub const WINDOW_SAMPLES: usize = 400; // 25 ms at 16 kHz
+pub const HOP_SAMPLES: usize = 160; // 10 ms
+const FFT_SIZE: usize = 512;
+const MEL_BANDS: usize = 40;
+const MEL_MIN_HZ: f32 = 20.0;
+const MEL_MAX_HZ: f32 = 7_600.0;
+/// Cepstral coefficients kept, excluding c0 (overall level).
+pub const CEPSTRA: usize = 19;
+const PRE_EMPHASIS: f32 = 0.97;
+
+pub struct MfccFrame {
+    /// Mean square of the windowed frame, in dB.
+    pub energy_db: f32,
+    /// Liftered c1..c19.
+    pub cepstra: Vec<f32>,
+}
+
+pub struct FbankFrame {
+    /// Mean square of the windowed frame, in dB.
+    pub energy_db: f32,
+    /// Natural log of each mel band's energy.
+    pub log_mel: Vec<f32>,
+}
+
+/// Mel-frequency cepstra of 16 kHz mono audio: 25 ms Hamming windows every
+/// 10 ms, 40 mel bands, sinusoidal lifter.
+pub struct Mfcc {
+    mel: MelAnalysis,
+    dct: Vec<Vec<f32>>,
+}
+
+impl Mfcc {
+    pub fn new() -> Self {
+        let dct = (1..=CEPSTRA)
+            .map(|k| {
+                // Sinusoidal lifter evens out the coefficients' ranges.
+                let lifter = 1.0 + 11.0 * (PI * k as f32 / 22.0).sin();
+                (0..MEL_BANDS)
+                    .map(|m| lifter * (PI * k as f32 * (m as f32 + 0.5) / MEL_BANDS as f32).cos())
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            mel: MelAnalysis::new(MEL_BANDS),
+            dct,
+        }
+    }
+
+    /// One frame per full window in `samples`, starting every hop. A caller
+    /// streaming audio drops `frames.len() * HOP_SAMPLES` samples afterwards.
+    pub fn frames(&self, samples: &[f32]) -> Vec<MfccFrame> {
+        self.mel
+            .frames(samples)
+            .into_iter()
+            .map(|frame| MfccFrame {
+                energy_db: frame.energy_db,
+                cepstra: self
+                    .dct
+                    .iter()
+                    .map(|row| row.iter().zip(&frame.log_mel).map(|(d, l)| d * l).sum())
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl Default for Mfcc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Log mel filterbank energies ("fbank") of 16 kHz mono audio, framed like
+/// `Mfcc`: the input most speaker embedding models are trained on.
+pub struct Fbank {
+    mel: MelAnalysis,
+}
+
+impl Fbank {
+    pub fn new(bands: usize) -> Self {
+        Self {
+            mel: MelAnalysis::new(bands),
+        }
+    }
+
+    pub fn bands(&self) -> usize {
+        self.mel.filters.len()
+    }
+
+    pub fn frames(&self, samples: &[f32]) -> Vec<FbankFrame> {
+        self.mel.frames(samples)
+    }
+}
+
+struct MelAnalysis {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    filters: Vec<Vec<(usize, f32)>>,
+}
+
+impl MelAnalysis {
+    fn new(bands: usize) -> Self {
+        let window = (0..WINDOW_SAMPLES)
+            .map(|i| 0.54 - 0.46 * (2.0 * PI * i as f32 / (WINDOW_SAMPLES - 1) as f32).cos())
+            .collect();
+
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            filters: mel_filters(bands),
+        }
+    }
+
+    fn frames(&self, samples: &[f32]) -> Vec<FbankFrame> {
+        if samples.len() < WINDOW_SAMPLES {
+            return Vec::new();
+        }
+
+        let mut buffer = vec![Complex::new(0.0f32, 0.0); FFT_SIZE];
+        (0..=samples.len() - WINDOW_SAMPLES)
+            .step_by(HOP_SAMPLES)
+            .map(|start| {
+                let previous = samples[start.saturating_sub(1)];
+                self.frame(
+                    &samples[start..start + WINDOW_SAMPLES],
+                    previous,
+                    &mut buffer,
+                )
+            })
+            .collect()
+    }
+
+    fn frame(&self, samples: &[f32], previous: f32, buffer: &mut [Complex<f32>]) -> FbankFrame {
+        let mut energy = 0.0f32;
+        let mut last = previous;
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            *slot = match samples.get(i) {
+                Some(&x) => {
+                    let windowed = (x - PRE_EMPHASIS * last) * self.window[i];
+                    energy += x * x * self.window[i] * self.window[i];
+                    last = x;
+                    Complex::new(windowed, 0.0)
+                }
+                None => Complex::new(0.0, 0.0),
+            };
+        }
+        self.fft.process(buffer);
+
+        let power: Vec<f32> = buffer[..FFT_SIZE / 2 + 1]
+            .iter()
+            .map(|c| c.norm_sqr())
+            .collect();
+
+        FbankFrame {
+            energy_db: 10.0 * (energy / WINDOW_SAMPLES as f32).max(1e-10).log10(),
+            log_mel: self
+                .filters
+                .iter()
+                .map(|filter| {
+                    let energy: f32 = filter.iter().map(|&(bin, w)| power[bin] * w).sum();
+                    energy.max(1e-10).ln()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Triangular filters on the mel scale as sparse (bin, weight) lists.
+fn mel_filters(bands: usize) -> Vec<Vec<(usize, f32)>> {
+    let to_mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+    let to_hz = |mel: f32| 700.0 * (10f32.powf(mel / 2595.0) - 1.0);
+    let bin_hz = SAMPLE_RATE as f32 / FFT_SIZE as f32;
+
+    let (low, high) = (to_mel(MEL_MIN_HZ), to_mel(MEL_MAX_HZ));
+    let edges: Vec<f32> = (0..bands + 2)
+        .map(|i| to_hz(low + (high - low) * i as f32 / (bands + 1) as f32))
+        .collect();
+
+    (0..bands)
+        .map(|m| {
+            let (left, centre, right) = (edges[m], edges[m + 1], edges[m + 2]);
+            (0..=FFT_SIZE / 2)
+                .filter_map(|bin| {
+                    let hz = bin as f32 * bin_hz;
+                    let weight = if hz > left && hz <= centre {
+                        (hz - left) / (centre - left)
+                    } else if hz > centre && hz < right {
+                        (right - hz) / (right - centre)
+                    } else {
+                        0.0
+                    };
+                    (weight > 0.0).then_some((bin, weight))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_tone_peaks_in_its_mel_band() {
+        let tone: Vec<f32> = (0..SAMPLE_RATE as usize / 10)
+            .map(|i| 0.5 * (2.0 * PI * 1_000.0 * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect();
+        let fbank = Fbank::new(80);
+        let frames = fbank.frames(&tone);
+        assert_eq!(
+            frames.len(),
+            (tone.len() - WINDOW_SAMPLES) / HOP_SAMPLES + 1
+        );
+
+        let to_mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+        let (low, high) = (to_mel(MEL_MIN_HZ), to_mel(MEL_MAX_HZ));
+        let expected = ((to_mel(1_000.0) - low) / (high - low) * 81.0).round() as usize - 1;
+        for frame in &frames {
+            let peak = (0..fbank.bands())
+                .max_by(|&a, &b| frame.log_mel[a].total_cmp(&frame.log_mel[b]))
+                .unwrap();
+            assert!(peak.abs_diff(expected) <= 1, "peak in band {}", peak);
+            // Mean square 0.125, times the Hamming window's 0.397.
+            assert!((frame.energy_db - 10.0 * (0.125f32 * 0.397).log10()).abs() < 0.5);
+        }
+        assert_eq!(Mfcc::new().frames(&tone[..WINDOW_SAMPLES - 1]).len(), 0);
+    }
+
+    #[test]
+    fn cepstra_ignore_the_level() {
+        // Broadband, so no mel band sits at the energy floor.
+        let mut state = 1u32;
+        let loud: Vec<f32> = (0..SAMPLE_RATE as usize / 4)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+            })
+            .collect();
+        let quiet: Vec<f32> = loud.iter().map(|s| s * 0.01).collect();
+        let mfcc = Mfcc::new();
+        for (a, b) in mfcc.frames(&loud).iter().zip(mfcc.frames(&quiet)) {
+            assert!((a.energy_db - b.energy_db - 40.0).abs() < 0.1);
+            for (x, y) in a.cepstra.iter().zip(&b.cepstra) {
+                assert!((x - y).abs() < 1e-2, "{} vs {}", x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Frames of latency kept in hand before the first frame is released, to
+/// absorb device packet jitter.
+const JITTER_FRAMES: u32 = 2;
+/// Frames kept queued at most; older ones are dropped when the device runs
+/// ahead of the wall clock.
+const MAX_QUEUED_FRAMES: usize = 10;
+/// A loop stall longer than this resynchronises instead of bursting.
+const MAX_CATCH_UP_FRAMES: u32 = 5;
+
+/// Releases frames on a fixed wall-clock cadence, one per `period`,
+/// regardless of how bursty their arrival is. Slots with nothing queued are
+/// filled with silence.
+pub struct Pacer {
+    period: Duration,
+    next: Option<Instant>,
+    queue: VecDeque<Vec<u8>>,
+}
+
+impl Pacer {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            next: None,
+            queue: VecDeque::with_capacity(MAX_QUEUED_FRAMES + 1),
+        }
+    }
+
+    pub fn enqueue(&mut self, frame: Vec<u8>) {
+        self.queue.push_back(frame);
+        if self.queue.len() > MAX_QUEUED_FRAMES {
+            self.queue.pop_front();
+        }
+    }
+
+    /// Frames whose slot has come by `now`, in order. The cadence starts
+    /// with the first queued frame.
+    pub fn due(&mut self, now: Instant, silence: impl Fn() -> Vec<u8>) -> Vec<Vec<u8>> {
+        let mut next = match self.next {
+            Some(next) => next,
+            None if self.queue.is_empty() => return Vec::new(),
+            None => now + self.period * JITTER_FRAMES,
+        };
+
+        if now.saturating_duration_since(next) > self.period * MAX_CATCH_UP_FRAMES {
+            next = now;
+        }
+
+        let mut frames = Vec::new();
+        while next <= now {
+            frames.push(self.queue.pop_front().unwrap_or_else(&silence));
+            next += self.period;
+        }
+        self.next = Some(next);
+        frames
+    }
+
+    /// Every queued frame at once, for when the stream ends.
+    pub fn drain(&mut self) -> Vec<Vec<u8>> {
+        self.next = None;
+        self.queue.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(20);
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    fn frame(id: u8) -> Vec<u8> {
+        vec![id]
+    }
+
+    fn due(pacer: &mut Pacer, now: Instant) -> Vec<Vec<u8>> {
+        pacer.due(now, Vec::new)
+    }
+
+    #[test]
+    fn releases_one_frame_per_period_after_the_jitter_delay() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(PERIOD);
+        assert!(due(&mut pacer, start).is_empty());
+
+        pacer.enqueue(frame(1));
+        assert!(due(&mut pacer, start).is_empty());
+        assert!(due(&mut pacer, ms(start, 39)).is_empty());
+        assert_eq!(due(&mut pacer, ms(start, 40)), [frame(1)]);
+
+        // A burst of arrivals still leaves one frame per slot.
+        pacer.enqueue(frame(2));
+        pacer.enqueue(frame(3));
+        pacer.enqueue(frame(4));
+        assert!(due(&mut pacer, ms(start, 59)).is_empty());
+        assert_eq!(due(&mut pacer, ms(start, 60)), [frame(2)]);
+        assert_eq!(due(&mut pacer, ms(start, 80)), [frame(3)]);
+        assert_eq!(due(&mut pacer, ms(start, 100)), [frame(4)]);
+
+        // Empty slots are filled with silence.
+        assert_eq!(due(&mut pacer, ms(start, 120)), [Vec::<u8>::new()]);
+    }
+
+    #[test]
+    fn catches_up_on_short_stalls_and_resyncs_after_long_ones() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(PERIOD);
+        for id in 0..8 {
+            pacer.enqueue(frame(id));
+        }
+        assert!(due(&mut pacer, start).is_empty());
+        assert_eq!(due(&mut pacer, ms(start, 40)), [frame(0)]);
+
+        // Four periods late: the missed slots are delivered together.
+        assert_eq!(
+            due(&mut pacer, ms(start, 120)),
+            [frame(1), frame(2), frame(3), frame(4)]
+        );
+
+        // Far behind: one frame now, and the cadence restarts from here.
+        assert_eq!(due(&mut pacer, ms(start, 400)), [frame(5)]);
+        assert!(due(&mut pacer, ms(start, 419)).is_empty());
+        assert_eq!(due(&mut pacer, ms(start, 420)), [frame(6)]);
+    }
+
+    #[test]
+    fn a_device_running_ahead_loses_the_oldest_frames() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(PERIOD);
+        for id in 0..(MAX_QUEUED_FRAMES as u8 + 2) {
+            pacer.enqueue(frame(id));
+        }
+        assert!(due(&mut pacer, start).is_empty());
+        assert_eq!(due(&mut pacer, ms(start, 40)), [frame(2)]);
+    }
+
+    #[test]
+    fn drain_hands_out_everything_and_restarts_the_cadence() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(PERIOD);
+        pacer.enqueue(frame(1));
+        assert!(due(&mut pacer, start).is_empty());
+        assert_eq!(due(&mut pacer, ms(start, 40)), [frame(1)]);
+        pacer.enqueue(frame(2));
+        pacer.enqueue(frame(3));
+        assert_eq!(pacer.drain(), [frame(2), frame(3)]);
+
+        pacer.enqueue(frame(4));
+        assert!(due(&mut pacer, ms(start, 60)).is_empty());
+        assert_eq!(due(&mut pacer, ms(start, 100)), [frame(4)]);
+    }
+}
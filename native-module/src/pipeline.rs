@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// One block of audio moving through a pipeline: interleaved `f32` at the
+/// consumer's output rate and channel count.
+pub struct Frame {
+    pub samples: Vec<f32>,
+    pub channels: usize,
+}
+
+impl Frame {
+    pub fn new(samples: Vec<f32>, channels: usize) -> Self {
+        Self { samples, channels }
+    }
+
+    /// Per-sample mean of the channels.
+    pub fn mono(&self) -> Vec<f32> {
+        let channels = self.channels.max(1);
+        self.samples
+            .chunks(channels)
+            .map(|c| c.iter().sum::<f32>() / channels as f32)
+            .collect()
+    }
+}
+
+/// What a stage decided about a frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Flow {
+    Continue,
+    /// Stop here; later stages and delivery never see the frame.
+    Drop,
+}
+
+/// One processing step of a consumer's frame pipeline.
+pub trait Stage: Send {
+    /// Name used in the `stages` option and in stats.
+    fn name(&self) -> &'static str;
+
+    fn process(&mut self, frame: &mut Frame) -> Flow;
+
+    /// Forgets all state, as if no audio had been processed.
+    fn reset(&mut self);
+
+    /// Delay this stage adds to the audio.
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Stage-specific counters, e.g. frames suppressed.
+    fn stats(&self) -> HashMap<String, f64> {
+        HashMap::new()
+    }
+}
+
+#[napi(object)]
+pub struct StageStats {
+    pub name: String,
+    /// Frames the stage received.
+    pub frames: u32,
+    /// Frames it dropped.
+    pub dropped: u32,
+    /// Total time spent in the stage.
+    pub processing_ms: f64,
+    pub latency_ms: f64,
+    pub values: HashMap<String, f64>,
+}
+
+#[napi(object)]
+pub struct PipelineStats {
+    /// Sum of the stage latencies.
+    pub latency_ms: f64,
+    pub stages: Vec<StageStats>,
+    /// Samples limited to full scale when encoding to i16.
+    pub clipped_samples: f64,
+    /// Samples reshaped by `softClip`.
+    pub soft_clipped_samples: f64,
+}
+
+struct Entry {
+    stage: Box<dyn Stage>,
+    frames: u32,
+    dropped: u32,
+    processing: Duration,
+}
+
+/// Stages run in order on each frame until one drops it. A subscriber's
+/// frames are already downmixed and resampled to its output format when
+/// they enter, and are encoded after the last stage; those two steps are
+/// not stages and cannot be reordered.
+#[derive(Default)]
+pub struct Pipeline {
+    entries: Vec<Entry>,
+}
+
+impl Pipeline {
+    pub fn new(stages: Vec<Box<dyn Stage>>) -> Self {
+        Self {
+            entries: stages
+                .into_iter()
+                .map(|stage| Entry {
+                    stage,
+                    frames: 0,
+                    dropped: 0,
+                    processing: Duration::ZERO,
+                })
+                .collect(),
+        }
+    }
+
+    /// Runs the frame through every stage; `Flow::Drop` if one dropped it.
+    pub fn process(&mut self, frame: &mut Frame) -> Flow {
+        for entry in self.entries.iter_mut() {
+            let started = Instant::now();
+            let flow = entry.stage.process(frame);
+            entry.processing += started.elapsed();
+            entry.frames = entry.frames.saturating_add(1);
+            if flow == Flow::Drop {
+                entry.dropped = entry.dropped.saturating_add(1);
+                return Flow::Drop;
+            }
+        }
+        Flow::Continue
+    }
+
+    /// Resets every stage, e.g. before a new capture run. Counters are kept.
+    pub fn reset(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.stage.reset();
+        }
+    }
+
+    pub fn latency(&self) -> Duration {
+        self.entries.iter().map(|e| e.stage.latency()).sum()
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        PipelineStats {
+            latency_ms: self.latency().as_secs_f64() * 1000.0,
+            stages: self
+                .entries
+                .iter()
+                .map(|e| StageStats {
+                    name: e.stage.name().to_string(),
+                    frames: e.frames,
+                    dropped: e.dropped,
+                    processing_ms: e.processing.as_secs_f64() * 1000.0,
+                    latency_ms: e.stage.latency().as_secs_f64() * 1000.0,
+                    values: e.stage.stats(),
+                })
+                .collect(),
+            clipped_samples: 0.0,
+            soft_clipped_samples: 0.0,
+        }
+    }
+}
+
+/// Built-in stages a subscriber can run, by their `stages` option name.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StageKind {
+    HighPass,
+    Denoise,
+    Eq,
+    Agc,
+    Vad,
+    SuppressSilence,
+}
+
+/// Order used when the options do not give one.
+pub const DEFAULT_STAGES: [StageKind; 6] = [
+    StageKind::HighPass,
+    StageKind::Denoise,
+    StageKind::Eq,
+    StageKind::Agc,
+    StageKind::Vad,
+    StageKind::SuppressSilence,
+];
+
+impl StageKind {
+    pub fn parse(value: &str) -> napi::Result<Self> {
+        match value {
+            "highPass" => Ok(StageKind::HighPass),
+            "denoise" => Ok(StageKind::Denoise),
+            "eq" => Ok(StageKind::Eq),
+            "agc" => Ok(StageKind::Agc),
+            "vad" => Ok(StageKind::Vad),
+            "suppressSilence" => Ok(StageKind::SuppressSilence),
+            other => Err(napi::Error::from_reason(format!(
+                "Unknown pipeline stage: {}",
+                other
+            ))),
+        }
+    }
+
+    /// Parses a `stages` option: names in processing order, each at most once.
+    pub fn parse_list(names: &[String]) -> napi::Result<Vec<Self>> {
+        let mut stages = Vec::with_capacity(names.len());
+        for name in names {
+            let kind = Self::parse(name)?;
+            if stages.contains(&kind) {
+                return Err(napi::Error::from_reason(format!(
+                    "Pipeline stage listed twice: {}",
+                    name
+                )));
+            }
+            stages.push(kind);
+        }
+        Ok(stages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scales by a constant and drops frames whose first sample is negative.
+    struct Gain(f32);
+
+    impl Stage for Gain {
+        fn name(&self) -> &'static str {
+            "gain"
+        }
+
+        fn process(&mut self, frame: &mut Frame) -> Flow {
+            if frame.samples.first().is_some_and(|&s| s < 0.0) {
+                return Flow::Drop;
+            }
+            for sample in frame.samples.iter_mut() {
+                *sample *= self.0;
+            }
+            Flow::Continue
+        }
+
+        fn reset(&mut self) {}
+
+        fn latency(&self) -> Duration {
+            Duration::from_millis(5)
+        }
+    }
+
+    #[test]
+    fn runs_stages_in_order() {
+        let mut pipeline = Pipeline::new(vec![Box::new(Gain(2.0)), Box::new(Gain(0.25))]);
+        let mut frame = Frame::new(vec![1.0, 0.5], 1);
+        assert_eq!(pipeline.process(&mut frame), Flow::Continue);
+        assert_eq!(frame.samples, vec![0.5, 0.25]);
+        assert_eq!(pipeline.latency(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn dropped_frames_skip_later_stages() {
+        let mut pipeline = Pipeline::new(vec![Box::new(Gain(1.0)), Box::new(Gain(1.0))]);
+        let mut frame = Frame::new(vec![-1.0], 1);
+        assert_eq!(pipeline.process(&mut frame), Flow::Drop);
+
+        let stats = pipeline.stats();
+        assert_eq!((stats.stages[0].frames, stats.stages[0].dropped), (1, 1));
+        assert_eq!(stats.stages[1].frames, 0);
+    }
+
+    #[test]
+    fn mixes_frames_to_mono() {
+        let frame = Frame::new(vec![1.0, 0.0, 0.5, 0.5], 2);
+        assert_eq!(frame.mono(), vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn rejects_unknown_and_repeated_stages() {
+        let names = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            StageKind::parse_list(&names(&["suppressSilence", "agc", "highPass"])).unwrap(),
+            vec![
+                StageKind::SuppressSilence,
+                StageKind::Agc,
+                StageKind::HighPass
+            ]
+        );
+        assert!(StageKind::parse_list(&names(&["resample"])).is_err());
+        assert!(StageKind::parse_list(&names(&["eq", "eq"])).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+/// Sample encodings a device mix format can report. Integer formats are
+/// signed little-endian; `I24` is packed (3 bytes per sample).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SampleEncoding {
+    I16,
+    I24,
+    I32,
+    F32,
+    F64,
+}
+
+impl SampleEncoding {
+    pub fn bytes(self) -> usize {
+        match self {
+            SampleEncoding::I16 => 2,
+            SampleEncoding::I24 => 3,
+            SampleEncoding::I32 | SampleEncoding::F32 => 4,
+            SampleEncoding::F64 => 8,
+        }
+    }
+}
+
+/// Layout of interleaved device bytes: encoding, channel count and the size
+/// of one frame (all channels), which may include trailing padding.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SampleFormat {
+    encoding: SampleEncoding,
+    channels: usize,
+    block_align: usize,
+}
+
+impl SampleFormat {
+    pub fn new(encoding: SampleEncoding, channels: usize) -> Self {
+        Self {
+            encoding,
+            channels,
+            block_align: encoding.bytes() * channels,
+        }
+    }
+
+    /// Builds a format from `WAVEFORMATEX(TENSIBLE)` style fields. Samples
+    /// with fewer valid bits than their container (e.g. 24-in-32) are
+    /// left-justified, so they decode as the container type.
+    pub fn from_wave_format(
+        is_float: bool,
+        bits_per_sample: u16,
+        channels: usize,
+        block_align: usize,
+    ) -> Result<Self, String> {
+        let encoding = match (is_float, bits_per_sample) {
+            (false, 16) => SampleEncoding::I16,
+            (false, 24) => SampleEncoding::I24,
+            (false, 32) => SampleEncoding::I32,
+            (true, 32) => SampleEncoding::F32,
+            (true, 64) => SampleEncoding::F64,
+            (float, bits) => {
+                return Err(format!(
+                    "Unsupported sample format: {} bit {}",
+                    bits,
+                    if float { "float" } else { "integer" }
+                ))
+            }
+        };
+
+        if channels == 0 || block_align < encoding.bytes() * channels {
+            return Err(format!(
+                "Invalid block align {} for {} channels of {:?}",
+                block_align, channels, encoding
+            ));
+        }
+
+        Ok(Self {
+            encoding,
+            channels,
+            block_align,
+        })
+    }
+
+    pub fn encoding(&self) -> SampleEncoding {
+        self.encoding
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn block_align(&self) -> usize {
+        self.block_align
+    }
+
+    /// Decodes whole frames to interleaved `f32` in [-1, 1). Frame padding
+    /// is skipped and a trailing partial frame is ignored.
+    pub fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+        let mut samples = Vec::with_capacity(bytes.len() / self.block_align * self.channels);
+        samples.extend(self.samples(bytes));
+        samples
+    }
+
+    /// `decode` without allocating, for realtime callbacks.
+    pub fn samples<'a>(&self, bytes: &'a [u8]) -> impl Iterator<Item = f32> + 'a {
+        let (encoding, channels) = (self.encoding, self.channels);
+        let sample_bytes = encoding.bytes();
+        bytes.chunks_exact(self.block_align).flat_map(move |frame| {
+            (0..channels).map(move |ch| {
+                let start = ch * sample_bytes;
+                decode_sample(encoding, &frame[start..start + sample_bytes])
+            })
+        })
+    }
+}
+
+fn decode_sample(encoding: SampleEncoding, b: &[u8]) -> f32 {
+    match encoding {
+        SampleEncoding::I16 => i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0,
+        SampleEncoding::I24 => {
+            // Place the 24 bits in the top of an i32 so the sign extends.
+            let value = i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8;
+            value as f32 / 8_388_608.0
+        }
+        SampleEncoding::I32 => {
+            i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0
+        }
+        SampleEncoding::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        SampleEncoding::F64 => {
+            f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{} != {}", a, e);
+        }
+    }
+
+    #[test]
+    fn decodes_i16() {
+        let bytes: Vec<u8> = [0i16, 16_384, -32_768, 32_767]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let format = SampleFormat::new(SampleEncoding::I16, 2);
+        assert_close(
+            &format.decode(&bytes),
+            &[0.0, 0.5, -1.0, 32_767.0 / 32_768.0],
+        );
+    }
+
+    #[test]
+    fn decodes_packed_i24_with_sign() {
+        // 0x400000 = +0.5, 0xC00000 = -0.5, 0x800000 = -1.0
+        let bytes = [0x00, 0x00, 0x40, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x80];
+        let format = SampleFormat::new(SampleEncoding::I24, 1);
+        assert_close(&format.decode(&bytes), &[0.5, -0.5, -1.0]);
+    }
+
+    #[test]
+    fn decodes_i32() {
+        let bytes: Vec<u8> = [i32::MIN, 1 << 30]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let format = SampleFormat::new(SampleEncoding::I32, 1);
+        assert_close(&format.decode(&bytes), &[-1.0, 0.5]);
+    }
+
+    #[test]
+    fn decodes_f32_and_f64() {
+        let bytes: Vec<u8> = [0.25f32, -0.75]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let format = SampleFormat::new(SampleEncoding::F32, 2);
+        assert_close(&format.decode(&bytes), &[0.25, -0.75]);
+
+        let bytes: Vec<u8> = [0.125f64, -1.0]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let format = SampleFormat::new(SampleEncoding::F64, 1);
+        assert_close(&format.decode(&bytes), &[0.125, -1.0]);
+    }
+
+    #[test]
+    fn skips_frame_padding_from_block_align() {
+        // Two channels of i16 in an 8-byte frame: 4 bytes of padding each.
+        let mut bytes = Vec::new();
+        for (l, r) in [(16_384i16, -16_384i16), (0, 8_192)] {
+            bytes.extend_from_slice(&l.to_le_bytes());
+            bytes.extend_from_slice(&r.to_le_bytes());
+            bytes.extend_from_slice(&[0xAA; 4]);
+        }
+        let format = SampleFormat::from_wave_format(false, 16, 2, 8).unwrap();
+        assert_close(&format.decode(&bytes), &[0.5, -0.5, 0.0, 0.25]);
+    }
+
+    #[test]
+    fn ignores_trailing_partial_frame() {
+        let bytes: Vec<u8> = [0.5f32, 0.5, 0.5]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let format = SampleFormat::new(SampleEncoding::F32, 2);
+        assert_eq!(format.decode(&bytes).len(), 2);
+    }
+
+    #[test]
+    fn maps_wave_format_fields() {
+        let format = SampleFormat::from_wave_format(false, 24, 2, 6).unwrap();
+        assert_eq!(format.encoding(), SampleEncoding::I24);
+        let format = SampleFormat::from_wave_format(true, 64, 1, 8).unwrap();
+        assert_eq!(format.encoding(), SampleEncoding::F64);
+
+        assert!(SampleFormat::from_wave_format(false, 8, 1, 1).is_err());
+        assert!(SampleFormat::from_wave_format(true, 32, 2, 4).is_err());
+    }
+}
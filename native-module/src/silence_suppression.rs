@@ -1,9 +1,17 @@
-use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::audio_config::FRAME_MS;
+use crate::conversion::f32_to_i16;
+use crate::pipeline::{Flow, Frame, Stage};
 
 pub struct SilenceSuppressionConfig {
     pub speech_threshold_rms: f32,
     pub speech_hangover: Duration,
     pub silence_keepalive_interval: Duration,
+    /// Audio per processed frame. Hangover and keepalive are timed in audio,
+    /// so a file processed faster than real time behaves like live capture.
+    pub frame_duration: Duration,
 }
 
 impl Default for SilenceSuppressionConfig {
@@ -12,6 +20,7 @@ impl Default for SilenceSuppressionConfig {
             speech_threshold_rms: 100.0,
             speech_hangover: Duration::from_millis(200),
             silence_keepalive_interval: Duration::from_millis(100),
+            frame_duration: Duration::from_millis(FRAME_MS as u64),
         }
     }
 }
@@ -19,8 +28,10 @@ impl Default for SilenceSuppressionConfig {
 pub struct SilenceSuppressor {
     config: SilenceSuppressionConfig,
     state: SuppressionState,
-    last_speech_time: Instant,
-    last_keepalive_time: Instant,
+    /// Audio processed so far, and when speech and keepalive last occurred.
+    position: Duration,
+    last_speech_time: Duration,
+    last_keepalive_time: Duration,
 }
 
 #[derive(Clone, Copy)]
@@ -38,17 +49,18 @@ pub enum FrameAction {
 
 impl SilenceSuppressor {
     pub fn new(config: SilenceSuppressionConfig) -> Self {
-        let now = Instant::now();
         Self {
             config,
             state: SuppressionState::Active,
-            last_speech_time: now,
-            last_keepalive_time: now,
+            position: Duration::ZERO,
+            last_speech_time: Duration::ZERO,
+            last_keepalive_time: Duration::ZERO,
         }
     }
 
     pub fn process(&mut self, frame: &[i16]) -> FrameAction {
-        let now = Instant::now();
+        self.position += self.config.frame_duration;
+        let now = self.position;
         let rms = calculate_rms(frame);
 
         if rms >= self.config.speech_threshold_rms {
@@ -59,7 +71,7 @@ impl SilenceSuppressor {
 
         match self.state {
             SuppressionState::Active | SuppressionState::Hangover => {
-                if now.duration_since(self.last_speech_time) > self.config.speech_hangover {
+                if now - self.last_speech_time > self.config.speech_hangover {
                     self.state = SuppressionState::Suppressed;
                 } else {
                     self.state = SuppressionState::Hangover;
@@ -69,13 +81,77 @@ impl SilenceSuppressor {
             SuppressionState::Suppressed => {}
         }
 
-        if now.duration_since(self.last_keepalive_time) >= self.config.silence_keepalive_interval {
+        if now - self.last_keepalive_time >= self.config.silence_keepalive_interval {
             self.last_keepalive_time = now;
             FrameAction::SendSilence
         } else {
             FrameAction::Suppress
         }
     }
+
+    pub fn reset(&mut self) {
+        self.state = SuppressionState::Active;
+        self.position = Duration::ZERO;
+        self.last_speech_time = Duration::ZERO;
+        self.last_keepalive_time = Duration::ZERO;
+    }
+}
+
+/// Silence suppression as a pipeline stage: speech passes, silence is
+/// dropped apart from periodic all-zero keepalive frames.
+pub struct SuppressionStage {
+    suppressor: SilenceSuppressor,
+    sent: u64,
+    keepalive: u64,
+    suppressed: u64,
+}
+
+impl SuppressionStage {
+    pub fn new(config: SilenceSuppressionConfig) -> Self {
+        Self {
+            suppressor: SilenceSuppressor::new(config),
+            sent: 0,
+            keepalive: 0,
+            suppressed: 0,
+        }
+    }
+}
+
+impl Stage for SuppressionStage {
+    fn name(&self) -> &'static str {
+        "suppressSilence"
+    }
+
+    fn process(&mut self, frame: &mut Frame) -> Flow {
+        let pcm: Vec<i16> = frame.mono().into_iter().map(f32_to_i16).collect();
+        match self.suppressor.process(&pcm) {
+            FrameAction::Send(_) => {
+                self.sent += 1;
+                Flow::Continue
+            }
+            FrameAction::SendSilence => {
+                self.keepalive += 1;
+                frame.samples.fill(0.0);
+                Flow::Continue
+            }
+            FrameAction::Suppress => {
+                self.suppressed += 1;
+                Flow::Drop
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.suppressor.reset();
+    }
+
+    fn stats(&self) -> HashMap<String, f64> {
+        HashMap::from([
+            ("sent".to_string(), self.sent as f64),
+            ("keepalive".to_string(), self.keepalive as f64),
+            ("suppressed".to_string(), self.suppressed as f64),
+        ])
+    }
 }
 
 fn calculate_rms(samples: &[i16]) -> f32 {
@@ -93,3 +169,35 @@ fn calculate_rms(samples: &[i16]) -> f32 {
 pub fn generate_silence_frame(size: usize) -> Vec<i16> {
     vec![0i16; size]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(suppressor: &mut SilenceSuppressor, level: i16) -> &'static str {
+        match suppressor.process(&[level; 320]) {
+            FrameAction::Send(_) => "send",
+            FrameAction::SendSilence => "keepalive",
+            FrameAction::Suppress => "suppress",
+        }
+    }
+
+    #[test]
+    fn hangover_and_keepalive_follow_audio_time() {
+        // Processed back to back, far faster than real time.
+        let mut suppressor = SilenceSuppressor::new(SilenceSuppressionConfig {
+            frame_duration: Duration::from_millis(20),
+            ..SilenceSuppressionConfig::default()
+        });
+        assert_eq!(action(&mut suppressor, 1000), "send");
+        // The 200 ms hangover is ten 20 ms frames.
+        for _ in 0..10 {
+            assert_eq!(action(&mut suppressor, 0), "send");
+        }
+        let rest: Vec<_> = (0..6).map(|_| action(&mut suppressor, 0)).collect();
+        assert_eq!(
+            rest,
+            ["keepalive", "suppress", "suppress", "suppress", "suppress", "keepalive"]
+        );
+    }
+}
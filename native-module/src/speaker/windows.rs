@@ -1,3 +1,5 @@
+use crate::downmix::{ChannelLayout, DownmixMode, Downmixer};
+use crate::system_audio::mix_format;
 use napi::bindgen_prelude::*;
 use std::collections::VecDeque;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -63,9 +65,15 @@ fn run_audio_loopback(running: Arc<AtomicBool>, callback: impl Fn(Vec<f32>) + Se
         .get_mixformat()
         .map_err(|e| Error::from_reason(format!("Failed to get mix format: {:?}", e)))?;
 
-    let sample_rate = format.get_samplespersec();
-    let channels = format.get_nchannels();
-    let block_align = format.get_blockalign() as usize;
+    // The wasapi crate's format wraps the same `WAVEFORMATEXTENSIBLE`.
+    let mix = unsafe {
+        mix_format(format.as_waveformatex_ref() as *const _
+            as *const windows::Win32::Media::Audio::WAVEFORMATEX)
+    }
+    .map_err(|e| Error::from_reason(format!("Unsupported mix format: {}", e)))?;
+    let (sample_rate, channels) = (mix.sample_rate, mix.channels);
+    let sample_format = mix.sample_format;
+    let block_align = sample_format.block_align();
 
     println!(
         "Loopback capture: {} Hz, {} channels, block_align: {}",
@@ -103,7 +111,8 @@ fn run_audio_loopback(running: Arc<AtomicBool>, callback: impl Fn(Vec<f32>) + Se
         .start_stream()
         .map_err(|e| Error::from_reason(format!("Failed to start stream: {:?}", e)))?;
 
-    let mut resampler = SimpleResampler::new(sample_rate as usize, channels as usize);
+    let layout = mix.layout;
+    let mut resampler = SimpleResampler::new(sample_rate as usize, layout)?;
     let mut sample_queue: VecDeque<u8> =
         VecDeque::with_capacity(block_align * (buffer_size as usize + 1024) * 4);
 
@@ -132,7 +141,7 @@ fn run_audio_loopback(running: Arc<AtomicBool>, callback: impl Fn(Vec<f32>) + Se
 
         if sample_queue.len() >= block_align * 256 {
             let bytes: Vec<u8> = sample_queue.drain(..block_align * 256).collect();
-            let samples = bytes_to_f32(&bytes, channels as usize);
+            let samples = sample_format.decode(&bytes);
             if let Some(resampled) =
                 resampler.process(samples, sample_rate as usize, channels as usize)
             {
@@ -145,27 +154,20 @@ fn run_audio_loopback(running: Arc<AtomicBool>, callback: impl Fn(Vec<f32>) + Se
     Ok(())
 }
 
-fn bytes_to_f32(bytes: &[u8], channels: usize) -> Vec<f32> {
-    let mut samples = Vec::with_capacity(bytes.len() / 4);
-    for chunk in bytes.chunks_exact(4) {
-        let sample = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
-        samples.push(sample);
-    }
-    samples
-}
-
 struct SimpleResampler {
     target_rate: usize,
+    downmixer: Downmixer,
     leftover: Vec<f32>,
 }
 
 impl SimpleResampler {
-    fn new(source_rate: usize, _channels: usize) -> Self {
+    fn new(source_rate: usize, layout: ChannelLayout) -> Result<Self> {
         let target_rate = 16000usize;
-        SimpleResampler {
+        Ok(SimpleResampler {
             target_rate,
+            downmixer: Downmixer::new(DownmixMode::Average, layout).map_err(Error::from_reason)?,
             leftover: Vec::new(),
-        }
+        })
     }
 
     fn process(
@@ -178,17 +180,8 @@ impl SimpleResampler {
             return None;
         }
 
-        let is_stereo = channels >= 2;
-
-        let mut mono: Vec<f32> = if is_stereo {
-            samples
-                .chunks(channels as usize)
-                .map(|chunk| {
-                    let left = chunk.get(0).copied().unwrap_or(0.0);
-                    let right = chunk.get(1).copied().unwrap_or(0.0);
-                    (left + right) / 2.0
-                })
-                .collect()
+        let mut mono: Vec<f32> = if channels > 1 {
+            self.downmixer.process(&samples)
         } else {
             samples
         };
@@ -0,0 +1,30 @@
+use crate::loudness::LoudnessSummary;
+
+#[napi(object)]
+#[derive(Clone, Default)]
+pub struct CaptureStats {
+    pub momentary_lufs: Option<f64>,
+    pub short_term_lufs: Option<f64>,
+    pub integrated_lufs: Option<f64>,
+    pub true_peak_dbtp: Option<f64>,
+    /// Stretches the device delivered no audio for, and their total length.
+    pub gap_count: u32,
+    pub gap_ms: f64,
+    /// Device clock rate relative to the system clock in parts per million
+    /// (positive when fast), once enough audio has been observed.
+    pub drift_ppm: Option<f64>,
+}
+
+impl CaptureStats {
+    pub fn set_loudness(&mut self, loudness: LoudnessSummary) {
+        self.momentary_lufs = loudness.momentary_lufs;
+        self.short_term_lufs = loudness.short_term_lufs;
+        self.integrated_lufs = loudness.integrated_lufs;
+        self.true_peak_dbtp = loudness.true_peak_dbtp;
+    }
+
+    pub fn add_gap(&mut self, duration_ms: f64) {
+        self.gap_count += 1;
+        self.gap_ms += duration_ms;
+    }
+}
@@ -1,4 +1,7 @@
+use crate::conversion::f32_to_i16;
+
 pub struct StreamingResampler {
+    nominal_ratio: f64,
     ratio: f64,
     fractional_pos: f64,
     prev_sample: f32,
@@ -9,6 +12,7 @@ impl StreamingResampler {
     pub fn new(input_sample_rate: f64, output_sample_rate: f64) -> Self {
         let ratio = input_sample_rate / output_sample_rate;
         Self {
+            nominal_ratio: ratio,
             ratio,
             fractional_pos: 0.0,
             prev_sample: 0.0,
@@ -16,7 +20,18 @@ impl StreamingResampler {
         }
     }
 
+    /// Corrects for an input clock running `ppm` parts per million fast
+    /// (positive) or slow relative to the output clock. Takes effect from
+    /// the next sample, so it can be adjusted continuously.
+    pub fn set_drift_ppm(&mut self, ppm: f64) {
+        self.ratio = self.nominal_ratio * (1.0 + ppm * 1e-6);
+    }
+
     pub fn resample(&mut self, input: &[f32]) -> Vec<i16> {
+        self.process(input).into_iter().map(f32_to_i16).collect()
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
         if input.is_empty() {
             return Vec::new();
         }
@@ -51,8 +66,7 @@ impl StreamingResampler {
             };
 
             let interpolated = sample_a + (frac as f32) * (sample_b - sample_a);
-            let scaled = (interpolated * 32767.0).clamp(-32768.0, 32767.0);
-            output.push(scaled as i16);
+            output.push(interpolated);
 
             self.fractional_pos += self.ratio;
         }
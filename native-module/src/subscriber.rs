@@ -0,0 +1,836 @@
+use napi::{Env, JsFunction};
+use std::time::{Duration, Instant};
+
+use crate::agc::AgcStage;
+use crate::audio_config::{FRAME_MS, SAMPLE_RATE};
+use crate::conversion::{linear_to_alaw, linear_to_mulaw, ConversionConfig, I16Converter};
+use crate::delivery::{DeliveryConfig, DeliveryQueue, DeliveryStats, OverflowPolicy};
+use crate::denoise::DenoiseStage;
+use crate::downmix::{ChannelLayout, DownmixMode, Downmixer};
+use crate::filters::{FilterChain, FilterConfig, FilterOptions, FilterStage};
+use crate::framing::{Chunk, Framer, FramingConfig, OutputFormat, Payload, StreamFormat};
+use crate::pacer::Pacer;
+use crate::pipeline::{Flow, Frame, Pipeline, PipelineStats, Stage, StageKind, DEFAULT_STAGES};
+use crate::silence_suppression::{SilenceSuppressionConfig, SuppressionStage};
+use crate::streaming_resampler::StreamingResampler;
+use crate::vad::VadStage;
+use crate::websocket::WebSocketSink;
+
+#[napi(object)]
+#[derive(Default)]
+pub struct SubscriberOptions {
+    pub sample_rate: Option<u32>,
+    /// `"i16"` (little-endian, default), `"i16be"`, `"f32"` (little-endian),
+    /// `"mulaw"` or `"alaw"` (G.711, one byte per sample).
+    pub sample_type: Option<String>,
+    /// TPDF dither when converting to an integer format. Default false.
+    pub dither: Option<bool>,
+    /// Soft-clip peaks near full scale when converting to an integer format
+    /// instead of clipping them flat. Default false.
+    pub soft_clip: Option<bool>,
+    pub suppress_silence: Option<bool>,
+    /// Processing frame, default 20 ms: silence suppression decides per
+    /// frame. Also the delivery size unless `chunkMs` is given.
+    pub frame_ms: Option<u32>,
+    /// `"average"` (default), `"itu"`, `"stereo"` or `"channel"`.
+    pub downmix: Option<String>,
+    /// Source channel index for `downmix: "channel"`. A subscriber asking
+    /// for a channel the device lacks is removed once capture starts.
+    pub channel: Option<u32>,
+    /// Deliver exactly one frame every `frameMs` of wall-clock time, filling
+    /// gaps with silence. Adds about two frames of latency.
+    pub paced: Option<bool>,
+    /// Fill stretches the device delivered nothing for with silence, keeping
+    /// the stream in step with wall time. Default true.
+    pub fill_gaps: Option<bool>,
+    /// High-pass corner in Hz applied before silence suppression, e.g. 20 to
+    /// remove DC offset and rumble. Default 0 (off).
+    pub high_pass_hz: Option<f64>,
+    /// EQ bands applied after the high-pass, in order.
+    pub eq: Option<Vec<FilterOptions>>,
+    /// Spectral noise reduction of steady background noise. Adds about
+    /// 20 ms of latency. Default false.
+    pub denoise: Option<bool>,
+    /// Automatic gain control towards this RMS level in dBFS, e.g. -20.
+    /// Default none (off).
+    pub agc_target_db: Option<f64>,
+    /// Drop every frame the speech detector calls silence, without the
+    /// keepalive frames of `suppressSilence`. Default false.
+    pub vad: Option<bool>,
+    /// Processing stages in order: any of `"highPass"`, `"denoise"`,
+    /// `"eq"`, `"agc"`, `"vad"` and `"suppressSilence"`. Stages left out are
+    /// skipped. Default is that order. Stages see the audio already
+    /// downmixed and resampled; encoding to `sampleType` always comes last.
+    pub stages: Option<Vec<String>>,
+    /// `"binary"` (default) delivers a `Buffer`; `"base64"` a base64 string
+    /// of the same bytes; `"json"` a JSON string
+    /// `{"type", "seq", "timestampMs", "durationMs", "wallClockMs",
+    /// "sampleRate", "channels", "sampleType", "audio"}` with base64 audio.
+    /// Strings go out as text messages on a WebSocket.
+    pub format: Option<String>,
+    /// Audio per delivery, independent of `frameMs`, e.g. 100 to cut the
+    /// callbacks to ten a second. Default one frame. Chunks never span
+    /// suppressed silence, so they can be shorter.
+    pub chunk_ms: Option<u32>,
+    /// Deliver a partly filled chunk once its oldest audio has waited this
+    /// long. Default none: chunks are sent when full.
+    pub max_latency_ms: Option<u32>,
+    /// `"type"` of each JSON envelope, e.g. `"input_audio_buffer.append"`.
+    /// Left out by default.
+    pub json_type: Option<String>,
+    /// Name of the JSON field holding the audio, default `"audio"`.
+    pub json_audio_field: Option<String>,
+    /// Include `seq`, the timestamps and the format fields in JSON
+    /// envelopes, default true. Turn off for APIs that reject unknown
+    /// fields.
+    pub json_metadata: Option<bool>,
+    /// Audio allowed to wait for the JS thread, default 2000 ms. Beyond it
+    /// the `overflow` policy applies.
+    pub max_queue_ms: Option<u32>,
+    /// `"dropOldest"` (default) drops the oldest waiting chunk; the next
+    /// chunk delivered then carries a `DeliveryGap` as the callback's second
+    /// argument. `"coalesce"` holds new audio back while the queue is full
+    /// and delivers it as one larger chunk, dropping only audio older than
+    /// `maxQueueMs`.
+    pub overflow: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SampleType {
+    I16,
+    I16Be,
+    F32,
+    MuLaw,
+    ALaw,
+}
+
+impl SampleType {
+    fn parse(value: &str) -> napi::Result<Self> {
+        match value {
+            "i16" => Ok(SampleType::I16),
+            "i16be" => Ok(SampleType::I16Be),
+            "f32" => Ok(SampleType::F32),
+            "mulaw" => Ok(SampleType::MuLaw),
+            "alaw" => Ok(SampleType::ALaw),
+            other => Err(napi::Error::from_reason(format!(
+                "Unknown sample type: {}",
+                other
+            ))),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SampleType::I16 => "i16",
+            SampleType::I16Be => "i16be",
+            SampleType::F32 => "f32",
+            SampleType::MuLaw => "mulaw",
+            SampleType::ALaw => "alaw",
+        }
+    }
+
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            SampleType::I16 | SampleType::I16Be => 2,
+            SampleType::F32 => 4,
+            SampleType::MuLaw | SampleType::ALaw => 1,
+        }
+    }
+
+    /// Encoded silence: zero bytes except for the G.711 laws.
+    fn silence(self, samples: usize) -> Vec<u8> {
+        match self {
+            SampleType::I16 | SampleType::I16Be => vec![0; samples * 2],
+            SampleType::F32 => vec![0; samples * 4],
+            SampleType::MuLaw => vec![linear_to_mulaw(0); samples],
+            SampleType::ALaw => vec![linear_to_alaw(0); samples],
+        }
+    }
+}
+
+pub struct SubscriberConfig {
+    pub sample_rate: u32,
+    pub sample_type: SampleType,
+    pub conversion: ConversionConfig,
+    pub suppress_silence: bool,
+    pub frame_ms: u32,
+    pub downmix: DownmixMode,
+    pub paced: bool,
+    pub fill_gaps: bool,
+    pub filters: FilterConfig,
+    pub denoise: bool,
+    pub agc_target_db: Option<f64>,
+    pub vad: bool,
+    pub stages: Vec<StageKind>,
+    pub framing: FramingConfig,
+    pub delivery: DeliveryConfig,
+}
+
+impl Default for SubscriberConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: SAMPLE_RATE,
+            sample_type: SampleType::I16,
+            conversion: ConversionConfig::default(),
+            suppress_silence: true,
+            frame_ms: FRAME_MS,
+            downmix: DownmixMode::Average,
+            paced: false,
+            fill_gaps: true,
+            filters: FilterConfig::default(),
+            denoise: false,
+            agc_target_db: None,
+            vad: false,
+            stages: DEFAULT_STAGES.to_vec(),
+            framing: FramingConfig::default(),
+            delivery: DeliveryConfig::default(),
+        }
+    }
+}
+
+impl SubscriberConfig {
+    pub fn from_options(options: Option<SubscriberOptions>) -> napi::Result<Self> {
+        let mut config = Self::default();
+        let options = match options {
+            Some(o) => o,
+            None => return Ok(config),
+        };
+
+        if let Some(rate) = options.sample_rate {
+            if !(8_000..=192_000).contains(&rate) {
+                return Err(napi::Error::from_reason(format!(
+                    "Unsupported sample rate: {}",
+                    rate
+                )));
+            }
+            config.sample_rate = rate;
+        }
+        if let Some(ref sample_type) = options.sample_type {
+            config.sample_type = SampleType::parse(sample_type)?;
+        }
+        config.conversion = ConversionConfig {
+            dither: options.dither.unwrap_or(false),
+            soft_clip: options.soft_clip.unwrap_or(false),
+        };
+        if let Some(suppress) = options.suppress_silence {
+            config.suppress_silence = suppress;
+        }
+        if let Some(frame_ms) = options.frame_ms {
+            if frame_ms == 0 || frame_ms > 1000 {
+                return Err(napi::Error::from_reason(format!(
+                    "Unsupported frame duration: {} ms",
+                    frame_ms
+                )));
+            }
+            config.frame_ms = frame_ms;
+        }
+        if let Some(ref downmix) = options.downmix {
+            config.downmix =
+                DownmixMode::parse(downmix, options.channel).map_err(napi::Error::from_reason)?;
+        }
+        config.paced = options.paced.unwrap_or(false);
+        config.fill_gaps = options.fill_gaps.unwrap_or(true);
+        config.filters = FilterConfig::from_options(options.high_pass_hz, options.eq)?;
+        config.denoise = options.denoise.unwrap_or(false);
+        if let Some(target) = options.agc_target_db {
+            if !(-60.0..=0.0).contains(&target) {
+                return Err(napi::Error::from_reason(format!(
+                    "Unsupported AGC target: {} dBFS",
+                    target
+                )));
+            }
+            config.agc_target_db = Some(target);
+        }
+        config.vad = options.vad.unwrap_or(false);
+        if let Some(ref stages) = options.stages {
+            config.stages = StageKind::parse_list(stages)?;
+        }
+        if let Some(ref format) = options.format {
+            config.framing.format = OutputFormat::parse(format)?;
+        }
+        if let Some(chunk_ms) = options.chunk_ms {
+            if chunk_ms == 0 || chunk_ms > 10_000 {
+                return Err(napi::Error::from_reason(format!(
+                    "Unsupported chunk duration: {} ms",
+                    chunk_ms
+                )));
+            }
+            config.framing.chunk_ms = Some(chunk_ms);
+        }
+        if let Some(max_latency_ms) = options.max_latency_ms {
+            if max_latency_ms == 0 {
+                return Err(napi::Error::from_reason("Maximum latency must be positive"));
+            }
+            config.framing.max_latency = Some(Duration::from_millis(max_latency_ms as u64));
+        }
+        config.framing.json_type = options.json_type;
+        if let Some(field) = options.json_audio_field {
+            config.framing.audio_field = field;
+        }
+        config.framing.metadata = options.json_metadata.unwrap_or(true);
+        if let Some(max_queue_ms) = options.max_queue_ms {
+            if max_queue_ms == 0 {
+                return Err(napi::Error::from_reason("Queue duration must be positive"));
+            }
+            config.delivery.max_queue = Duration::from_millis(max_queue_ms as u64);
+        }
+        if let Some(ref overflow) = options.overflow {
+            config.delivery.policy = OverflowPolicy::parse(overflow)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Samples per frame across all output channels.
+    pub fn frame_samples(&self) -> usize {
+        (self.sample_rate as usize * self.frame_ms as usize) / 1000 * self.downmix.output_channels()
+    }
+
+    /// Audio per delivery.
+    pub fn chunk_ms(&self) -> u32 {
+        self.framing.chunk_ms.unwrap_or(self.frame_ms)
+    }
+
+    fn framer(&self) -> Framer {
+        Framer::new(
+            self.framing.clone(),
+            StreamFormat {
+                sample_rate: self.sample_rate,
+                channels: self.downmix.output_channels(),
+                sample_type: self.sample_type.name(),
+                bytes_per_sample: self.sample_type.bytes_per_sample(),
+                silence: self.sample_type.silence(1)[0],
+            },
+        )
+    }
+
+    /// The enabled stages in their configured order.
+    fn pipeline(&self) -> Pipeline {
+        let channels = self.downmix.output_channels();
+        let stages = self
+            .stages
+            .iter()
+            .filter_map(|&kind| -> Option<Box<dyn Stage>> {
+                match kind {
+                    StageKind::HighPass if self.filters.high_pass_hz > 0.0 => {
+                        Some(Box::new(FilterStage::new(
+                            "highPass",
+                            FilterChain::high_pass(self.sample_rate, self.filters.high_pass_hz),
+                            channels,
+                        )))
+                    }
+                    StageKind::Denoise if self.denoise => {
+                        Some(Box::new(DenoiseStage::new(self.sample_rate, channels)))
+                    }
+                    StageKind::Eq if !self.filters.eq.is_empty() => Some(Box::new(
+                        FilterStage::new("eq", self.filters.eq_chain(self.sample_rate), channels),
+                    )),
+                    StageKind::Agc => self.agc_target_db.map(|target| -> Box<dyn Stage> {
+                        Box::new(AgcStage::new(self.sample_rate, target))
+                    }),
+                    StageKind::Vad if self.vad => Some(Box::new(VadStage::new(
+                        Duration::from_millis(self.frame_ms as u64),
+                    ))),
+                    StageKind::SuppressSilence if self.suppress_silence => {
+                        Some(Box::new(SuppressionStage::new(SilenceSuppressionConfig {
+                            frame_duration: Duration::from_millis(self.frame_ms as u64),
+                            ..SilenceSuppressionConfig::default()
+                        })))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        Pipeline::new(stages)
+    }
+}
+
+/// Per-input-format state: rebuilt whenever the device rate or layout changes.
+struct Chain {
+    input_sample_rate: u32,
+    downmixer: Downmixer,
+    resamplers: Vec<StreamingResampler>,
+}
+
+/// Where a subscriber's encoded chunks go.
+pub enum Output {
+    Callback(DeliveryQueue),
+    /// Sent straight to a server without crossing the JS event loop.
+    WebSocket(WebSocketSink),
+    /// Handed to native code, e.g. file processing.
+    Sink(Box<dyn Fn(Payload) + Send>),
+}
+
+impl Output {
+    pub fn callback(
+        env: Env,
+        callback: &JsFunction,
+        config: &SubscriberConfig,
+    ) -> napi::Result<Self> {
+        let queue = DeliveryQueue::new(env, callback, config.delivery, config.chunk_ms())?;
+        Ok(Output::Callback(queue))
+    }
+}
+
+/// One consumer of a capture stream. Each subscriber downmixes, resamples,
+/// frames and suppresses the shared device audio independently, so consumers
+/// with different output formats can share a single loopback stream.
+pub struct Subscriber {
+    id: u32,
+    config: SubscriberConfig,
+    chain: Option<Chain>,
+    frame_buffer: Vec<f32>,
+    pipeline: Pipeline,
+    converter: I16Converter,
+    pacer: Option<Pacer>,
+    framer: Framer,
+    drift_ppm: f64,
+    output: Output,
+}
+
+impl Subscriber {
+    pub fn new(id: u32, config: SubscriberConfig, output: Output) -> Self {
+        let pacer = if config.paced {
+            Some(Pacer::new(Duration::from_millis(config.frame_ms as u64)))
+        } else {
+            None
+        };
+
+        Self {
+            id,
+            frame_buffer: Vec::with_capacity(config.frame_samples() * 4),
+            pipeline: config.pipeline(),
+            converter: I16Converter::new(config.conversion),
+            framer: config.framer(),
+            config,
+            chain: None,
+            pacer,
+            drift_ppm: 0.0,
+            output,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Compensates the resamplers for the device clock's measured drift.
+    pub fn set_drift_ppm(&mut self, ppm: f64) {
+        self.drift_ppm = ppm;
+        if let Some(ref mut chain) = self.chain {
+            for resampler in chain.resamplers.iter_mut() {
+                resampler.set_drift_ppm(ppm);
+            }
+        }
+    }
+
+    /// Forgets the audio of a previous capture run: the partial frame,
+    /// resampler, stage and pacing state. Sequence numbers carry on.
+    pub fn reset(&mut self) {
+        self.chain = None;
+        self.frame_buffer.clear();
+        self.pipeline.reset();
+        if self.pacer.is_some() {
+            self.pacer = Some(Pacer::new(Duration::from_millis(
+                self.config.frame_ms as u64,
+            )));
+        }
+    }
+
+    pub fn fills_gaps(&self) -> bool {
+        self.config.fill_gaps
+    }
+
+    /// Feeds interleaved device samples through this subscriber's chain and
+    /// delivers every complete frame to its callback. Fails if the requested
+    /// downmix does not fit the device layout.
+    pub fn push(
+        &mut self,
+        samples: &[f32],
+        input_sample_rate: u32,
+        layout: &ChannelLayout,
+    ) -> Result<(), String> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        let output_rate = self.config.sample_rate as f64;
+        let downmix = self.config.downmix;
+        let drift_ppm = self.drift_ppm;
+        let chain = match self.chain {
+            Some(ref mut chain)
+                if chain.input_sample_rate == input_sample_rate
+                    && chain.downmixer.layout() == layout =>
+            {
+                chain
+            }
+            _ => self.chain.insert(Chain {
+                input_sample_rate,
+                downmixer: Downmixer::new(downmix, layout.clone())?,
+                resamplers: (0..downmix.output_channels())
+                    .map(|_| {
+                        let mut resampler =
+                            StreamingResampler::new(input_sample_rate as f64, output_rate);
+                        resampler.set_drift_ppm(drift_ppm);
+                        resampler
+                    })
+                    .collect(),
+            }),
+        };
+
+        let mixed = chain.downmixer.process(samples);
+        if chain.resamplers.len() == 1 {
+            self.frame_buffer
+                .extend(chain.resamplers[0].process(&mixed));
+        } else {
+            let channels = chain.resamplers.len();
+            let resampled: Vec<Vec<f32>> = chain
+                .resamplers
+                .iter_mut()
+                .enumerate()
+                .map(|(ch, resampler)| {
+                    let plane: Vec<f32> =
+                        mixed.iter().skip(ch).step_by(channels).copied().collect();
+                    resampler.process(&plane)
+                })
+                .collect();
+            let len = resampled.iter().map(Vec::len).min().unwrap_or(0);
+            for i in 0..len {
+                for plane in &resampled {
+                    self.frame_buffer.push(plane[i]);
+                }
+            }
+        }
+
+        let frame_samples = self.config.frame_samples();
+        while self.frame_buffer.len() >= frame_samples {
+            let frame: Vec<f32> = self.frame_buffer.drain(0..frame_samples).collect();
+            self.emit_frame(frame);
+        }
+        Ok(())
+    }
+
+    pub fn pipeline_stats(&self) -> PipelineStats {
+        PipelineStats {
+            clipped_samples: self.converter.clipped() as f64,
+            soft_clipped_samples: self.converter.soft_clipped() as f64,
+            ..self.pipeline.stats()
+        }
+    }
+
+    fn emit_frame(&mut self, samples: Vec<f32>) {
+        let channels = self.config.downmix.output_channels();
+        let mut frame = Frame::new(samples, channels);
+        if self.pipeline.process(&mut frame) == Flow::Continue {
+            let bytes = encode(&frame.samples, self.config.sample_type, &mut self.converter);
+            self.queue_or_deliver(bytes);
+        } else if self.pacer.is_none() {
+            // Paced streams fill the slot with silence instead.
+            let samples = frame.samples.len() / channels;
+            if let Some(chunk) = self.framer.skip(samples, Instant::now()) {
+                self.deliver(chunk);
+            }
+        }
+    }
+
+    /// Delivers everything still buffered: the partial frame, paced frames
+    /// not yet due and the chunk in progress. Called when the input ends.
+    pub fn finish(&mut self) {
+        if !self.frame_buffer.is_empty() {
+            let tail = std::mem::take(&mut self.frame_buffer);
+            self.emit_frame(tail);
+        }
+        let queued = self.pacer.as_mut().map(Pacer::drain).unwrap_or_default();
+        let now = Instant::now();
+        for bytes in queued {
+            self.frame_out(bytes, now);
+        }
+        self.flush();
+    }
+
+    /// Delivers a partly filled or held chunk.
+    fn flush(&mut self) {
+        self.coalesce();
+        if let Some(chunk) = self.framer.release().or_else(|| self.framer.flush()) {
+            self.deliver(chunk);
+        }
+    }
+
+    /// Queue depth, drops and latency of callback delivery; `None` for
+    /// other outputs.
+    pub fn delivery_stats(&self) -> Option<DeliveryStats> {
+        match self.output {
+            Output::Callback(ref queue) => Some(queue.stats()),
+            _ => None,
+        }
+    }
+
+    /// Releases paced frames whose slot has come, substituting silence when
+    /// the device delivered nothing, and chunks held past the maximum
+    /// latency. Called on every capture loop iteration.
+    pub fn tick(&mut self, now: Instant) {
+        if let Some(pacer) = self.pacer.as_mut() {
+            let silence = self.config.sample_type.silence(self.config.frame_samples());
+            let frames = pacer.due(now, || silence.clone());
+            for bytes in frames {
+                self.frame_out(bytes, now);
+            }
+        }
+        self.coalesce();
+        if let Some(chunk) = self.framer.due(now) {
+            self.deliver(chunk);
+        }
+    }
+
+    fn queue_or_deliver(&mut self, bytes: Vec<u8>) {
+        match self.pacer.as_mut() {
+            Some(pacer) => pacer.enqueue(bytes),
+            None => self.frame_out(bytes, Instant::now()),
+        }
+    }
+
+    fn frame_out(&mut self, bytes: Vec<u8>, now: Instant) {
+        self.coalesce();
+        for chunk in self.framer.push(bytes, now) {
+            self.deliver(chunk);
+        }
+    }
+
+    /// With the `coalesce` policy, holds audio back in the framer while the
+    /// callback queue is full and queues it as one chunk once there is room.
+    fn coalesce(&mut self) {
+        let queue = match self.output {
+            Output::Callback(ref queue) if queue.policy() == OverflowPolicy::Coalesce => queue,
+            _ => return,
+        };
+        // Reported first: the dropped audio precedes the released chunk.
+        let dropped_ms = self.framer.take_dropped_ms();
+        if dropped_ms > 0.0 {
+            queue.note_dropped(dropped_ms);
+        }
+        if queue.is_full() {
+            self.framer.hold(queue.max_queue());
+        } else if let Some(chunk) = self.framer.release() {
+            queue.push(chunk);
+        }
+    }
+
+    fn deliver(&self, chunk: Chunk) {
+        match self.output {
+            Output::Callback(ref queue) => queue.push(chunk),
+            Output::WebSocket(ref sink) => sink.send(chunk.payload),
+            Output::Sink(ref sink) => sink(chunk.payload),
+        }
+    }
+}
+
+fn encode(frame: &[f32], sample_type: SampleType, converter: &mut I16Converter) -> Vec<u8> {
+    match sample_type {
+        SampleType::F32 => frame.iter().flat_map(|s| s.to_le_bytes()).collect(),
+        SampleType::I16 => converter
+            .convert(frame)
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect(),
+        SampleType::I16Be => converter
+            .convert(frame)
+            .iter()
+            .flat_map(|s| s.to_be_bytes())
+            .collect(),
+        SampleType::MuLaw => converter
+            .convert(frame)
+            .into_iter()
+            .map(linear_to_mulaw)
+            .collect(),
+        SampleType::ALaw => converter
+            .convert(frame)
+            .into_iter()
+            .map(linear_to_alaw)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn sink() -> (Output, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let writer = received.clone();
+        let output = Output::Sink(Box::new(move |payload| {
+            if let Payload::Binary(bytes) = payload {
+                writer.lock().unwrap().push(bytes);
+            }
+        }));
+        (output, received)
+    }
+
+    /// 48 kHz mono i16 without filtering or suppression: samples pass
+    /// through unchanged.
+    fn passthrough() -> SubscriberConfig {
+        SubscriberConfig::from_options(Some(SubscriberOptions {
+            sample_rate: Some(48_000),
+            suppress_silence: Some(false),
+            high_pass_hz: Some(0.0),
+            ..SubscriberOptions::default()
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        let invalid = [
+            SubscriberOptions {
+                sample_rate: Some(4_000),
+                ..SubscriberOptions::default()
+            },
+            SubscriberOptions {
+                sample_type: Some("u8".to_string()),
+                ..SubscriberOptions::default()
+            },
+            SubscriberOptions {
+                frame_ms: Some(0),
+                ..SubscriberOptions::default()
+            },
+            SubscriberOptions {
+                chunk_ms: Some(20_000),
+                ..SubscriberOptions::default()
+            },
+            SubscriberOptions {
+                downmix: Some("surround".to_string()),
+                ..SubscriberOptions::default()
+            },
+            SubscriberOptions {
+                max_queue_ms: Some(0),
+                ..SubscriberOptions::default()
+            },
+            SubscriberOptions {
+                overflow: Some("block".to_string()),
+                ..SubscriberOptions::default()
+            },
+            SubscriberOptions {
+                agc_target_db: Some(6.0),
+                ..SubscriberOptions::default()
+            },
+        ];
+        for options in invalid {
+            assert!(SubscriberConfig::from_options(Some(options)).is_err());
+        }
+
+        let config = SubscriberConfig::from_options(None).unwrap();
+        assert_eq!(
+            (config.sample_rate, config.frame_ms),
+            (SAMPLE_RATE, FRAME_MS)
+        );
+        assert!(config.suppress_silence);
+    }
+
+    #[test]
+    fn delivers_whole_frames_and_flushes_the_tail_on_finish() {
+        let (output, received) = sink();
+        let mut subscriber = Subscriber::new(1, passthrough(), output);
+        let mono = ChannelLayout::default_for(1);
+
+        // 30 ms: one 20 ms frame, 10 ms left over.
+        subscriber.push(&[0.25; 1_440], 48_000, &mono).unwrap();
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert_eq!(received.lock().unwrap()[0].len(), 960 * 2);
+
+        subscriber.finish();
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 2);
+            assert_eq!(received[1].len(), 480 * 2);
+            assert_eq!(i16::from_le_bytes([received[1][0], received[1][1]]), 8_192);
+        }
+
+        // Nothing is left to flush a second time.
+        subscriber.finish();
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn finish_delivers_paced_frames_not_yet_due() {
+        let (output, received) = sink();
+        let config = SubscriberConfig {
+            paced: true,
+            ..passthrough()
+        };
+        let mut subscriber = Subscriber::new(1, config, output);
+
+        subscriber
+            .push(&[0.25; 2_880], 48_000, &ChannelLayout::default_for(1))
+            .unwrap();
+        subscriber.finish();
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn reset_forgets_the_previous_run() {
+        let (output, received) = sink();
+        let config = SubscriberConfig {
+            stages: vec![StageKind::Denoise],
+            denoise: true,
+            ..passthrough()
+        };
+        let mut subscriber = Subscriber::new(1, config, output);
+        let mono = ChannelLayout::default_for(1);
+
+        subscriber.push(&[0.25; 1_440], 48_000, &mono).unwrap();
+        assert_eq!(subscriber.pipeline_stats().stages[0].name, "denoise");
+        subscriber.reset();
+        subscriber.finish();
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn coalescing_holds_the_newest_audio_while_the_queue_is_full() {
+        let calls = Arc::new(Mutex::new(0));
+        let counter = calls.clone();
+        let config = SubscriberConfig {
+            delivery: DeliveryConfig {
+                max_queue: Duration::from_millis(40),
+                policy: OverflowPolicy::Coalesce,
+            },
+            ..passthrough()
+        };
+        let queue = DeliveryQueue::with_notify(config.delivery, config.chunk_ms(), move || {
+            *counter.lock().unwrap() += 1;
+        });
+        let receiver = queue.receiver();
+        let mut subscriber = Subscriber::new(1, config, Output::Callback(queue));
+        let mono = ChannelLayout::default_for(1);
+        let mut frame = |level: f32| subscriber.push(&[level; 960], 48_000, &mono).unwrap();
+
+        // Two 20 ms frames fill the queue; the next three are held and only
+        // the newest 40 ms of them kept.
+        for k in 1..=5 {
+            frame(0.125 * k as f32);
+        }
+        assert_eq!(*calls.lock().unwrap(), 2);
+        assert!(receiver.next().is_some() && receiver.next().is_some());
+
+        frame(0.75);
+        assert_eq!(*calls.lock().unwrap(), 4);
+        let held = receiver.next().unwrap();
+        let bytes = match held.payload {
+            Payload::Binary(bytes) => bytes,
+            Payload::Text(_) => unreachable!(),
+        };
+        assert_eq!(bytes.len(), 2 * 960 * 2);
+        // Frames 4 and 5, judged by their ends: the resampler delays the
+        // audio by a few samples.
+        let end_of =
+            |frame: usize| i16::from_le_bytes([bytes[frame * 1920 - 2], bytes[frame * 1920 - 1]]);
+        assert_eq!((end_of(1), end_of(2)), (16_384, 20_480));
+        let gap = held.gap.unwrap();
+        assert_eq!((gap.dropped_chunks, gap.dropped_ms), (0, 20.0));
+
+        let next = receiver.next().unwrap();
+        assert!(next.gap.is_none());
+        assert!(receiver.next().is_none());
+        let stats = subscriber.delivery_stats().unwrap();
+        assert_eq!((stats.delivered_chunks, stats.dropped_ms), (4.0, 20.0));
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::{Duration, Instant};
+
+/// How far the timeline may fall behind wall time before a gap is declared.
+/// Comfortably above a device period plus scheduling jitter.
+const GAP_TOLERANCE: Duration = Duration::from_millis(100);
+
+/// Keeps a capture timeline consistent with wall time. Loopback streams stop
+/// delivering packets while nothing plays; this reports how many frames are
+/// missing, from the device position where the backend has one and from the
+/// wall clock otherwise.
+pub struct GapTracker {
+    sample_rate: u32,
+    /// Device position expected for the next packet, counting synthesized
+    /// frames.
+    next_position: Option<u64>,
+    /// Wall time up to which the timeline has been covered.
+    covered_until: Instant,
+    in_gap: bool,
+}
+
+impl GapTracker {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            next_position: None,
+            covered_until: Instant::now(),
+            in_gap: false,
+        }
+    }
+
+    /// Restarts the timeline, e.g. when the stream starts playing.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.sample_rate);
+    }
+
+    /// A packet of `frames` arrived at `now`, starting at device frame
+    /// `position` if known. Returns the frames missing before it.
+    pub fn packet(&mut self, position: Option<u64>, frames: u64, now: Instant) -> u64 {
+        let gap = match (position, self.next_position) {
+            (Some(position), Some(expected)) => position.saturating_sub(expected),
+            _ => {
+                let behind = now.saturating_duration_since(self.covered_until);
+                if self.in_gap || behind >= GAP_TOLERANCE {
+                    self.duration_to_frames(behind)
+                } else {
+                    0
+                }
+            }
+        };
+
+        self.in_gap = false;
+        self.next_position = Some(match position {
+            Some(position) => position + frames,
+            None => self.next_position.unwrap_or(0) + gap + frames,
+        });
+        self.covered_until = self.covered_until.max(now) + self.frames_to_duration(frames);
+        gap
+    }
+
+    /// Nothing arrived by `now`. Once the timeline is more than the
+    /// tolerance behind, returns the frames needed to catch up to wall time,
+    /// and keeps doing so on every call until packets resume.
+    pub fn idle(&mut self, now: Instant) -> u64 {
+        let behind = now.saturating_duration_since(self.covered_until);
+        if !self.in_gap && behind < GAP_TOLERANCE {
+            return 0;
+        }
+
+        self.in_gap = true;
+        let frames = self.duration_to_frames(behind);
+        self.covered_until += self.frames_to_duration(frames);
+        if let Some(ref mut next) = self.next_position {
+            *next += frames;
+        }
+        frames
+    }
+
+    fn duration_to_frames(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * self.sample_rate as f64) as u64
+    }
+
+    fn frames_to_duration(&self, frames: u64) -> Duration {
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    /// Tracker whose timeline is covered up to `start + 100 ms`.
+    fn tracker(start: Instant) -> GapTracker {
+        let mut tracker = GapTracker::new(RATE);
+        assert_eq!(tracker.packet(None, 4_800, start), 0);
+        tracker
+    }
+
+    fn assert_frames(actual: u64, expected: u64) {
+        assert!(actual.abs_diff(expected) <= 1, "{} frames", actual);
+    }
+
+    #[test]
+    fn steady_packets_leave_no_gap() {
+        let start = Instant::now();
+        let mut tracker = tracker(start);
+        for i in 1..=50 {
+            // Arrival jitter well inside the tolerance.
+            let arrival = ms(start, 100 * i + if i % 2 == 0 { 60 } else { 0 });
+            assert_eq!(tracker.packet(None, 4_800, arrival), 0);
+        }
+    }
+
+    #[test]
+    fn idle_time_beyond_the_tolerance_becomes_a_gap() {
+        let start = Instant::now();
+        let mut tracker = tracker(start);
+        assert_eq!(tracker.idle(ms(start, 150)), 0);
+
+        // 125 ms behind: caught up in full, then in step with wall time.
+        assert_frames(tracker.idle(ms(start, 225)), 6_000);
+        assert_frames(tracker.idle(ms(start, 250)), 1_200);
+        // The first packet after the gap is preceded by the rest of it.
+        assert_frames(tracker.packet(None, 480, ms(start, 275)), 1_200);
+        assert_eq!(tracker.packet(None, 480, ms(start, 285)), 0);
+    }
+
+    #[test]
+    fn device_positions_take_precedence_over_the_clock() {
+        let start = Instant::now();
+        let mut tracker = GapTracker::new(RATE);
+        assert_eq!(tracker.packet(Some(0), 480, start), 0);
+        assert_eq!(tracker.packet(Some(480), 480, start), 0);
+        assert_eq!(tracker.packet(Some(1_920), 480, start), 960);
+
+        // Frames already synthesized while idle are not reported again.
+        let filled = tracker.idle(ms(start, 500));
+        assert!(filled > 0);
+        assert_eq!(tracker.packet(Some(2_400 + filled), 480, ms(start, 500)), 0);
+    }
+
+    #[test]
+    fn reset_starts_a_new_timeline() {
+        let start = Instant::now();
+        let mut tracker = GapTracker::new(RATE);
+        tracker.packet(Some(0), 480, start);
+        tracker.reset();
+        assert_eq!(tracker.packet(Some(96_000), 480, Instant::now()), 0);
+    }
+}
@@ -0,0 +1,41 @@
+mod gap;
+pub use self::gap::GapTracker;
+
+#[cfg(target_os = "windows")]
+mod wasapi;
+#[cfg(target_os = "windows")]
+pub use self::wasapi::{list_devices, mix_format, MixFormat, StreamWaker, SystemAudioStream};
+
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+mod pipewire;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub use self::pipewire::{list_devices, StreamWaker, SystemAudioStream};
+
+#[cfg(all(target_os = "linux", not(feature = "pipewire")))]
+mod pulse;
+#[cfg(all(target_os = "linux", not(feature = "pipewire")))]
+pub use self::pulse::{list_devices, StreamWaker, SystemAudioStream};
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+mod unsupported;
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+pub use self::unsupported::{list_devices, StreamWaker, SystemAudioStream};
+
+/// One poll's worth of capture: interleaved samples, preceded by
+/// `gap_frames` frames the device never delivered (see `GapTracker`).
+#[derive(Default)]
+pub struct AudioPacket {
+    pub gap_frames: u64,
+    pub samples: Vec<f32>,
+}
+
+#[napi(object)]
+pub struct AudioDevice {
+    /// Pass to the `SystemAudioCapture` constructor to capture this device.
+    pub id: String,
+    pub name: String,
+    /// `"output"` for a whole device mix, `"application"` for a single
+    /// application's output stream.
+    pub kind: String,
+    pub process_id: Option<u32>,
+}
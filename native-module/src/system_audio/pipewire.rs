@@ -0,0 +1,714 @@
+use super::{AudioDevice, AudioPacket, GapTracker};
+use crate::downmix::ChannelLayout;
+use crate::sample_format::{SampleEncoding, SampleFormat};
+use anyhow::{anyhow, Result};
+use pipewire as pw;
+use pw::properties::Properties;
+use pw::spa;
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use spa::param::audio::{AudioFormat, AudioInfoRaw};
+use spa::param::format::{MediaSubtype, MediaType};
+use spa::param::format_utils;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CAPTURE_RATE: u32 = 48_000;
+const CAPTURE_CHANNELS: u32 = 2;
+/// Two seconds of audio between the realtime thread and the capture loop.
+const RING_SAMPLES: usize = (CAPTURE_RATE * CAPTURE_CHANNELS * 2) as usize;
+const SINK_CLASS: &str = "Audio/Sink";
+const APP_OUTPUT_CLASS: &str = "Stream/Output/Audio";
+/// Kept short so the capture loop still runs (paced output, idle meters)
+/// while nothing is playing.
+const POLL_WAIT: Duration = Duration::from_millis(5);
+/// Longest a registry roundtrip may take. Device listing runs on the JS
+/// thread, so a daemon that never answers must not hang it.
+const REGISTRY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Captures from PipeWire. Device ids are `"default"` (monitor of the default
+/// sink), `"sink:<node name>"` (monitor of a specific sink), `"pid:<pid>"` or
+/// `"app:<name>"` (one application's playback stream only).
+pub struct SystemAudioStream {
+    target_object: Option<String>,
+    capture_sink: bool,
+    signal: Arc<Signal>,
+    samples: Option<HeapCons<f32>>,
+    gaps: GapTracker,
+    quit: Option<pw::channel::Sender<()>>,
+    loop_thread: Option<thread::JoinHandle<()>>,
+}
+
+/// Wakes the capture loop once the realtime thread has queued audio, or on
+/// `stop`. Only waiters take `lock`; notifying needs no lock.
+#[derive(Default)]
+struct Signal {
+    lock: Mutex<()>,
+    ready: Condvar,
+    /// The negotiated format is one this backend can decode.
+    accepted: AtomicBool,
+}
+
+/// Everything the realtime process callback touches, allocated up front.
+struct ProcessState {
+    producer: HeapProd<f32>,
+    /// How the accepted format decodes.
+    format: SampleFormat,
+    signal: Arc<Signal>,
+}
+
+impl SystemAudioStream {
+    pub fn new(device_id: Option<String>) -> Result<Self> {
+        let (target_object, capture_sink) = resolve_target(device_id.as_deref())?;
+
+        Ok(Self {
+            target_object,
+            capture_sink,
+            signal: Arc::new(Signal::default()),
+            samples: None,
+            gaps: GapTracker::new(CAPTURE_RATE),
+            quit: None,
+            loop_thread: None,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        CAPTURE_RATE
+    }
+
+    pub fn channels(&self) -> u32 {
+        CAPTURE_CHANNELS
+    }
+
+    pub fn channel_layout(&self) -> ChannelLayout {
+        ChannelLayout::default_for(CAPTURE_CHANNELS as usize)
+    }
+
+    pub fn play(&mut self) -> Result<()> {
+        if self.loop_thread.is_some() {
+            return Ok(());
+        }
+
+        let (producer, consumer) = HeapRb::<f32>::new(RING_SAMPLES).split();
+        let (quit_tx, quit_rx) = pw::channel::channel::<()>();
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
+        let target_object = self.target_object.clone();
+        let capture_sink = self.capture_sink;
+        let state = ProcessState {
+            producer,
+            format: sample_format(&requested_format()).map_err(|e| anyhow!(e))?,
+            signal: self.signal.clone(),
+        };
+
+        let handle = thread::spawn(move || {
+            let result = run_stream(target_object, capture_sink, state, quit_rx, &ready_tx);
+            if let Err(e) = result {
+                let _ = ready_tx.send(Err(e.to_string()));
+            }
+        });
+
+        match ready_rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(Ok(())) => {
+                self.gaps.reset();
+                self.samples = Some(consumer);
+                self.quit = Some(quit_tx);
+                self.loop_thread = Some(handle);
+                Ok(())
+            }
+            Ok(Err(e)) => Err(anyhow!("PipeWire stream failed: {}", e)),
+            Err(_) => Err(anyhow!("PipeWire stream did not start")),
+        }
+    }
+
+    pub fn waker(&self) -> StreamWaker {
+        StreamWaker {
+            signal: self.signal.clone(),
+        }
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        if let Some(quit) = self.quit.take() {
+            let _ = quit.send(());
+        }
+        if let Some(handle) = self.loop_thread.take() {
+            let _ = handle.join();
+        }
+        self.samples = None;
+        Ok(())
+    }
+
+    /// Interleaved stereo samples. PipeWire buffers carry no usable device
+    /// position here, so gaps (e.g. a suspended sink, or audio the ring had
+    /// no room for) are measured against the wall clock.
+    pub fn poll_audio(&mut self) -> AudioPacket {
+        let samples = self.take_samples();
+        let now = Instant::now();
+        if samples.is_empty() {
+            return AudioPacket {
+                gap_frames: self.gaps.idle(now),
+                samples,
+            };
+        }
+
+        let frames = (samples.len() / CAPTURE_CHANNELS as usize) as u64;
+        AudioPacket {
+            gap_frames: self.gaps.packet(None, frames, now),
+            samples,
+        }
+    }
+
+    fn take_samples(&mut self) -> Vec<f32> {
+        let consumer = match self.samples.as_mut() {
+            Some(consumer) => consumer,
+            None => return Vec::new(),
+        };
+        if consumer.is_empty() {
+            if let Ok(guard) = self.signal.lock.lock() {
+                let _ = self.signal.ready.wait_timeout(guard, POLL_WAIT);
+            }
+        }
+        pop_frames(consumer, CAPTURE_CHANNELS as usize)
+    }
+}
+
+/// Interrupts a `poll_audio` waiting for samples, so the capture loop sees a
+/// stop request straight away.
+pub struct StreamWaker {
+    signal: Arc<Signal>,
+}
+
+impl StreamWaker {
+    pub fn wake(&self) {
+        self.signal.ready.notify_all();
+    }
+}
+
+impl Drop for SystemAudioStream {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// The valid part of a buffer's data. SPA chunk offsets are taken modulo the
+/// mapped size, and the size is clamped to what follows the offset.
+fn chunk_region(data: &[u8], offset: u32, size: u32) -> &[u8] {
+    if data.is_empty() {
+        return data;
+    }
+    let start = offset as usize % data.len();
+    let end = start + (size as usize).min(data.len() - start);
+    &data[start..end]
+}
+
+/// Decodes and queues the whole frames of `bytes` that fit in the ring,
+/// without allocating. Audio that does not fit is dropped, and shows up as
+/// a gap once the capture loop catches up. Returns the samples queued.
+fn push_frames(producer: &mut HeapProd<f32>, bytes: &[u8], format: &SampleFormat) -> usize {
+    let channels = format.channels();
+    let whole = bytes.len() / format.block_align() * channels;
+    let room = producer.vacant_len() / channels * channels;
+    producer.push_iter(format.samples(bytes).take(whole.min(room)))
+}
+
+/// Takes every whole frame queued so far.
+fn pop_frames(consumer: &mut HeapCons<f32>, channels: usize) -> Vec<f32> {
+    let available = consumer.occupied_len() / channels * channels;
+    let mut samples = vec![0.0; available];
+    let popped = consumer.pop_slice(&mut samples);
+    samples.truncate(popped);
+    samples
+}
+
+/// The only format the stream offers: PipeWire converts whatever the source
+/// produces to 48 kHz stereo F32LE.
+fn requested_format() -> AudioInfoRaw {
+    let mut info = AudioInfoRaw::new();
+    info.set_format(AudioFormat::F32LE);
+    info.set_rate(CAPTURE_RATE);
+    info.set_channels(CAPTURE_CHANNELS);
+    info
+}
+
+/// `EnumFormat` parameter offering `requested_format`.
+fn format_param() -> Result<Vec<u8>> {
+    let format = spa::pod::Object {
+        type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: spa::param::ParamType::EnumFormat.as_raw(),
+        properties: requested_format().into(),
+    };
+    Ok(spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &spa::pod::Value::Object(format),
+    )
+    .map_err(|e| anyhow!("Failed to serialize stream format: {:?}", e))?
+    .0
+    .into_inner())
+}
+
+/// The shared decoder for a raw audio format.
+fn sample_format(info: &AudioInfoRaw) -> Result<SampleFormat, String> {
+    let encoding = match info.format() {
+        AudioFormat::S16LE => SampleEncoding::I16,
+        AudioFormat::S24LE => SampleEncoding::I24,
+        AudioFormat::S32LE => SampleEncoding::I32,
+        AudioFormat::F32LE => SampleEncoding::F32,
+        AudioFormat::F64LE => SampleEncoding::F64,
+        other => return Err(format!("{:?} cannot be decoded", other)),
+    };
+    if info.channels() == 0 {
+        return Err("no channels".to_string());
+    }
+    Ok(SampleFormat::new(encoding, info.channels() as usize))
+}
+
+/// Checks a negotiated `Format` parameter against `requested_format` and
+/// returns how it decodes.
+fn check_format(param: &spa::pod::Pod) -> Result<SampleFormat, String> {
+    let (media_type, media_subtype) =
+        format_utils::parse_format(param).map_err(|e| e.to_string())?;
+    if media_type != MediaType::Audio || media_subtype != MediaSubtype::Raw {
+        return Err("not raw audio".to_string());
+    }
+
+    let mut info = AudioInfoRaw::new();
+    info.parse(param).map_err(|e| e.to_string())?;
+    let requested = requested_format();
+    let format = sample_format(&info)?;
+    if Ok(format) != sample_format(&requested) || info.rate() != requested.rate() {
+        return Err(format!(
+            "{:?} at {} Hz, {} channel(s)",
+            info.format(),
+            info.rate(),
+            info.channels()
+        ));
+    }
+    Ok(format)
+}
+
+fn run_stream(
+    target_object: Option<String>,
+    capture_sink: bool,
+    state: ProcessState,
+    quit_rx: pw::channel::Receiver<()>,
+    ready: &mpsc::Sender<Result<(), String>>,
+) -> Result<()> {
+    pw::init();
+
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    let mut props = Properties::new();
+    props.insert("media.type", "Audio");
+    props.insert("media.category", "Capture");
+    props.insert("media.role", "Communication");
+    props.insert("node.name", "nyx-audio-capture");
+    if capture_sink {
+        props.insert("stream.capture.sink", "true");
+    }
+    if let Some(target) = target_object {
+        props.insert("target.object", target);
+    }
+
+    let stream = pw::stream::Stream::new(&core, "nyx-audio-capture", props)?;
+    let _listener = stream
+        .add_local_listener_with_user_data(state)
+        .param_changed(|_, state, id, param| {
+            let param = match param {
+                Some(param) if id == spa::param::ParamType::Format.as_raw() => param,
+                _ => return,
+            };
+            // The process callback decodes with `state.format`, which is
+            // what the requested format maps to; anything else is refused.
+            let accepted = match check_format(param) {
+                Ok(_) => true,
+                Err(e) => {
+                    eprintln!("PipeWire negotiated an unexpected format: {}", e);
+                    false
+                }
+            };
+            state.signal.accepted.store(accepted, Ordering::Release);
+        })
+        .process(|stream, state| {
+            // Realtime thread: no locks, no allocation.
+            let mut pw_buffer = match stream.dequeue_buffer() {
+                Some(b) => b,
+                None => return,
+            };
+            if !state.signal.accepted.load(Ordering::Acquire) {
+                return;
+            }
+            let datas = pw_buffer.datas_mut();
+            if datas.is_empty() {
+                return;
+            }
+            let data = &mut datas[0];
+            let (offset, size) = (data.chunk().offset(), data.chunk().size());
+            if let Some(bytes) = data.data() {
+                let region = chunk_region(bytes, offset, size);
+                if push_frames(&mut state.producer, region, &state.format) > 0 {
+                    state.signal.ready.notify_one();
+                }
+            }
+        })
+        .register()?;
+
+    let values = format_param()?;
+    let mut params =
+        [spa::pod::Pod::from_bytes(&values).ok_or_else(|| anyhow!("Invalid stream format"))?];
+
+    stream.connect(
+        spa::utils::Direction::Input,
+        None,
+        pw::stream::StreamFlags::AUTOCONNECT
+            | pw::stream::StreamFlags::MAP_BUFFERS
+            | pw::stream::StreamFlags::RT_PROCESS,
+        &mut params,
+    )?;
+
+    let quit_loop = mainloop.clone();
+    let _quit = quit_rx.attach(mainloop.loop_(), move |_| quit_loop.quit());
+
+    let _ = ready.send(Ok(()));
+    mainloop.run();
+
+    let _ = stream.disconnect();
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct NodeInfo {
+    /// Value for `target.object`: the object serial, or the node name.
+    target: String,
+    node_name: String,
+    description: Option<String>,
+    media_class: String,
+    app_name: Option<String>,
+    binary: Option<String>,
+    process_id: Option<u32>,
+}
+
+impl NodeInfo {
+    /// A node from its global properties; `None` for nodes without a media
+    /// class, which cannot carry audio.
+    fn from_props<'a>(prop: impl Fn(&str) -> Option<&'a str>) -> Option<Self> {
+        let media_class = prop("media.class")?.to_string();
+        let node_name = prop("node.name").unwrap_or_default().to_string();
+        let target = prop("object.serial")
+            .map(str::to_string)
+            .unwrap_or_else(|| node_name.clone());
+
+        Some(NodeInfo {
+            target,
+            node_name,
+            description: prop("node.description").map(str::to_string),
+            media_class,
+            app_name: prop("application.name").map(str::to_string),
+            binary: prop("application.process.binary").map(str::to_string),
+            process_id: prop("application.process.id").and_then(|pid| pid.parse().ok()),
+        })
+    }
+
+    fn matches_app(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        [
+            self.binary.as_deref(),
+            self.app_name.as_deref(),
+            Some(self.node_name.as_str()),
+        ]
+        .iter()
+        .flatten()
+        .any(|candidate| candidate.to_lowercase() == name)
+    }
+}
+
+/// Snapshot of the PipeWire graph's nodes, taken with a registry roundtrip.
+fn collect_nodes() -> Result<Vec<NodeInfo>> {
+    pw::init();
+
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+    let registry = core.get_registry()?;
+
+    let nodes = Rc::new(RefCell::new(Vec::new()));
+    let nodes_out = nodes.clone();
+    let _registry_listener = registry
+        .add_listener_local()
+        .global(move |global| {
+            if global.type_ != pw::types::ObjectType::Node {
+                return;
+            }
+            let props = match global.props {
+                Some(p) => p,
+                None => return,
+            };
+            if let Some(node) = NodeInfo::from_props(|key| props.get(key)) {
+                nodes.borrow_mut().push(node);
+            }
+        })
+        .register();
+
+    let done = Rc::new(Cell::new(false));
+    let done_flag = done.clone();
+    let done_loop = mainloop.clone();
+    let pending = core.sync(0)?;
+    let _core_listener = core
+        .add_listener_local()
+        .done(move |id, seq| {
+            if id == pw::core::PW_ID_CORE && seq == pending {
+                done_flag.set(true);
+                done_loop.quit();
+            }
+        })
+        .register();
+
+    let timed_out = Rc::new(Cell::new(false));
+    let timeout_flag = timed_out.clone();
+    let timeout_loop = mainloop.clone();
+    let timer = mainloop.loop_().add_timer(move |_| {
+        timeout_flag.set(true);
+        timeout_loop.quit();
+    });
+    timer
+        .update_timer(Some(REGISTRY_TIMEOUT), None)
+        .into_result()
+        .map_err(|e| anyhow!("Failed to arm the registry timeout: {}", e))?;
+
+    while !done.get() && !timed_out.get() {
+        mainloop.run();
+    }
+    if !done.get() {
+        return Err(anyhow!(
+            "PipeWire did not answer within {} ms",
+            REGISTRY_TIMEOUT.as_millis()
+        ));
+    }
+
+    let collected = nodes_out.take();
+    Ok(collected)
+}
+
+fn resolve_target(device_id: Option<&str>) -> Result<(Option<String>, bool)> {
+    let id = match device_id {
+        None | Some("default") => return Ok((None, true)),
+        Some(id) => id,
+    };
+
+    if let Some(node_name) = id.strip_prefix("sink:") {
+        return Ok((Some(node_name.to_string()), true));
+    }
+    if !id.starts_with("pid:") && !id.starts_with("app:") {
+        return Err(anyhow!("Unknown audio device id: {}", id));
+    }
+
+    match find_application(&collect_nodes()?, id)? {
+        Some(node) => Ok((Some(node.target.clone()), false)),
+        None => Err(anyhow!("No playback stream found for {}", id)),
+    }
+}
+
+/// The playback stream a `pid:` or `app:` device id refers to.
+fn find_application<'a>(nodes: &'a [NodeInfo], id: &str) -> Result<Option<&'a NodeInfo>> {
+    let mut streams = nodes.iter().filter(|n| n.media_class == APP_OUTPUT_CLASS);
+    if let Some(pid) = id.strip_prefix("pid:") {
+        let pid: u32 = pid
+            .parse()
+            .map_err(|_| anyhow!("Invalid process id: {}", pid))?;
+        Ok(streams.find(|n| n.process_id == Some(pid)))
+    } else if let Some(name) = id.strip_prefix("app:") {
+        Ok(streams.find(|n| n.matches_app(name)))
+    } else {
+        Err(anyhow!("Unknown audio device id: {}", id))
+    }
+}
+
+pub fn list_devices() -> Result<Vec<AudioDevice>> {
+    Ok(devices_from_nodes(collect_nodes()?))
+}
+
+fn devices_from_nodes(nodes: Vec<NodeInfo>) -> Vec<AudioDevice> {
+    let mut devices = vec![AudioDevice {
+        id: "default".to_string(),
+        name: "Default output".to_string(),
+        kind: "output".to_string(),
+        process_id: None,
+    }];
+
+    for node in nodes {
+        if node.media_class == SINK_CLASS {
+            devices.push(AudioDevice {
+                id: format!("sink:{}", node.node_name),
+                name: node.description.unwrap_or(node.node_name),
+                kind: "output".to_string(),
+                process_id: None,
+            });
+        } else if node.media_class == APP_OUTPUT_CLASS {
+            let id = match node.process_id {
+                Some(pid) => format!("pid:{}", pid),
+                None => format!("app:{}", node.binary.as_deref().unwrap_or(&node.node_name)),
+            };
+            devices.push(AudioDevice {
+                id,
+                name: node.app_name.or(node.binary).unwrap_or(node.node_name),
+                kind: "application".to_string(),
+                process_id: node.process_id,
+            });
+        }
+    }
+
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn node(props: &[(&str, &str)]) -> Option<NodeInfo> {
+        let props: HashMap<&str, &str> = props.iter().copied().collect();
+        NodeInfo::from_props(|key| props.get(key).copied())
+    }
+
+    fn graph() -> Vec<NodeInfo> {
+        [
+            node(&[
+                ("media.class", SINK_CLASS),
+                ("node.name", "alsa_output.pci"),
+                ("node.description", "Built-in Audio"),
+                ("object.serial", "41"),
+            ]),
+            node(&[
+                ("media.class", APP_OUTPUT_CLASS),
+                ("node.name", "Firefox"),
+                ("application.name", "Firefox"),
+                ("application.process.binary", "firefox"),
+                ("application.process.id", "4242"),
+                ("object.serial", "77"),
+            ]),
+            node(&[("media.class", APP_OUTPUT_CLASS), ("node.name", "mpv")]),
+            node(&[("media.class", "Audio/Source"), ("node.name", "mic")]),
+            node(&[("node.name", "driver")]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    #[test]
+    fn nodes_without_a_media_class_are_skipped() {
+        assert_eq!(graph().len(), 4);
+        // No serial: the node name is the target.
+        assert_eq!(graph()[2].target, "mpv");
+    }
+
+    #[test]
+    fn lists_sinks_and_playback_streams() {
+        let devices = devices_from_nodes(graph());
+        let ids: Vec<&str> = devices.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            ["default", "sink:alsa_output.pci", "pid:4242", "app:mpv"]
+        );
+        assert_eq!(devices[1].name, "Built-in Audio");
+        assert_eq!(devices[2].kind, "application");
+        assert_eq!(devices[2].process_id, Some(4242));
+    }
+
+    #[test]
+    fn finds_applications_by_pid_or_name() {
+        let nodes = graph();
+        let target = |id| {
+            find_application(&nodes, id)
+                .unwrap()
+                .map(|n| n.target.as_str())
+        };
+        assert_eq!(target("pid:4242"), Some("77"));
+        assert_eq!(target("app:FIREFOX"), Some("77"));
+        assert_eq!(target("app:mpv"), Some("mpv"));
+        assert_eq!(target("pid:1"), None);
+        // Sinks are never application targets.
+        assert_eq!(target("app:alsa_output.pci"), None);
+        assert!(find_application(&nodes, "pid:abc").is_err());
+    }
+
+    #[test]
+    fn offers_and_accepts_only_f32_stereo_at_48k() {
+        let bytes = format_param().unwrap();
+        let pod = spa::pod::Pod::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            check_format(pod),
+            Ok(SampleFormat::new(SampleEncoding::F32, 2))
+        );
+
+        let mut info = AudioInfoRaw::new();
+        info.parse(pod).unwrap();
+        assert_eq!(info.format(), AudioFormat::F32LE);
+        assert_eq!((info.rate(), info.channels()), (48_000, 2));
+    }
+
+    #[test]
+    fn rejects_other_negotiated_formats() {
+        let mut info = requested_format();
+        info.set_rate(44_100);
+        let format = spa::pod::Object {
+            type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+            id: spa::param::ParamType::Format.as_raw(),
+            properties: info.into(),
+        };
+        let bytes = spa::pod::serialize::PodSerializer::serialize(
+            std::io::Cursor::new(Vec::new()),
+            &spa::pod::Value::Object(format),
+        )
+        .unwrap()
+        .0
+        .into_inner();
+        assert!(check_format(spa::pod::Pod::from_bytes(&bytes).unwrap()).is_err());
+    }
+
+    #[test]
+    fn maps_raw_formats_to_the_shared_decoder() {
+        let mut info = AudioInfoRaw::new();
+        info.set_channels(1);
+        info.set_format(AudioFormat::S16LE);
+        let format = sample_format(&info).unwrap();
+        assert_eq!(format, SampleFormat::new(SampleEncoding::I16, 1));
+        assert_eq!(format.decode(&16_384i16.to_le_bytes()), [0.5]);
+
+        info.set_format(AudioFormat::U8);
+        assert!(sample_format(&info).is_err());
+    }
+
+    #[test]
+    fn chunk_region_honours_offset_and_size() {
+        let data: Vec<u8> = (0..16).collect();
+        assert_eq!(chunk_region(&data, 4, 8), &data[4..12]);
+        // Offsets wrap at the mapped size; sizes are clamped.
+        assert_eq!(chunk_region(&data, 20, 8), &data[4..12]);
+        assert_eq!(chunk_region(&data, 12, 100), &data[12..]);
+        assert!(chunk_region(&[], 3, 3).is_empty());
+    }
+
+    #[test]
+    fn ring_carries_whole_frames_only() {
+        let (mut producer, mut consumer) = HeapRb::<f32>::new(5).split();
+        let bytes: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+
+        // Room for five samples is two stereo frames; the seventh sample is
+        // half a frame anyway.
+        let format = SampleFormat::new(SampleEncoding::F32, 2);
+        assert_eq!(push_frames(&mut producer, &bytes, &format), 4);
+        assert_eq!(pop_frames(&mut consumer, 2), [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(push_frames(&mut producer, &bytes[..12], &format), 2);
+        assert_eq!(pop_frames(&mut consumer, 2), [1.0, 2.0]);
+        assert!(pop_frames(&mut consumer, 2).is_empty());
+    }
+}
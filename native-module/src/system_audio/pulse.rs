@@ -0,0 +1,310 @@
+use super::{AudioDevice, AudioPacket, GapTracker};
+use crate::downmix::ChannelLayout;
+use crate::sample_format::{SampleEncoding, SampleFormat};
+use anyhow::{anyhow, Result};
+use std::collections::VecDeque;
+use std::io::Read;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CAPTURE_RATE: u32 = 48_000;
+const CAPTURE_CHANNELS: u32 = 2;
+const MAX_BUFFERED_SAMPLES: usize = (CAPTURE_RATE * CAPTURE_CHANNELS * 2) as usize;
+const DEFAULT_SOURCE: &str = "@DEFAULT_MONITOR@";
+const MONITOR_SUFFIX: &str = ".monitor";
+/// Kept short so the capture loop still runs (paced output, idle meters)
+/// while nothing is playing.
+const POLL_WAIT: Duration = Duration::from_millis(5);
+
+/// Captures through PulseAudio (or pipewire-pulse) by running `parec`, for
+/// builds without the `pipewire` feature. Device ids are `"default"` (monitor
+/// of the default sink) or `"source:<name>"`; single applications cannot be
+/// captured this way.
+pub struct SystemAudioStream {
+    source: String,
+    buffer: Arc<SampleBuffer>,
+    gaps: GapTracker,
+    child: Option<Child>,
+    reader: Option<thread::JoinHandle<()>>,
+}
+
+struct SampleBuffer {
+    samples: Mutex<VecDeque<f32>>,
+    ready: Condvar,
+}
+
+impl SampleBuffer {
+    fn push(&self, decoded: Vec<f32>) {
+        let mut samples = match self.samples.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        samples.extend(decoded);
+        let excess = samples.len().saturating_sub(MAX_BUFFERED_SAMPLES);
+        let excess = excess - excess % CAPTURE_CHANNELS as usize;
+        samples.drain(..excess);
+        self.ready.notify_one();
+    }
+}
+
+impl SystemAudioStream {
+    pub fn new(device_id: Option<String>) -> Result<Self> {
+        let source = match device_id.as_deref() {
+            None | Some("default") => DEFAULT_SOURCE.to_string(),
+            Some(id) => match id.strip_prefix("source:") {
+                Some(name) => name.to_string(),
+                None if id.starts_with("pid:") || id.starts_with("app:") => {
+                    return Err(anyhow!(
+                        "Capturing a single application needs the `pipewire` feature: {}",
+                        id
+                    ))
+                }
+                None => return Err(anyhow!("Unknown audio device id: {}", id)),
+            },
+        };
+
+        Ok(Self {
+            source,
+            buffer: Arc::new(SampleBuffer {
+                samples: Mutex::new(VecDeque::new()),
+                ready: Condvar::new(),
+            }),
+            gaps: GapTracker::new(CAPTURE_RATE),
+            child: None,
+            reader: None,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        CAPTURE_RATE
+    }
+
+    pub fn channels(&self) -> u32 {
+        CAPTURE_CHANNELS
+    }
+
+    pub fn channel_layout(&self) -> ChannelLayout {
+        ChannelLayout::default_for(CAPTURE_CHANNELS as usize)
+    }
+
+    pub fn play(&mut self) -> Result<()> {
+        if self.child.is_some() {
+            return Ok(());
+        }
+
+        let mut child = Command::new("parec")
+            .args(["--raw", "--format=float32le"])
+            .arg(format!("--rate={}", CAPTURE_RATE))
+            .arg(format!("--channels={}", CAPTURE_CHANNELS))
+            .arg(format!("--device={}", self.source))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| {
+                anyhow!(
+                    "Failed to start parec (is pulseaudio-utils installed?): {}",
+                    e
+                )
+            })?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("parec has no output"))?;
+
+        let buffer = self.buffer.clone();
+        self.reader = Some(thread::spawn(move || read_samples(stdout, &buffer)));
+        self.gaps.reset();
+        self.child = Some(child);
+        Ok(())
+    }
+
+    pub fn waker(&self) -> StreamWaker {
+        StreamWaker {
+            buffer: self.buffer.clone(),
+        }
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+        Ok(())
+    }
+
+    /// Interleaved stereo samples. `parec` reports no device position, so
+    /// gaps are measured against the wall clock.
+    pub fn poll_audio(&mut self) -> AudioPacket {
+        let samples = self.take_samples();
+        let now = Instant::now();
+        if samples.is_empty() {
+            return AudioPacket {
+                gap_frames: self.gaps.idle(now),
+                samples,
+            };
+        }
+
+        let frames = (samples.len() / CAPTURE_CHANNELS as usize) as u64;
+        AudioPacket {
+            gap_frames: self.gaps.packet(None, frames, now),
+            samples,
+        }
+    }
+
+    fn take_samples(&self) -> Vec<f32> {
+        let mut samples = match self.buffer.samples.lock() {
+            Ok(s) => s,
+            Err(_) => return Vec::new(),
+        };
+        if samples.is_empty() {
+            samples = match self.buffer.ready.wait_timeout(samples, POLL_WAIT) {
+                Ok((s, _)) => s,
+                Err(_) => return Vec::new(),
+            };
+        }
+
+        let available = samples.len() - samples.len() % CAPTURE_CHANNELS as usize;
+        samples.drain(..available).collect()
+    }
+}
+
+/// Interrupts a `poll_audio` waiting for samples, so the capture loop sees a
+/// stop request straight away.
+pub struct StreamWaker {
+    buffer: Arc<SampleBuffer>,
+}
+
+impl StreamWaker {
+    pub fn wake(&self) {
+        self.buffer.ready.notify_all();
+    }
+}
+
+impl Drop for SystemAudioStream {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Decodes `parec` output until it exits, carrying partial frames over to
+/// the next read.
+fn read_samples(mut stdout: ChildStdout, buffer: &SampleBuffer) {
+    let format = SampleFormat::new(SampleEncoding::F32, CAPTURE_CHANNELS as usize);
+    let mut chunk = [0u8; 16_384];
+    let mut pending = Vec::new();
+    loop {
+        let read = match stdout.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(read) => read,
+        };
+        pending.extend_from_slice(&chunk[..read]);
+        let whole = pending.len() - pending.len() % format.block_align();
+        if whole > 0 {
+            buffer.push(format.decode(&pending[..whole]));
+            pending.drain(..whole);
+        }
+    }
+}
+
+pub fn list_devices() -> Result<Vec<AudioDevice>> {
+    let output = Command::new("pactl")
+        .args(["list", "short", "sources"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| {
+            anyhow!(
+                "Failed to run pactl (is pulseaudio-utils installed?): {}",
+                e
+            )
+        })?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "pactl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(devices_from_sources(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Devices from `pactl list short sources` output: one tab-separated line
+/// per source, the name in the second column. Only sink monitors carry
+/// system audio.
+fn devices_from_sources(listing: &str) -> Vec<AudioDevice> {
+    let mut devices = vec![AudioDevice {
+        id: "default".to_string(),
+        name: "Default output".to_string(),
+        kind: "output".to_string(),
+        process_id: None,
+    }];
+
+    for name in listing.lines().filter_map(|line| line.split('\t').nth(1)) {
+        if let Some(sink) = name.strip_suffix(MONITOR_SUFFIX) {
+            devices.push(AudioDevice {
+                id: format!("source:{}", name),
+                name: sink.to_string(),
+                kind: "output".to_string(),
+                process_id: None,
+            });
+        }
+    }
+
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_sink_monitors_only() {
+        let listing = "\
+53\talsa_output.pci-0000_00_1f.3.analog-stereo.monitor\tPipeWire\tfloat32le 2ch 48000Hz\tSUSPENDED
+54\talsa_input.pci-0000_00_1f.3.analog-stereo\tPipeWire\tfloat32le 2ch 48000Hz\tRUNNING
+";
+        let devices = devices_from_sources(listing);
+        let ids: Vec<&str> = devices.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "default",
+                "source:alsa_output.pci-0000_00_1f.3.analog-stereo.monitor"
+            ]
+        );
+        assert_eq!(
+            devices[1].name,
+            "alsa_output.pci-0000_00_1f.3.analog-stereo"
+        );
+        assert_eq!(devices_from_sources("").len(), 1);
+    }
+
+    #[test]
+    fn resolves_device_ids() {
+        let source =
+            |id: &str| SystemAudioStream::new(Some(id.to_string())).map(|s| s.source.clone());
+        assert_eq!(source("default").unwrap(), DEFAULT_SOURCE);
+        assert_eq!(source("source:x.monitor").unwrap(), "x.monitor");
+        assert!(source("pid:42").is_err());
+        assert!(source("speaker").is_err());
+    }
+
+    #[test]
+    fn buffer_keeps_the_newest_whole_frames() {
+        let buffer = SampleBuffer {
+            samples: Mutex::new(VecDeque::new()),
+            ready: Condvar::new(),
+        };
+        buffer.push(vec![1.0; MAX_BUFFERED_SAMPLES]);
+        buffer.push(vec![2.0; 4]);
+        let samples = buffer.samples.lock().unwrap();
+        assert_eq!(samples.len(), MAX_BUFFERED_SAMPLES);
+        assert_eq!(samples.iter().filter(|&&s| s == 2.0).count(), 4);
+    }
+}
@@ -0,0 +1,54 @@
+use super::{AudioDevice, AudioPacket};
+use crate::downmix::ChannelLayout;
+use anyhow::{anyhow, Result};
+
+pub struct SystemAudioStream {
+    sample_rate: u32,
+    channels: u32,
+}
+
+impl SystemAudioStream {
+    pub fn new(_device_id: Option<String>) -> Result<Self> {
+        Err(anyhow!(
+            "System audio capture is not supported on this platform"
+        ))
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    pub fn channel_layout(&self) -> ChannelLayout {
+        ChannelLayout::default_for(self.channels as usize)
+    }
+
+    pub fn play(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn waker(&self) -> StreamWaker {
+        StreamWaker
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn poll_audio(&mut self) -> AudioPacket {
+        AudioPacket::default()
+    }
+}
+
+pub struct StreamWaker;
+
+impl StreamWaker {
+    pub fn wake(&self) {}
+}
+
+pub fn list_devices() -> Result<Vec<AudioDevice>> {
+    Ok(Vec::new())
+}
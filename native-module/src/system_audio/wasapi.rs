@@ -0,0 +1,294 @@
+use super::{AudioDevice, AudioPacket, GapTracker};
+use crate::downmix::ChannelLayout;
+use crate::sample_format::SampleFormat;
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use wasapi::{initialize_mta, DeviceEnumerator, Direction};
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0};
+use windows::Win32::Media::Audio::{
+    eConsole, eRender, IAudioCaptureClient, IAudioClient, IMMDeviceEnumerator, MMDeviceEnumerator,
+    AUDCLNT_BUFFERFLAGS_SILENT, AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+    AUDCLNT_STREAMFLAGS_LOOPBACK, WAVEFORMATEX, WAVEFORMATEXTENSIBLE,
+};
+use windows::Win32::System::Com::{CoCreateInstance, CoTaskMemFree, CLSCTX_ALL};
+use windows::Win32::System::Threading::{CreateEventW, SetEvent, WaitForSingleObject};
+
+/// Short enough that the capture loop keeps running (paced output, gap
+/// synthesis, idle meters) while the device is silent.
+const POLL_WAIT_MS: u32 = 5;
+
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Loopback capture of a render device. The client is opened through the
+/// Windows API rather than the wasapi crate so that the stream owns its
+/// buffer event and a `StreamWaker` can signal it.
+pub struct SystemAudioStream {
+    audio_client: IAudioClient,
+    capture_client: IAudioCaptureClient,
+    event: Arc<Event>,
+    sample_rate: u32,
+    channels: u32,
+    layout: ChannelLayout,
+    sample_format: SampleFormat,
+    gaps: GapTracker,
+    is_running: Arc<AtomicBool>,
+}
+
+unsafe impl Send for SystemAudioStream {}
+
+impl SystemAudioStream {
+    pub fn new(device_id: Option<String>) -> Result<Self> {
+        let _ = initialize_mta();
+
+        unsafe {
+            let enumerator: IMMDeviceEnumerator =
+                CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+            let device = match device_id.as_deref() {
+                None | Some("default") => enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?,
+                Some(id) => enumerator
+                    .GetDevice(&HSTRING::from(id))
+                    .map_err(|_| anyhow!("Audio device not found: {}", id))?,
+            };
+            let audio_client: IAudioClient = device.Activate(CLSCTX_ALL, None)?;
+
+            let format = audio_client.GetMixFormat()?;
+            let mix = mix_format(format);
+            let mut min_period = 0i64;
+            let initialized = audio_client
+                .GetDevicePeriod(None, Some(&mut min_period as *mut i64))
+                .and_then(|_| {
+                    audio_client.Initialize(
+                        AUDCLNT_SHAREMODE_SHARED,
+                        AUDCLNT_STREAMFLAGS_LOOPBACK | AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+                        min_period,
+                        0,
+                        format,
+                        None,
+                    )
+                });
+            CoTaskMemFree(Some(format as *const _));
+            initialized?;
+            let mix = mix?;
+
+            let event = Arc::new(Event::new()?);
+            audio_client.SetEventHandle(event.0)?;
+            let capture_client: IAudioCaptureClient = audio_client.GetService()?;
+
+            Ok(Self {
+                audio_client,
+                capture_client,
+                event,
+                sample_rate: mix.sample_rate,
+                channels: mix.channels,
+                layout: mix.layout,
+                sample_format: mix.sample_format,
+                gaps: GapTracker::new(mix.sample_rate),
+                is_running: Arc::new(AtomicBool::new(false)),
+            })
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    pub fn channel_layout(&self) -> ChannelLayout {
+        self.layout.clone()
+    }
+
+    pub fn play(&mut self) -> Result<()> {
+        unsafe { self.audio_client.Start()? };
+        self.gaps.reset();
+        self.is_running.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn waker(&self) -> StreamWaker {
+        StreamWaker {
+            event: self.event.clone(),
+        }
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        if self.is_running.swap(false, Ordering::SeqCst) {
+            unsafe { self.audio_client.Stop()? };
+        }
+        Ok(())
+    }
+
+    /// Interleaved samples in the device's channel layout. Shared-mode
+    /// loopback delivers no packets while nothing plays; the device position
+    /// of the next packet tells how long the pause was, and while it lasts
+    /// the wall clock does.
+    pub fn poll_audio(&mut self) -> AudioPacket {
+        if unsafe { WaitForSingleObject(self.event.0, POLL_WAIT_MS) } != WAIT_OBJECT_0 {
+            return AudioPacket {
+                gap_frames: self.gaps.idle(Instant::now()),
+                samples: Vec::new(),
+            };
+        }
+
+        let mut packet = AudioPacket::default();
+        let block_align = self.sample_format.block_align();
+
+        // A wake from `StreamWaker` finds no packet and returns empty.
+        while let Ok(size) = unsafe { self.capture_client.GetNextPacketSize() } {
+            if size == 0 {
+                break;
+            }
+
+            let mut data: *mut u8 = std::ptr::null_mut();
+            let mut frames = 0u32;
+            let mut flags = 0u32;
+            let mut position = 0u64;
+            let read = unsafe {
+                self.capture_client.GetBuffer(
+                    &mut data,
+                    &mut frames,
+                    &mut flags,
+                    Some(&mut position as *mut u64),
+                    None,
+                )
+            };
+            if read.is_err() {
+                break;
+            }
+
+            let gap = self
+                .gaps
+                .packet(Some(position), frames as u64, Instant::now());
+            if packet.samples.is_empty() {
+                packet.gap_frames += gap;
+            } else if gap > 0 {
+                // A discontinuity inside one poll: fill it in place.
+                let silence = gap as usize * self.channels as usize;
+                packet.samples.extend(std::iter::repeat_n(0.0, silence));
+            }
+
+            let samples = frames as usize * self.channels as usize;
+            if flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 != 0 || data.is_null() {
+                packet.samples.extend(std::iter::repeat_n(0.0, samples));
+            } else {
+                let bytes =
+                    unsafe { std::slice::from_raw_parts(data, frames as usize * block_align) };
+                packet.samples.extend(self.sample_format.decode(bytes));
+            }
+            if unsafe { self.capture_client.ReleaseBuffer(frames) }.is_err() {
+                break;
+            }
+        }
+
+        packet
+    }
+}
+
+/// A device mix format as the capture code needs it.
+pub struct MixFormat {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub layout: ChannelLayout,
+    pub sample_format: SampleFormat,
+}
+
+/// Reads a mix format: a `WAVEFORMATEX`, or the `WAVEFORMATEXTENSIBLE` it
+/// starts when tagged so.
+///
+/// # Safety
+///
+/// `format` must point to a valid `WAVEFORMATEX`, followed by the rest of a
+/// `WAVEFORMATEXTENSIBLE` if its tag says so.
+pub unsafe fn mix_format(format: *const WAVEFORMATEX) -> Result<MixFormat> {
+    let base = std::ptr::read_unaligned(format);
+    let channels = base.nChannels as u32;
+    let (tag, mask) = if base.wFormatTag == WAVE_FORMAT_EXTENSIBLE {
+        let extensible = std::ptr::read_unaligned(format as *const WAVEFORMATEXTENSIBLE);
+        // The standard subformat GUIDs carry the format tag in Data1.
+        let subformat = extensible.SubFormat;
+        (subformat.data1 as u16, extensible.dwChannelMask)
+    } else {
+        (base.wFormatTag, 0)
+    };
+    let sample_format = SampleFormat::from_wave_format(
+        tag == WAVE_FORMAT_IEEE_FLOAT,
+        base.wBitsPerSample,
+        channels as usize,
+        base.nBlockAlign as usize,
+    )
+    .map_err(|e| anyhow!(e))?;
+    Ok(MixFormat {
+        sample_rate: base.nSamplesPerSec,
+        channels,
+        layout: ChannelLayout::from_channel_mask(mask, channels as usize),
+        sample_format,
+    })
+}
+
+pub fn list_devices() -> Result<Vec<AudioDevice>> {
+    let _ = initialize_mta();
+
+    let enumerator = DeviceEnumerator::new()?;
+    let collection = enumerator.get_device_collection(&Direction::Render)?;
+
+    let mut devices = vec![AudioDevice {
+        id: "default".to_string(),
+        name: "Default output".to_string(),
+        kind: "output".to_string(),
+        process_id: None,
+    }];
+    for index in 0..collection.get_nbr_devices()? {
+        let device = collection.get_device_at_index(index)?;
+        devices.push(AudioDevice {
+            id: device.get_id()?,
+            name: device.get_friendlyname()?,
+            kind: "output".to_string(),
+            process_id: None,
+        });
+    }
+    Ok(devices)
+}
+
+/// The buffer event the client signals when a packet is ready.
+struct Event(HANDLE);
+
+// Event handles may be signalled and waited on from any thread.
+unsafe impl Send for Event {}
+unsafe impl Sync for Event {}
+
+impl Event {
+    fn new() -> Result<Self> {
+        let handle = unsafe { CreateEventW(None, false, false, PCWSTR::null())? };
+        Ok(Self(handle))
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Signals the stream's buffer event, so a `poll_audio` waiting on it
+/// returns and the capture loop sees a stop request straight away.
+pub struct StreamWaker {
+    event: Arc<Event>,
+}
+
+impl StreamWaker {
+    pub fn wake(&self) {
+        let _ = unsafe { SetEvent(self.event.0) };
+    }
+}
+
+impl Drop for SystemAudioStream {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
@@ -0,0 +1,552 @@
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::JsFunction;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::audio_config::SAMPLE_RATE;
+use crate::streaming_resampler::StreamingResampler;
+
+const DEFAULT_STEP_MS: u32 = 2_000;
+const DEFAULT_WINDOW_MS: u32 = 10_000;
+/// Step-sized blocks queued for the worker. While it is behind, further audio
+/// is coalesced into one block on the capture side instead.
+const QUEUE_BLOCKS: usize = 4;
+/// Audio held back for a stalled worker, in windows, before it is dropped.
+const MAX_PENDING_WINDOWS: usize = 2;
+/// A full window is cut at its quietest point within this much of its end,
+/// so words are not split between final results.
+const MAX_CUT_SEARCH_MS: u32 = 2_000;
+const CUT_FRAME_MS: u32 = 20;
+/// Windows quieter than this are dropped instead of decoded; Whisper tends
+/// to hallucinate text on silence.
+const SILENCE_RMS: f32 = 0.005;
+
+#[napi(object)]
+pub struct TranscriptionOptions {
+    /// Path to a whisper.cpp ggml model, e.g. `ggml-base.en.bin`.
+    pub model_path: String,
+    /// Spoken language code, default `"en"`; `"auto"` detects it.
+    pub language: Option<String>,
+    /// CPU threads used for inference, default 4.
+    pub threads: Option<u32>,
+    /// Audio between partial results, default 2000 ms.
+    pub step_ms: Option<u32>,
+    /// Longest audio per final result, default 10000 ms. Each window ends
+    /// at the quietest point of its last quarter (at most 2 s).
+    pub window_ms: Option<u32>,
+}
+
+#[napi(object)]
+pub struct TranscriptEvent {
+    pub text: String,
+    /// Partial results are superseded by later events for the same window;
+    /// a final result is not revised again.
+    pub is_final: bool,
+    /// Window bounds in milliseconds of audio since the subscription began.
+    pub start_ms: f64,
+    pub end_ms: f64,
+}
+
+pub struct TranscriptionConfig {
+    pub model_path: String,
+    pub language: String,
+    pub threads: u32,
+    pub step_ms: u32,
+    pub window_ms: u32,
+}
+
+impl TranscriptionConfig {
+    pub fn from_options(options: TranscriptionOptions) -> napi::Result<Self> {
+        let step_ms = options.step_ms.unwrap_or(DEFAULT_STEP_MS);
+        let window_ms = options.window_ms.unwrap_or(DEFAULT_WINDOW_MS);
+        if step_ms < 200 || window_ms < step_ms || window_ms > 30_000 {
+            return Err(napi::Error::from_reason(format!(
+                "Unsupported transcription timing: step {} ms, window {} ms",
+                step_ms, window_ms
+            )));
+        }
+
+        Ok(Self {
+            model_path: options.model_path,
+            language: options.language.unwrap_or_else(|| "en".to_string()),
+            threads: options.threads.unwrap_or(4).max(1),
+            step_ms,
+            window_ms,
+        })
+    }
+
+    fn step_samples(&self) -> usize {
+        (SAMPLE_RATE * self.step_ms / 1000) as usize
+    }
+
+    fn window_samples(&self) -> usize {
+        (SAMPLE_RATE * self.window_ms / 1000) as usize
+    }
+
+    fn cut_search_samples(&self) -> usize {
+        (SAMPLE_RATE * MAX_CUT_SEARCH_MS.min(self.window_ms / 4) / 1000) as usize
+    }
+}
+
+enum WorkerInput {
+    /// 16 kHz audio, preceded by `skipped` samples dropped while the worker
+    /// was behind.
+    Audio { samples: Vec<f32>, skipped: usize },
+    /// Finalise the open window, e.g. because capture stopped.
+    Flush,
+}
+
+/// A transcription consumer of a capture stream. Mono audio is resampled to
+/// 16 kHz here and handed to a worker thread that owns the model, so
+/// inference never stalls the capture loop.
+pub struct TranscriptionSubscriber {
+    id: u32,
+    resampler: Option<(u32, StreamingResampler)>,
+    drift_ppm: f64,
+    audio: mpsc::SyncSender<WorkerInput>,
+    /// Resampled audio not yet handed to the worker.
+    pending: Vec<f32>,
+    skipped: usize,
+    step_samples: usize,
+    max_pending: usize,
+}
+
+impl TranscriptionSubscriber {
+    /// Loads the model synchronously so a bad path or corrupt file is
+    /// reported to the caller rather than on the worker.
+    pub fn new(id: u32, config: TranscriptionConfig, callback: &JsFunction) -> napi::Result<Self> {
+        let tsfn: ThreadsafeFunction<TranscriptEvent, ErrorStrategy::Fatal> =
+            callback.create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))?;
+        let engine = engine::Engine::load(&config)?;
+        let step_samples = config.step_samples();
+        let max_pending = config.window_samples() * MAX_PENDING_WINDOWS;
+
+        let (audio, audio_rx) = mpsc::sync_channel(QUEUE_BLOCKS);
+        thread::spawn(move || run_worker(engine, config, audio_rx, tsfn));
+
+        Ok(Self {
+            id,
+            resampler: None,
+            drift_ppm: 0.0,
+            audio,
+            pending: Vec::with_capacity(step_samples * 2),
+            skipped: 0,
+            step_samples,
+            max_pending,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn set_drift_ppm(&mut self, ppm: f64) {
+        self.drift_ppm = ppm;
+        if let Some((_, ref mut resampler)) = self.resampler {
+            resampler.set_drift_ppm(ppm);
+        }
+    }
+
+    pub fn push(&mut self, mono: &[f32], input_sample_rate: u32) {
+        if mono.is_empty() {
+            return;
+        }
+
+        let resampler = match self.resampler {
+            Some((rate, ref mut resampler)) if rate == input_sample_rate => resampler,
+            _ => {
+                let mut resampler =
+                    StreamingResampler::new(input_sample_rate as f64, SAMPLE_RATE as f64);
+                resampler.set_drift_ppm(self.drift_ppm);
+                &mut self.resampler.insert((input_sample_rate, resampler)).1
+            }
+        };
+
+        self.pending.extend(resampler.process(mono));
+        if self.pending.len() >= self.step_samples {
+            self.hand_off();
+        }
+    }
+
+    /// Forgets resampler state and audio a previous run did not hand over;
+    /// its open window was already flushed by `finish`. Timestamps carry on.
+    pub fn reset(&mut self) {
+        self.resampler = None;
+        self.pending.clear();
+        self.skipped = 0;
+    }
+
+    /// Hands the pending audio to the worker at capture end and finalises
+    /// the open window. Blocks at most for the decode in progress.
+    pub fn finish(&mut self) {
+        let samples = std::mem::take(&mut self.pending);
+        let skipped = std::mem::take(&mut self.skipped);
+        if !samples.is_empty() || skipped > 0 {
+            let _ = self.audio.send(WorkerInput::Audio { samples, skipped });
+        }
+        let _ = self.audio.send(WorkerInput::Flush);
+    }
+
+    /// Queues the pending audio without blocking. A worker still busy with
+    /// earlier audio gets it later as one larger block; past
+    /// `MAX_PENDING_WINDOWS` the backlog is dropped and the worker told to
+    /// skip over it.
+    fn hand_off(&mut self) {
+        let input = WorkerInput::Audio {
+            samples: std::mem::take(&mut self.pending),
+            skipped: self.skipped,
+        };
+        match self.audio.try_send(input) {
+            Ok(()) => self.skipped = 0,
+            Err(mpsc::TrySendError::Full(WorkerInput::Audio { samples, .. })) => {
+                self.pending = samples;
+                if self.pending.len() > self.max_pending {
+                    self.skipped += self.pending.len();
+                    self.pending.clear();
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+impl Drop for TranscriptionSubscriber {
+    fn drop(&mut self) {
+        // The worker finalises whatever it has once the channel closes.
+        if !self.pending.is_empty() {
+            self.hand_off();
+        }
+    }
+}
+
+/// Decodes a sliding window: a partial result every `step_ms` of new audio,
+/// and a final one once the window is full. Audio that queued up during a
+/// decode is taken in one go, so only the latest partial is decoded. Dropping
+/// the subscriber closes the channel, which finalises whatever audio is left.
+fn run_worker(
+    mut engine: engine::Engine,
+    config: TranscriptionConfig,
+    audio: mpsc::Receiver<WorkerInput>,
+    tsfn: ThreadsafeFunction<TranscriptEvent, ErrorStrategy::Fatal>,
+) {
+    let mut windows = Windower::new(&config);
+
+    let mut emit = |window: Window| {
+        if rms(&window.samples) < SILENCE_RMS {
+            return;
+        }
+        match engine.transcribe(&window.samples) {
+            Ok(text) if !text.is_empty() => {
+                let event = TranscriptEvent {
+                    text,
+                    is_final: window.is_final,
+                    start_ms: samples_to_ms(window.start),
+                    end_ms: samples_to_ms(window.start + window.samples.len()),
+                };
+                tsfn.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Transcription error: {}", e),
+        }
+    };
+
+    while let Ok(input) = audio.recv() {
+        let mut ready = Vec::new();
+        for input in std::iter::once(input).chain(audio.try_iter()) {
+            match input {
+                WorkerInput::Audio { samples, skipped } => {
+                    ready.extend(windows.skip(skipped));
+                    ready.extend(windows.push(&samples));
+                }
+                WorkerInput::Flush => ready.extend(windows.finish()),
+            }
+        }
+        ready.extend(windows.partial());
+        ready.into_iter().for_each(&mut emit);
+    }
+
+    windows.finish().into_iter().for_each(emit);
+}
+
+/// A stretch of audio to decode, `start` in samples since the subscription
+/// began.
+#[derive(Debug, PartialEq)]
+struct Window {
+    start: usize,
+    samples: Vec<f32>,
+    is_final: bool,
+}
+
+/// Splits the stream into windows of at most `window_len` samples, cut at
+/// the quietest point near their end, with partials every `step` samples.
+struct Windower {
+    step: usize,
+    window_len: usize,
+    cut_search: usize,
+    window: Vec<f32>,
+    window_start: usize,
+    decoded_len: usize,
+}
+
+impl Windower {
+    fn new(config: &TranscriptionConfig) -> Self {
+        let window_len = config.window_samples();
+        Self {
+            step: config.step_samples(),
+            window_len,
+            cut_search: config.cut_search_samples(),
+            window: Vec::with_capacity(window_len),
+            window_start: 0,
+            decoded_len: 0,
+        }
+    }
+
+    /// Appends audio and returns the windows it completes.
+    fn push(&mut self, mut samples: &[f32]) -> Vec<Window> {
+        let mut finals = Vec::new();
+        while !samples.is_empty() {
+            let take = (self.window_len - self.window.len()).min(samples.len());
+            self.window.extend_from_slice(&samples[..take]);
+            samples = &samples[take..];
+
+            if self.window.len() == self.window_len {
+                let cut = quietest_cut(&self.window, self.cut_search);
+                let rest = self.window.split_off(cut);
+                finals.push(self.close(rest));
+            }
+        }
+        finals
+    }
+
+    /// Audio the worker never received: the open window ends, and the next
+    /// one starts after the hole.
+    fn skip(&mut self, samples: usize) -> Option<Window> {
+        if samples == 0 {
+            return None;
+        }
+        let last = self.finish();
+        self.window_start += samples;
+        last
+    }
+
+    /// The open window as a partial result, once `step` new samples arrived.
+    fn partial(&mut self) -> Option<Window> {
+        if self.window.len() < self.decoded_len + self.step {
+            return None;
+        }
+        self.decoded_len = self.window.len();
+        Some(Window {
+            start: self.window_start,
+            samples: self.window.clone(),
+            is_final: false,
+        })
+    }
+
+    fn finish(&mut self) -> Option<Window> {
+        if self.window.is_empty() {
+            return None;
+        }
+        Some(self.close(Vec::new()))
+    }
+
+    /// Ends the open window and starts the next one with `rest`.
+    fn close(&mut self, rest: Vec<f32>) -> Window {
+        let samples = std::mem::replace(&mut self.window, rest);
+        self.window.reserve(self.window_len);
+        let window = Window {
+            start: self.window_start,
+            samples,
+            is_final: true,
+        };
+        self.window_start += window.samples.len();
+        self.decoded_len = 0;
+        window
+    }
+}
+
+/// Where to end a full window: the middle of its quietest `CUT_FRAME_MS`
+/// frame within the last `search` samples, or its end if that is too short.
+fn quietest_cut(window: &[f32], search: usize) -> usize {
+    let frame = (SAMPLE_RATE * CUT_FRAME_MS / 1000) as usize;
+    let search = search.min(window.len());
+    if search < frame {
+        return window.len();
+    }
+
+    let region = window.len() - search;
+    window[region..]
+        .chunks_exact(frame)
+        .enumerate()
+        .map(|(i, chunk)| (i, chunk.iter().map(|s| s * s).sum::<f32>()))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| region + i * frame + frame / 2)
+        .unwrap_or(window.len())
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = samples.iter().map(|&s| (s as f64) * (s as f64)).sum();
+    (sum / samples.len() as f64).sqrt() as f32
+}
+
+fn samples_to_ms(samples: usize) -> f64 {
+    samples as f64 * 1000.0 / SAMPLE_RATE as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: usize = (SAMPLE_RATE / 1000) as usize;
+
+    fn windower(step_ms: u32, window_ms: u32) -> Windower {
+        Windower::new(&TranscriptionConfig {
+            model_path: String::new(),
+            language: "en".to_string(),
+            threads: 1,
+            step_ms,
+            window_ms,
+        })
+    }
+
+    fn speech(ms: usize) -> Vec<f32> {
+        (0..ms * MS).map(|i| (i as f32 * 0.3).sin() * 0.5).collect()
+    }
+
+    #[test]
+    fn partials_follow_the_step_and_coalesce() {
+        let mut windows = windower(200, 10_000);
+        assert!(windows.push(&speech(150)).is_empty());
+        assert_eq!(windows.partial(), None);
+
+        // Three steps arriving together give one partial with all of them.
+        windows.push(&speech(450));
+        let partial = windows.partial().unwrap();
+        assert_eq!((partial.start, partial.samples.len()), (0, 600 * MS));
+        assert!(!partial.is_final);
+        assert_eq!(windows.partial(), None);
+    }
+
+    #[test]
+    fn full_windows_are_cut_at_the_quietest_point() {
+        // 1 s windows search their last 250 ms for a cut.
+        let mut windows = windower(200, 1_000);
+        let mut audio = speech(1_500);
+        audio[850 * MS..870 * MS].fill(0.0);
+
+        let finals = windows.push(&audio);
+        assert_eq!(finals.len(), 1);
+        assert!(finals[0].is_final);
+        assert_eq!(finals[0].samples.len(), 860 * MS);
+
+        // The rest opens the next window, timed after the cut.
+        let last = windows.finish().unwrap();
+        assert_eq!((last.start, last.samples.len()), (860 * MS, 640 * MS));
+        assert_eq!(windows.finish(), None);
+    }
+
+    #[test]
+    fn windows_without_a_pause_still_end_within_the_search() {
+        let mut windows = windower(200, 1_000);
+        let finals = windows.push(&speech(3_000));
+        assert!(finals.len() >= 2);
+        for window in &finals {
+            let len = window.samples.len();
+            assert!((750 * MS..=1_000 * MS).contains(&len), "{} samples", len);
+        }
+    }
+
+    #[test]
+    fn skipped_audio_closes_the_window_and_moves_the_timeline() {
+        let mut windows = windower(200, 10_000);
+        windows.push(&speech(500));
+        let closed = windows.skip(1_000 * MS).unwrap();
+        assert_eq!((closed.start, closed.samples.len()), (0, 500 * MS));
+        assert!(closed.is_final);
+
+        windows.push(&speech(300));
+        assert_eq!(windows.partial().unwrap().start, 1_500 * MS);
+        assert_eq!(windows.skip(0), None);
+    }
+}
+
+#[cfg(feature = "transcription")]
+mod engine {
+    use super::TranscriptionConfig;
+    use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+
+    pub struct Engine {
+        state: whisper_rs::WhisperState,
+        language: String,
+        threads: i32,
+    }
+
+    impl Engine {
+        pub fn load(config: &TranscriptionConfig) -> napi::Result<Self> {
+            let mut params = WhisperContextParameters::default();
+            params.use_gpu(false);
+            let context =
+                WhisperContext::new_with_params(&config.model_path, params).map_err(|e| {
+                    napi::Error::from_reason(format!(
+                        "Failed to load Whisper model {}: {}",
+                        config.model_path, e
+                    ))
+                })?;
+            let state = context
+                .create_state()
+                .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+
+            Ok(Self {
+                state,
+                language: config.language.clone(),
+                threads: config.threads as i32,
+            })
+        }
+
+        pub fn transcribe(&mut self, audio: &[f32]) -> Result<String, String> {
+            let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+            params.set_n_threads(self.threads);
+            params.set_language(Some(&self.language));
+            params.set_no_context(true);
+            params.set_single_segment(true);
+            params.set_print_progress(false);
+            params.set_print_realtime(false);
+            params.set_print_special(false);
+            params.set_print_timestamps(false);
+
+            self.state.full(params, audio).map_err(|e| e.to_string())?;
+
+            let segments = self.state.full_n_segments().map_err(|e| e.to_string())?;
+            let mut text = String::new();
+            for i in 0..segments {
+                let segment = self
+                    .state
+                    .full_get_segment_text(i)
+                    .map_err(|e| e.to_string())?;
+                text.push_str(&segment);
+            }
+            Ok(text.trim().to_string())
+        }
+    }
+}
+
+#[cfg(not(feature = "transcription"))]
+mod engine {
+    use super::TranscriptionConfig;
+
+    pub struct Engine;
+
+    impl Engine {
+        pub fn load(_config: &TranscriptionConfig) -> napi::Result<Self> {
+            Err(napi::Error::from_reason(
+                "Transcription support was not compiled in (enable the `transcription` feature)",
+            ))
+        }
+
+        pub fn transcribe(&mut self, _audio: &[f32]) -> Result<String, String> {
+            Ok(String::new())
+        }
+    }
+}
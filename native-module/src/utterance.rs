@@ -0,0 +1,529 @@
+use napi::bindgen_prelude::Buffer;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::JsFunction;
+use std::sync::Arc;
+
+use crate::audio_config::{FRAME_SAMPLES, SAMPLE_RATE};
+use crate::conversion::f32_to_i16;
+use crate::diarization::{DiarizationConfig, SpeakerLabeler, SpeakerWorker, DEFAULT_MAX_SPEAKERS};
+use crate::filters::{parse_high_pass_hz, FilterChain, FilterStage, DEFAULT_HIGH_PASS_HZ};
+use crate::pipeline::{Frame, Pipeline, Stage};
+use crate::silence_suppression::{FrameAction, SilenceSuppressionConfig, SilenceSuppressor};
+use crate::streaming_resampler::StreamingResampler;
+
+const DEFAULT_MIN_DURATION_MS: u32 = 300;
+const DEFAULT_MAX_DURATION_MS: u32 = 15_000;
+
+#[napi(object)]
+pub struct UtteranceOptions {
+    /// Attach each complete utterance (16 kHz mono i16 LE) to its
+    /// `speechEnd` event. Default false.
+    pub deliver_audio: Option<bool>,
+    /// Speech shorter than this is ignored, default 300 ms.
+    pub min_duration_ms: Option<u32>,
+    /// Longer speech is split into several utterances, default 15000 ms.
+    pub max_duration_ms: Option<u32>,
+    /// Label each utterance with a speaker id (on-device clustering of
+    /// voice embeddings, on a worker thread). Default false.
+    pub diarize: Option<bool>,
+    /// ONNX speaker embedding model for `diarize`, e.g. an ECAPA-TDNN export
+    /// taking 80-band fbank features. Needs the `speaker-model` build
+    /// feature; without a model voices are compared by MFCC statistics,
+    /// which only separates clearly different voices.
+    pub speaker_model: Option<String>,
+    /// Cosine similarity (0-1) needed to match a known speaker, default 0.5
+    /// with `speakerModel` and 0.85 without. Lower merges more voices into
+    /// one speaker.
+    pub speaker_threshold: Option<f64>,
+    /// Speakers distinguished at most, default 8.
+    pub max_speakers: Option<u32>,
+    /// High-pass corner in Hz applied before speech detection, e.g. 20 so DC
+    /// offset and rumble do not read as speech. Default 0 (off).
+    pub high_pass_hz: Option<f64>,
+}
+
+#[napi(object)]
+pub struct UtteranceEvent {
+    /// `"speechStart"`, `"speechEnd"` or, with `diarize`, `"speaker"`.
+    pub kind: String,
+    /// Milliseconds of audio since the subscription began.
+    pub start_ms: f64,
+    /// Set on `speechEnd` and `speaker`.
+    pub end_ms: Option<f64>,
+    /// The utterance hit `maxDurationMs`; the next one starts at `endMs`.
+    pub forced_split: bool,
+    pub audio: Option<Buffer>,
+    /// Speaker id on `speaker` events, which follow each `speechEnd` (same
+    /// `startMs`/`endMs`) once its voice has been compared. Numbered from 0
+    /// in order of first appearance; absent if the utterance had too little
+    /// voice or the labelling fell behind.
+    pub speaker: Option<u32>,
+}
+
+#[derive(Clone)]
+pub struct SegmenterConfig {
+    pub deliver_audio: bool,
+    pub min_duration_ms: u32,
+    pub max_duration_ms: u32,
+    pub diarization: Option<DiarizationConfig>,
+    pub high_pass_hz: f64,
+}
+
+impl Default for SegmenterConfig {
+    fn default() -> Self {
+        Self {
+            deliver_audio: false,
+            min_duration_ms: DEFAULT_MIN_DURATION_MS,
+            max_duration_ms: DEFAULT_MAX_DURATION_MS,
+            diarization: None,
+            high_pass_hz: DEFAULT_HIGH_PASS_HZ,
+        }
+    }
+}
+
+impl SegmenterConfig {
+    pub fn from_options(options: Option<UtteranceOptions>) -> napi::Result<Self> {
+        let mut config = Self::default();
+        let options = match options {
+            Some(o) => o,
+            None => return Ok(config),
+        };
+
+        config.deliver_audio = options.deliver_audio.unwrap_or(false);
+        if let Some(min) = options.min_duration_ms {
+            config.min_duration_ms = min;
+        }
+        if let Some(max) = options.max_duration_ms {
+            config.max_duration_ms = max;
+        }
+        if config.max_duration_ms == 0 || config.max_duration_ms < config.min_duration_ms {
+            return Err(napi::Error::from_reason(format!(
+                "Unsupported utterance durations: min {} ms, max {} ms",
+                config.min_duration_ms, config.max_duration_ms
+            )));
+        }
+
+        if let Some(threshold) = options.speaker_threshold {
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err(napi::Error::from_reason(format!(
+                    "Unsupported speaker threshold: {}",
+                    threshold
+                )));
+            }
+        }
+        if options.diarize.unwrap_or(false) {
+            config.diarization = Some(DiarizationConfig {
+                model_path: options.speaker_model,
+                threshold: options.speaker_threshold,
+                max_speakers: options.max_speakers.unwrap_or(DEFAULT_MAX_SPEAKERS),
+            });
+        }
+        if let Some(hz) = options.high_pass_hz {
+            config.high_pass_hz = parse_high_pass_hz(hz)?;
+        }
+        Ok(config)
+    }
+
+    /// Whether segments carry their audio: for delivery or to embed it.
+    fn keeps_audio(&self) -> bool {
+        self.deliver_audio || self.diarization.is_some()
+    }
+
+    fn min_samples(&self) -> usize {
+        ms_to_samples(self.min_duration_ms)
+    }
+
+    fn max_samples(&self) -> usize {
+        ms_to_samples(self.max_duration_ms)
+    }
+}
+
+pub enum Segment {
+    Start {
+        start: usize,
+    },
+    End {
+        start: usize,
+        end: usize,
+        forced_split: bool,
+        audio: Option<Vec<i16>>,
+    },
+}
+
+struct Utterance {
+    start: usize,
+    announced: bool,
+    /// Continues a forced split, so it is announced without waiting for the
+    /// minimum length.
+    continued: bool,
+    audio: Vec<i16>,
+}
+
+/// Groups 16 kHz frames into utterances using the silence suppressor's
+/// speech/hangover decision. Positions are in samples since creation.
+pub struct UtteranceSegmenter {
+    config: SegmenterConfig,
+    suppressor: SilenceSuppressor,
+    position: usize,
+    current: Option<Utterance>,
+}
+
+impl UtteranceSegmenter {
+    pub fn new(config: SegmenterConfig) -> Self {
+        Self {
+            config,
+            suppressor: SilenceSuppressor::new(SilenceSuppressionConfig::default()),
+            position: 0,
+            current: None,
+        }
+    }
+
+    pub fn process_frame(&mut self, frame: &[i16]) -> Vec<Segment> {
+        let speech = matches!(self.suppressor.process(frame), FrameAction::Send(_));
+        let frame_start = self.position;
+        self.position += frame.len();
+
+        let mut segments = Vec::new();
+        if !speech {
+            if let Some(utterance) = self.current.take() {
+                if utterance.announced {
+                    segments.push(Segment::End {
+                        start: utterance.start,
+                        end: frame_start,
+                        forced_split: false,
+                        audio: self.config.keeps_audio().then_some(utterance.audio),
+                    });
+                }
+            }
+            return segments;
+        }
+
+        let keep_audio = self.config.keeps_audio();
+        let utterance = self.current.get_or_insert_with(|| Utterance {
+            start: frame_start,
+            announced: false,
+            continued: false,
+            audio: Vec::new(),
+        });
+        if keep_audio {
+            utterance.audio.extend_from_slice(frame);
+        }
+
+        let length = self.position - utterance.start;
+        if !utterance.announced && (utterance.continued || length >= self.config.min_samples()) {
+            utterance.announced = true;
+            segments.push(Segment::Start {
+                start: utterance.start,
+            });
+        }
+
+        if length >= self.config.max_samples() {
+            let audio = std::mem::take(&mut utterance.audio);
+            segments.push(Segment::End {
+                start: utterance.start,
+                end: self.position,
+                forced_split: true,
+                audio: keep_audio.then_some(audio),
+            });
+            // The continuation is announced with its first frame, so speech
+            // ending right at the split leaves no empty utterance behind.
+            utterance.start = self.position;
+            utterance.announced = false;
+            utterance.continued = true;
+        }
+
+        segments
+    }
+
+    /// Ends an utterance still open at the end of the input, if it is long
+    /// enough to have been announced.
+    pub fn finish(&mut self) -> Option<Segment> {
+        let utterance = self.current.take().filter(|u| u.announced)?;
+        Some(Segment::End {
+            start: utterance.start,
+            end: self.position,
+            forced_split: false,
+            audio: self.config.keeps_audio().then_some(utterance.audio),
+        })
+    }
+}
+
+/// An utterance consumer of a capture stream: resamples the mono mix to
+/// 16 kHz, segments it and reports `speechStart`/`speechEnd` events.
+pub struct UtteranceSubscriber {
+    id: u32,
+    resampler: Option<(u32, StreamingResampler)>,
+    drift_ppm: f64,
+    /// Filtering of the 16 kHz audio ahead of speech detection.
+    pipeline: Pipeline,
+    frame_buffer: Vec<i16>,
+    segmenter: UtteranceSegmenter,
+    /// Labels finished utterances when diarizing.
+    speakers: Option<SpeakerWorker>,
+    deliver_audio: bool,
+    events: Arc<dyn Fn(UtteranceEvent) + Send + Sync>,
+}
+
+impl UtteranceSubscriber {
+    pub fn new(id: u32, config: SegmenterConfig, callback: &JsFunction) -> napi::Result<Self> {
+        let tsfn: ThreadsafeFunction<UtteranceEvent, ErrorStrategy::Fatal> =
+            callback.create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))?;
+
+        Self::with_events(
+            id,
+            config,
+            Box::new(move |event| {
+                tsfn.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+            }),
+        )
+    }
+
+    /// Reports events to native code instead of a JS callback. A speaker
+    /// model is loaded here, so a bad path is reported to the caller.
+    pub fn with_events(
+        id: u32,
+        config: SegmenterConfig,
+        events: Box<dyn Fn(UtteranceEvent) + Send + Sync>,
+    ) -> napi::Result<Self> {
+        let events: Arc<dyn Fn(UtteranceEvent) + Send + Sync> = Arc::from(events);
+        let speakers = match config.diarization {
+            Some(ref diarization) => {
+                let labeler = SpeakerLabeler::new(diarization).map_err(napi::Error::from_reason)?;
+                let events = events.clone();
+                Some(SpeakerWorker::spawn(
+                    labeler,
+                    Arc::new(move |label| {
+                        events(UtteranceEvent {
+                            kind: "speaker".to_string(),
+                            start_ms: samples_to_ms(label.start),
+                            end_ms: Some(samples_to_ms(label.end)),
+                            forced_split: false,
+                            audio: None,
+                            speaker: label.speaker,
+                        })
+                    }),
+                ))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            id,
+            resampler: None,
+            drift_ppm: 0.0,
+            pipeline: detection_pipeline(config.high_pass_hz),
+            frame_buffer: Vec::with_capacity(FRAME_SAMPLES * 4),
+            deliver_audio: config.deliver_audio,
+            segmenter: UtteranceSegmenter::new(config),
+            speakers,
+            events,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn set_drift_ppm(&mut self, ppm: f64) {
+        self.drift_ppm = ppm;
+        if let Some((_, ref mut resampler)) = self.resampler {
+            resampler.set_drift_ppm(ppm);
+        }
+    }
+
+    pub fn push(&mut self, mono: &[f32], input_sample_rate: u32) {
+        if mono.is_empty() {
+            return;
+        }
+
+        let resampler = match self.resampler {
+            Some((rate, ref mut resampler)) if rate == input_sample_rate => resampler,
+            _ => {
+                let mut resampler =
+                    StreamingResampler::new(input_sample_rate as f64, SAMPLE_RATE as f64);
+                resampler.set_drift_ppm(self.drift_ppm);
+                &mut self.resampler.insert((input_sample_rate, resampler)).1
+            }
+        };
+        let mut frame = Frame::new(resampler.process(mono), 1);
+        self.pipeline.process(&mut frame);
+        self.frame_buffer
+            .extend(frame.samples.into_iter().map(f32_to_i16));
+
+        while self.frame_buffer.len() >= FRAME_SAMPLES {
+            let frame: Vec<i16> = self.frame_buffer.drain(0..FRAME_SAMPLES).collect();
+            for segment in self.segmenter.process_frame(&frame) {
+                self.deliver(segment);
+            }
+        }
+    }
+
+    /// Forgets buffered audio and filter state before a new capture run.
+    /// Positions carry on from the previous run.
+    pub fn reset(&mut self) {
+        self.resampler = None;
+        self.pipeline.reset();
+        self.frame_buffer.clear();
+    }
+
+    /// Reports the utterance in progress at the end of the input and waits
+    /// for the outstanding speaker labels.
+    pub fn finish(&mut self) {
+        if let Some(segment) = self.segmenter.finish() {
+            self.deliver(segment);
+        }
+        if let Some(ref speakers) = self.speakers {
+            speakers.flush();
+        }
+    }
+
+    fn deliver(&mut self, segment: Segment) {
+        match segment {
+            Segment::Start { start } => (self.events)(UtteranceEvent {
+                kind: "speechStart".to_string(),
+                start_ms: samples_to_ms(start),
+                end_ms: None,
+                forced_split: false,
+                audio: None,
+                speaker: None,
+            }),
+            Segment::End {
+                start,
+                end,
+                forced_split,
+                audio,
+            } => {
+                (self.events)(UtteranceEvent {
+                    kind: "speechEnd".to_string(),
+                    start_ms: samples_to_ms(start),
+                    end_ms: Some(samples_to_ms(end)),
+                    forced_split,
+                    audio: audio.as_ref().filter(|_| self.deliver_audio).map(|pcm| {
+                        let bytes: Vec<u8> = pcm.iter().flat_map(|s| s.to_le_bytes()).collect();
+                        Buffer::from(bytes)
+                    }),
+                    speaker: None,
+                });
+                // Queued once `speechEnd` is out, so `speaker` always follows.
+                if let (Some(speakers), Some(pcm)) = (self.speakers.as_ref(), audio) {
+                    speakers.submit(start, end, pcm);
+                }
+            }
+        }
+    }
+}
+
+fn detection_pipeline(high_pass_hz: f64) -> Pipeline {
+    let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+    if high_pass_hz > 0.0 {
+        stages.push(Box::new(FilterStage::new(
+            "highPass",
+            FilterChain::high_pass(SAMPLE_RATE, high_pass_hz),
+            1,
+        )));
+    }
+    Pipeline::new(stages)
+}
+
+fn ms_to_samples(ms: u32) -> usize {
+    (SAMPLE_RATE as usize * ms as usize) / 1000
+}
+
+fn samples_to_ms(samples: usize) -> f64 {
+    samples as f64 * 1000.0 / SAMPLE_RATE as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: usize = FRAME_SAMPLES;
+
+    /// `(kind, start, end, forced_split)` per segment, in frames.
+    type Event = (&'static str, usize, Option<usize>, bool);
+
+    fn segmenter(min_duration_ms: u32, max_duration_ms: u32) -> UtteranceSegmenter {
+        let mut segmenter = UtteranceSegmenter::new(SegmenterConfig {
+            min_duration_ms,
+            max_duration_ms,
+            ..SegmenterConfig::default()
+        });
+        // The suppressor starts in hangover; let it settle.
+        assert!(feed(&mut segmenter, false, 15).is_empty());
+        segmenter
+    }
+
+    fn feed(segmenter: &mut UtteranceSegmenter, speech: bool, frames: usize) -> Vec<Event> {
+        let frame = vec![if speech { 2_000 } else { 0 }; FRAME];
+        (0..frames)
+            .flat_map(|_| segmenter.process_frame(&frame))
+            .map(|segment| match segment {
+                Segment::Start { start } => ("start", start / FRAME, None, false),
+                Segment::End {
+                    start,
+                    end,
+                    forced_split,
+                    ..
+                } => ("end", start / FRAME, Some(end / FRAME), forced_split),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn utterances_end_after_the_hangover() {
+        let mut segmenter = segmenter(300, 15_000);
+        // Announced once 300 ms (15 frames) long.
+        let started = feed(&mut segmenter, true, 20);
+        assert_eq!(started, [("start", 15, None, false)]);
+        // 200 ms of hangover belongs to the utterance.
+        let ended = feed(&mut segmenter, false, 20);
+        assert_eq!(ended, [("end", 15, Some(45), false)]);
+        assert!(segmenter.finish().is_none());
+    }
+
+    #[test]
+    fn speech_shorter_than_the_minimum_is_ignored() {
+        let mut segmenter = segmenter(300, 15_000);
+        // 40 ms of speech plus 200 ms hangover stays under 300 ms.
+        feed(&mut segmenter, true, 2);
+        assert!(feed(&mut segmenter, false, 20).is_empty());
+    }
+
+    #[test]
+    fn long_speech_is_split_and_continued() {
+        let mut segmenter = segmenter(300, 1_000);
+        let events = feed(&mut segmenter, true, 60);
+        assert_eq!(
+            events,
+            [
+                ("start", 15, None, false),
+                ("end", 15, Some(65), true),
+                ("start", 65, None, false),
+            ]
+        );
+        let ended = feed(&mut segmenter, false, 20);
+        assert_eq!(ended, [("end", 65, Some(85), false)]);
+    }
+
+    #[test]
+    fn a_split_at_the_end_of_speech_leaves_no_empty_utterance() {
+        let mut segmenter = segmenter(300, 1_000);
+        // 40 frames of speech and 10 of hangover reach exactly 1000 ms.
+        feed(&mut segmenter, true, 40);
+        let events = feed(&mut segmenter, false, 20);
+        assert_eq!(events, [("end", 15, Some(65), true)]);
+        assert!(segmenter.finish().is_none());
+    }
+
+    #[test]
+    fn finish_ends_an_announced_utterance() {
+        let mut segmenter = segmenter(300, 15_000);
+        feed(&mut segmenter, true, 20);
+        match segmenter.finish() {
+            Some(Segment::End { start, end, .. }) => {
+                assert_eq!((start, end), (15 * FRAME, 35 * FRAME))
+            }
+            _ => panic!("expected the open utterance"),
+        }
+    }
+}
@@ -1,3 +1,10 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::conversion::f32_to_i16;
+use crate::pipeline::{Flow, Frame, Stage};
+use crate::silence_suppression::{FrameAction, SilenceSuppressionConfig, SilenceSuppressor};
+
 #[derive(Default)]
 pub struct Vad {
 }
@@ -11,3 +18,77 @@ impl Vad {
         0.05
     }
 }
+
+/// Speech gate: passes the frames the silence suppressor's speech decision
+/// (level threshold plus hangover) accepts and drops the rest outright,
+/// without the keepalive frames `suppressSilence` sends.
+pub struct VadStage {
+    detector: SilenceSuppressor,
+    speech: u64,
+    silence: u64,
+}
+
+impl VadStage {
+    pub fn new(frame_duration: Duration) -> Self {
+        Self {
+            detector: SilenceSuppressor::new(SilenceSuppressionConfig {
+                frame_duration,
+                silence_keepalive_interval: Duration::MAX,
+                ..SilenceSuppressionConfig::default()
+            }),
+            speech: 0,
+            silence: 0,
+        }
+    }
+}
+
+impl Stage for VadStage {
+    fn name(&self) -> &'static str {
+        "vad"
+    }
+
+    fn process(&mut self, frame: &mut Frame) -> Flow {
+        let pcm: Vec<i16> = frame.mono().into_iter().map(f32_to_i16).collect();
+        if matches!(self.detector.process(&pcm), FrameAction::Send(_)) {
+            self.speech += 1;
+            Flow::Continue
+        } else {
+            self.silence += 1;
+            Flow::Drop
+        }
+    }
+
+    fn reset(&mut self) {
+        self.detector.reset();
+    }
+
+    fn stats(&self) -> HashMap<String, f64> {
+        HashMap::from([
+            ("speech".to_string(), self.speech as f64),
+            ("silence".to_string(), self.silence as f64),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_silence_after_the_hangover_without_keepalives() {
+        let mut vad = VadStage::new(Duration::from_millis(20));
+        let mut flow = |level: f32| vad.process(&mut Frame::new(vec![level; 320], 1));
+        assert_eq!(flow(0.1), Flow::Continue);
+        // The 200 ms hangover, then nothing until speech returns.
+        let silence: Vec<Flow> = (0..30).map(|_| flow(0.0)).collect();
+        assert!(silence[..10].iter().all(|&f| f == Flow::Continue));
+        assert!(silence[10..].iter().all(|&f| f == Flow::Drop));
+        assert_eq!(flow(0.1), Flow::Continue);
+
+        vad.reset();
+        assert_eq!(
+            vad.process(&mut Frame::new(vec![0.0; 320], 1)),
+            Flow::Continue
+        );
+    }
+}
@@ -0,0 +1,335 @@
+use anyhow::{anyhow, bail, Result};
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::conversion::{ConversionConfig, I16Converter};
+use crate::loudness::LoudnessSummary;
+use crate::sample_format::{SampleEncoding, SampleFormat};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Fixed part of an EBU Tech 3285 v2 `bext` chunk; no coding history follows.
+const BEXT_BYTES: u32 = 602;
+/// Bytes before the `data` payload in files written here.
+const HEADER_BYTES: u32 = 12 + 24 + 8 + BEXT_BYTES + 8;
+/// `bext` value for a loudness field that was not measured.
+const BEXT_UNKNOWN: i16 = 0x7FFF;
+
+/// Interleaved samples decoded from a WAV file.
+pub struct WavAudio {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub samples: Vec<f32>,
+}
+
+/// Reads a PCM (16/24/32-bit) or float (32/64-bit) WAV file, including
+/// `WAVE_FORMAT_EXTENSIBLE` ones.
+pub fn read_wav(path: &Path) -> Result<WavAudio> {
+    let bytes = fs::read(path)?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        bail!("{} is not a WAV file", path.display());
+    }
+
+    let mut format: Option<(SampleFormat, u32)> = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into()?) as usize;
+        let body = &bytes[offset + 8..(offset + 8 + size).min(bytes.len())];
+
+        match id {
+            b"fmt " => format = Some(parse_fmt(body)?),
+            b"data" => {
+                let (format, sample_rate) =
+                    format.ok_or_else(|| anyhow!("WAV data chunk before fmt chunk"))?;
+                return Ok(WavAudio {
+                    sample_rate,
+                    channels: format.channels(),
+                    samples: format.decode(body),
+                });
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even size.
+        offset += 8 + size + size % 2;
+    }
+
+    bail!("{} has no audio data", path.display())
+}
+
+fn parse_fmt(body: &[u8]) -> Result<(SampleFormat, u32)> {
+    if body.len() < 16 {
+        bail!("Truncated WAV fmt chunk");
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+    let mut tag = u16_at(0);
+    let channels = u16_at(2) as usize;
+    let sample_rate = u32::from_le_bytes(body[4..8].try_into()?);
+    let block_align = u16_at(12) as usize;
+    let bits = u16_at(14);
+
+    if tag == WAVE_FORMAT_EXTENSIBLE && body.len() >= 26 {
+        // The sub-format GUID starts with the plain format tag.
+        tag = u16_at(24);
+    }
+    let is_float = match tag {
+        WAVE_FORMAT_PCM => false,
+        WAVE_FORMAT_IEEE_FLOAT => true,
+        other => bail!("Unsupported WAV format tag: {:#x}", other),
+    };
+
+    let format = SampleFormat::from_wave_format(is_float, bits, channels, block_align)
+        .map_err(|e| anyhow!(e))?;
+    Ok((format, sample_rate))
+}
+
+/// Streams interleaved samples to a 16-bit PCM or 32-bit float WAV file.
+/// Sizes in the header are kept current by `update_header`, so a file cut
+/// short (e.g. the process is killed) stays readable up to the last update.
+/// A broadcast-wave `bext` chunk carries loudness set with `set_loudness`.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    encoding: SampleEncoding,
+    channels: u16,
+    sample_rate: u32,
+    data_bytes: u32,
+    loudness: LoudnessSummary,
+    converter: I16Converter,
+}
+
+impl WavWriter {
+    pub fn create(
+        path: &Path,
+        sample_rate: u32,
+        channels: u16,
+        encoding: SampleEncoding,
+    ) -> Result<Self> {
+        if !matches!(encoding, SampleEncoding::I16 | SampleEncoding::F32) {
+            bail!("Unsupported WAV output encoding: {:?}", encoding);
+        }
+
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            encoding,
+            channels,
+            sample_rate,
+            data_bytes: 0,
+            loudness: LoudnessSummary::default(),
+            converter: I16Converter::new(ConversionConfig::default()),
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        for &sample in samples {
+            match self.encoding {
+                SampleEncoding::I16 => {
+                    let sample = self.converter.convert_sample(sample);
+                    self.file.write_all(&sample.to_le_bytes())?
+                }
+                _ => self.file.write_all(&sample.to_le_bytes())?,
+            }
+        }
+        self.data_bytes = self
+            .data_bytes
+            .saturating_add((samples.len() * self.encoding.bytes()) as u32);
+        Ok(())
+    }
+
+    /// 16-bit samples written as they are, without a round trip through
+    /// `f32`. Only valid for `SampleEncoding::I16` files.
+    pub fn write_i16(&mut self, samples: &[i16]) -> Result<()> {
+        if self.encoding != SampleEncoding::I16 {
+            bail!("16-bit samples written to a {:?} WAV file", self.encoding);
+        }
+        for &sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_bytes = self.data_bytes.saturating_add((samples.len() * 2) as u32);
+        Ok(())
+    }
+
+    /// Dither and soft clipping for 16-bit files; both off by default.
+    pub fn set_conversion(&mut self, config: ConversionConfig) {
+        self.converter = I16Converter::new(config);
+    }
+
+    /// Samples limited to full scale while writing a 16-bit file.
+    pub fn clipped_samples(&self) -> u64 {
+        self.converter.clipped()
+    }
+
+    /// Loudness recorded in the `bext` chunk on the next header update.
+    pub fn set_loudness(&mut self, loudness: LoudnessSummary) {
+        self.loudness = loudness;
+    }
+
+    pub fn update_header(&mut self) -> Result<()> {
+        let end = self.file.stream_position()?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.seek(SeekFrom::Start(end))?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.update_header()
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let sample_bytes = self.encoding.bytes() as u16;
+        let block_align = sample_bytes * self.channels;
+        let tag = match self.encoding {
+            SampleEncoding::I16 => WAVE_FORMAT_PCM,
+            _ => WAVE_FORMAT_IEEE_FLOAT,
+        };
+
+        let f = &mut self.file;
+        f.write_all(b"RIFF")?;
+        f.write_all(
+            &(HEADER_BYTES - 8)
+                .saturating_add(self.data_bytes)
+                .to_le_bytes(),
+        )?;
+        f.write_all(b"WAVEfmt ")?;
+        f.write_all(&16u32.to_le_bytes())?;
+        f.write_all(&tag.to_le_bytes())?;
+        f.write_all(&self.channels.to_le_bytes())?;
+        f.write_all(&self.sample_rate.to_le_bytes())?;
+        f.write_all(&(self.sample_rate * block_align as u32).to_le_bytes())?;
+        f.write_all(&block_align.to_le_bytes())?;
+        f.write_all(&(sample_bytes * 8).to_le_bytes())?;
+        f.write_all(b"bext")?;
+        f.write_all(&BEXT_BYTES.to_le_bytes())?;
+        f.write_all(&bext_body(&self.loudness))?;
+        f.write_all(b"data")?;
+        f.write_all(&self.data_bytes.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+/// Fixed `bext` fields: description, originator and dates are left empty;
+/// loudness values are hundredths of LUFS/LU/dBTP.
+fn bext_body(loudness: &LoudnessSummary) -> Vec<u8> {
+    let mut body = Vec::with_capacity(BEXT_BYTES as usize);
+    // Description, Originator, OriginatorReference, OriginationDate/Time.
+    body.resize(256 + 32 + 32 + 10 + 8, 0);
+    body.extend_from_slice(&0u64.to_le_bytes()); // TimeReference
+    body.extend_from_slice(&2u16.to_le_bytes()); // Version
+    body.resize(body.len() + 64, 0); // UMID
+
+    let hundredths = |value: Option<f64>| match value {
+        Some(v) if v.is_finite() => (v * 100.0)
+            .round()
+            .clamp(i16::MIN as f64, (BEXT_UNKNOWN - 1) as f64)
+            as i16,
+        _ => BEXT_UNKNOWN,
+    };
+    for value in [
+        hundredths(loudness.integrated_lufs),
+        BEXT_UNKNOWN, // LoudnessRange
+        hundredths(loudness.true_peak_dbtp),
+        hundredths(loudness.max_momentary_lufs),
+        hundredths(loudness.max_short_term_lufs),
+    ] {
+        body.extend_from_slice(&value.to_le_bytes());
+    }
+    body.resize(BEXT_BYTES as usize, 0); // Reserved
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("nyx-audio-{}-{}.wav", std::process::id(), name))
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn i16_at(bytes: &[u8], offset: usize) -> i16 {
+        i16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    #[test]
+    fn round_trips_i16_and_f32_with_sizes_and_bext() {
+        let samples: Vec<f32> = (0..480).map(|i| (i as f32 / 480.0) - 0.5).collect();
+        for (encoding, tolerance) in [
+            (SampleEncoding::I16, 1.0 / 32_768.0),
+            (SampleEncoding::F32, 0.0),
+        ] {
+            let path = temp_path(&format!("{:?}", encoding));
+            let mut writer = WavWriter::create(&path, 48_000, 2, encoding).unwrap();
+            writer.write(&samples[..240]).unwrap();
+            writer.set_loudness(LoudnessSummary {
+                integrated_lufs: Some(-23.0),
+                true_peak_dbtp: Some(-1.504),
+                max_momentary_lufs: Some(f64::NEG_INFINITY),
+                ..LoudnessSummary::default()
+            });
+            writer.update_header().unwrap();
+
+            // Readable up to the last update while still being written.
+            let partial = read_wav(&path).unwrap();
+            assert_eq!(partial.samples.len(), 240);
+
+            writer.write(&samples[240..]).unwrap();
+            writer.finish().unwrap();
+
+            let bytes = fs::read(&path).unwrap();
+            let data_bytes = (samples.len() * encoding.bytes()) as u32;
+            assert_eq!(bytes.len(), HEADER_BYTES as usize + data_bytes as usize);
+            assert_eq!(&bytes[0..4], b"RIFF");
+            assert_eq!(u32_at(&bytes, 4), bytes.len() as u32 - 8);
+            assert_eq!(
+                &bytes[HEADER_BYTES as usize - 8..HEADER_BYTES as usize - 4],
+                b"data"
+            );
+            assert_eq!(u32_at(&bytes, HEADER_BYTES as usize - 4), data_bytes);
+
+            // `bext` follows the 16-byte fmt chunk.
+            let bext = 12 + 24;
+            assert_eq!(&bytes[bext..bext + 4], b"bext");
+            assert_eq!(u32_at(&bytes, bext + 4), BEXT_BYTES);
+            let body = bext + 8;
+            assert!(bytes[body..body + 338].iter().all(|&b| b == 0));
+            assert_eq!(i16_at(&bytes, body + 346), 2, "version");
+            let loudness = body + 412;
+            assert_eq!(i16_at(&bytes, loudness), -2300);
+            assert_eq!(i16_at(&bytes, loudness + 2), BEXT_UNKNOWN);
+            assert_eq!(i16_at(&bytes, loudness + 4), -150);
+            assert_eq!(i16_at(&bytes, loudness + 6), BEXT_UNKNOWN);
+            assert_eq!(i16_at(&bytes, loudness + 8), BEXT_UNKNOWN);
+
+            let audio = read_wav(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!((audio.sample_rate, audio.channels), (48_000, 2));
+            assert_eq!(audio.samples.len(), samples.len());
+            for (read, written) in audio.samples.iter().zip(&samples) {
+                assert!(
+                    (read - written).abs() <= tolerance,
+                    "{} vs {}",
+                    read,
+                    written
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_i16_samples_for_a_float_file() {
+        let path = temp_path("f32-write-i16");
+        let mut writer = WavWriter::create(&path, 16_000, 1, SampleEncoding::F32).unwrap();
+        assert!(writer.write_i16(&[0, 1]).is_err());
+        drop(writer);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tungstenite::client::{uri_mode, IntoClientRequest};
+use tungstenite::handshake::HandshakeError;
+use tungstenite::http::{HeaderName, HeaderValue};
+use tungstenite::stream::{MaybeTlsStream, Mode};
+use tungstenite::{Message, WebSocket};
+
+use crate::framing::Payload;
+
+const DEFAULT_BUFFER_MS: u32 = 5_000;
+const DEFAULT_INITIAL_BACKOFF_MS: u32 = 250;
+const DEFAULT_MAX_BACKOFF_MS: u32 = 30_000;
+/// How long the worker waits for a frame before checking the socket for
+/// incoming messages.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const READ_TIMEOUT: Duration = Duration::from_millis(1);
+/// Limit on the TCP connect and on each read or write of the upgrade, so an
+/// unreachable or silent server counts as a failed attempt.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[napi(object)]
+pub struct WebSocketOptions {
+    /// `ws://` or `wss://` endpoint receiving one message per chunk: binary,
+    /// or text for the base64 and JSON formats.
+    pub url: String,
+    /// Extra HTTP headers for the upgrade request, e.g. `Authorization`.
+    pub headers: Option<HashMap<String, String>>,
+    /// Text messages sent after every (re)connect, before any audio, e.g. the
+    /// backend's `{"type":"start"}` control message.
+    pub handshake: Option<Vec<String>>,
+    /// Audio kept while disconnected and sent on reconnect, default 5000 ms.
+    /// Older frames are dropped first. Also bounds the audio waiting for the
+    /// sending thread.
+    pub buffer_ms: Option<u32>,
+    /// First reconnect delay, doubled per failed attempt, default 250 ms.
+    pub initial_backoff_ms: Option<u32>,
+    /// Reconnect delay cap, default 30000 ms.
+    pub max_backoff_ms: Option<u32>,
+}
+
+#[napi(object)]
+pub struct WebSocketEvent {
+    /// `"connected"`, `"disconnected"`, `"reconnecting"` or `"message"`.
+    pub kind: String,
+    /// Error text for `"disconnected"`/`"reconnecting"`, or the server's
+    /// text message for `"message"`.
+    pub message: Option<String>,
+    /// Reconnect attempt number, from 1.
+    pub attempt: Option<u32>,
+    /// Delay before the next attempt.
+    pub delay_ms: Option<u32>,
+    /// Frames dropped from the buffers since the last connect.
+    pub dropped_frames: Option<u32>,
+}
+
+impl WebSocketEvent {
+    fn new(kind: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            message: None,
+            attempt: None,
+            delay_ms: None,
+            dropped_frames: None,
+        }
+    }
+}
+
+pub struct WebSocketConfig {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub handshake: Vec<String>,
+    pub max_buffered_frames: usize,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl WebSocketConfig {
+    /// `chunk_ms` is the audio in each of the subscriber's messages, used to
+    /// size the reconnect buffer.
+    pub fn from_options(options: WebSocketOptions, chunk_ms: u32) -> napi::Result<Self> {
+        if !(options.url.starts_with("ws://") || options.url.starts_with("wss://")) {
+            return Err(napi::Error::from_reason(format!(
+                "Unsupported WebSocket URL: {}",
+                options.url
+            )));
+        }
+
+        let initial_backoff_ms = options
+            .initial_backoff_ms
+            .unwrap_or(DEFAULT_INITIAL_BACKOFF_MS)
+            .max(1);
+        let max_backoff_ms = options
+            .max_backoff_ms
+            .unwrap_or(DEFAULT_MAX_BACKOFF_MS)
+            .max(initial_backoff_ms);
+        let buffer_ms = options.buffer_ms.unwrap_or(DEFAULT_BUFFER_MS);
+
+        Ok(Self {
+            url: options.url,
+            headers: options.headers.unwrap_or_default().into_iter().collect(),
+            handshake: options.handshake.unwrap_or_default(),
+            max_buffered_frames: (buffer_ms / chunk_ms.max(1)) as usize,
+            initial_backoff: Duration::from_millis(initial_backoff_ms as u64),
+            max_backoff: Duration::from_millis(max_backoff_ms as u64),
+        })
+    }
+}
+
+/// Streams audio messages to a WebSocket from a worker thread, reconnecting
+/// with exponential backoff and buffering audio while disconnected. Dropping
+/// the sink closes the connection.
+pub struct WebSocketSink {
+    frames: mpsc::SyncSender<Payload>,
+    /// Shared with the worker so a full channel can lose its oldest frame.
+    receiver: Arc<Mutex<mpsc::Receiver<Payload>>>,
+    dropped: Arc<AtomicU32>,
+}
+
+impl WebSocketSink {
+    pub fn new(config: WebSocketConfig, events: Box<dyn Fn(WebSocketEvent) + Send>) -> Self {
+        let (frames, frames_rx) = mpsc::sync_channel(config.max_buffered_frames.max(1));
+        let receiver = Arc::new(Mutex::new(frames_rx));
+        let dropped = Arc::new(AtomicU32::new(0));
+        let worker = Worker::new(config, receiver.clone(), dropped.clone(), events);
+        thread::spawn(move || worker.run());
+        Self {
+            frames,
+            receiver,
+            dropped,
+        }
+    }
+
+    /// Never blocks the capture thread: while the worker is stuck (e.g. in
+    /// a slow write) the oldest queued frame makes room.
+    pub fn send(&self, frame: Payload) {
+        let mut frame = frame;
+        loop {
+            match self.frames.try_send(frame) {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+                Err(TrySendError::Full(rejected)) => {
+                    frame = rejected;
+                    // The worker holds the lock only while receiving, which
+                    // returns at once when the channel is full.
+                    if let Ok(receiver) = self.receiver.lock() {
+                        if receiver.try_recv().is_ok() {
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+struct Worker {
+    config: WebSocketConfig,
+    frames: Arc<Mutex<mpsc::Receiver<Payload>>>,
+    events: Box<dyn Fn(WebSocketEvent) + Send>,
+    pending: VecDeque<Payload>,
+    /// Frames dropped here or by the sink since the last connect.
+    dropped: Arc<AtomicU32>,
+    closed: bool,
+}
+
+impl Worker {
+    fn new(
+        config: WebSocketConfig,
+        frames: Arc<Mutex<mpsc::Receiver<Payload>>>,
+        dropped: Arc<AtomicU32>,
+        events: Box<dyn Fn(WebSocketEvent) + Send>,
+    ) -> Self {
+        Self {
+            config,
+            frames,
+            events,
+            pending: VecDeque::new(),
+            dropped,
+            closed: false,
+        }
+    }
+
+    fn run(mut self) {
+        let mut attempt = 0u32;
+
+        while !self.closed {
+            match self.connect() {
+                Ok(mut socket) => {
+                    attempt = 0;
+                    (self.events)(WebSocketEvent {
+                        dropped_frames: Some(self.dropped.swap(0, Ordering::Relaxed)),
+                        ..WebSocketEvent::new("connected")
+                    });
+
+                    let error = self.stream(&mut socket);
+                    if self.closed {
+                        let _ = socket.close(None);
+                        let _ = socket.flush();
+                        return;
+                    }
+                    (self.events)(WebSocketEvent {
+                        message: error.map(|e| e.to_string()),
+                        ..WebSocketEvent::new("disconnected")
+                    });
+                }
+                Err(e) => {
+                    attempt += 1;
+                    let delay = self.backoff(attempt);
+                    (self.events)(WebSocketEvent {
+                        message: Some(e.to_string()),
+                        attempt: Some(attempt),
+                        delay_ms: Some(delay.as_millis() as u32),
+                        ..WebSocketEvent::new("reconnecting")
+                    });
+                    self.buffer_until(Instant::now() + delay);
+                }
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<Socket, Box<tungstenite::Error>> {
+        let mut request = self.config.url.as_str().into_client_request()?;
+        for (name, value) in &self.config.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| tungstenite::Error::HttpFormat(e.into()))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| tungstenite::Error::HttpFormat(e.into()))?;
+            request.headers_mut().insert(name, value);
+        }
+
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .ok_or(tungstenite::Error::Url(
+                tungstenite::error::UrlError::NoHostName,
+            ))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(match uri_mode(uri)? {
+            Mode::Plain => 80,
+            Mode::Tls => 443,
+        });
+        let stream = connect_tcp(host, port)
+            .and_then(|stream| {
+                stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+                stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+                Ok(stream)
+            })
+            .map_err(tungstenite::Error::Io)?;
+
+        let (mut socket, _) = tungstenite::client_tls(request, stream).map_err(|e| match e {
+            HandshakeError::Failure(e) => e,
+            HandshakeError::Interrupted(_) => {
+                tungstenite::Error::Io(io::ErrorKind::TimedOut.into())
+            }
+        })?;
+        set_read_timeout(&mut socket, Some(READ_TIMEOUT)).map_err(tungstenite::Error::Io)?;
+        for text in &self.config.handshake {
+            socket.send(Message::Text(text.clone()))?;
+        }
+        Ok(socket)
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << (attempt - 1).min(16);
+        (self.config.initial_backoff * factor).min(self.config.max_backoff)
+    }
+
+    /// Sends buffered then live frames until the connection fails (returning
+    /// the error) or the sink is dropped (returning `None`).
+    fn stream(&mut self, socket: &mut Socket) -> Option<tungstenite::Error> {
+        loop {
+            while let Some(frame) = self.pending.pop_front() {
+                let message = match frame {
+                    Payload::Binary(ref bytes) => Message::Binary(bytes.clone()),
+                    Payload::Text(ref text) => Message::Text(text.clone()),
+                };
+                if let Err(e) = socket.send(message) {
+                    self.pending.push_front(frame);
+                    return Some(e);
+                }
+            }
+
+            match self.receive(POLL_INTERVAL) {
+                Ok(frame) => self.pending.push_back(frame),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.closed = true;
+                    return None;
+                }
+            }
+
+            match socket.read() {
+                Ok(Message::Text(text)) => (self.events)(WebSocketEvent {
+                    message: Some(text),
+                    ..WebSocketEvent::new("message")
+                }),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(ref e))
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Some(e),
+            }
+        }
+    }
+
+    /// Keeps accepting frames into the bounded reconnect buffer until
+    /// `deadline`.
+    fn buffer_until(&mut self, deadline: Instant) {
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+            match self.receive(deadline - now) {
+                Ok(frame) => {
+                    self.pending.push_back(frame);
+                    while self.pending.len() > self.config.max_buffered_frames {
+                        self.pending.pop_front();
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn receive(&self, timeout: Duration) -> Result<Payload, RecvTimeoutError> {
+        match self.frames.lock() {
+            Ok(frames) => frames.recv_timeout(timeout),
+            Err(_) => Err(RecvTimeoutError::Disconnected),
+        }
+    }
+}
+
+/// Tries each address of `host` in turn, each for at most `CONNECT_TIMEOUT`.
+fn connect_tcp(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "host has no address");
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+fn set_read_timeout(socket: &mut Socket, timeout: Option<Duration>) -> io::Result<()> {
+    match socket.get_mut() {
+        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(timeout),
+        MaybeTlsStream::Rustls(stream) => stream.get_mut().set_read_timeout(timeout),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    fn config(url: String) -> WebSocketConfig {
+        WebSocketConfig {
+            url,
+            headers: vec![("X-Session".to_string(), "test".to_string())],
+            handshake: vec![r#"{"type":"start"}"#.to_string()],
+            max_buffered_frames: 100,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+        }
+    }
+
+    fn read_binary(socket: &mut WebSocket<TcpStream>) -> Vec<u8> {
+        loop {
+            match socket.read().unwrap() {
+                Message::Binary(data) => return data,
+                Message::Text(_) | Message::Ping(_) | Message::Pong(_) => {}
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn streams_frames_and_reconnects_with_buffered_audio() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let kinds = Arc::new(Mutex::new(Vec::new()));
+        let recorded = kinds.clone();
+        let sink = WebSocketSink::new(
+            config(url),
+            Box::new(move |event| recorded.lock().unwrap().push(event.kind)),
+        );
+
+        // First connection: handshake text, then frames in order.
+        let (stream, _) = listener.accept().unwrap();
+        let mut server = tungstenite::accept(stream).unwrap();
+        match server.read().unwrap() {
+            Message::Text(text) => assert_eq!(text, r#"{"type":"start"}"#),
+            other => panic!("expected handshake, got {:?}", other),
+        }
+        sink.send(Payload::Binary(vec![1, 2]));
+        sink.send(Payload::Binary(vec![3, 4]));
+        assert_eq!(read_binary(&mut server), vec![1, 2]);
+        assert_eq!(read_binary(&mut server), vec![3, 4]);
+
+        // Drop the connection; once the sink notices, frames are buffered
+        // and delivered in order after the reconnect.
+        drop(server);
+        thread::sleep(Duration::from_millis(200));
+        sink.send(Payload::Binary(vec![5]));
+        sink.send(Payload::Text("6".to_string()));
+        let (stream, _) = listener.accept().unwrap();
+        let mut server = tungstenite::accept(stream).unwrap();
+        assert_eq!(read_binary(&mut server), vec![5]);
+        match server.read().unwrap() {
+            Message::Text(text) => assert_eq!(text, "6"),
+            other => panic!("expected text, got {:?}", other),
+        }
+
+        drop(sink);
+        let kinds = kinds.lock().unwrap();
+        assert_eq!(kinds.first().map(String::as_str), Some("connected"));
+        assert!(kinds.iter().any(|k| k == "disconnected"));
+        assert_eq!(kinds.iter().filter(|k| *k == "connected").count(), 2);
+    }
+
+    #[test]
+    fn a_full_queue_loses_its_oldest_frames() {
+        // The server never answers the upgrade, so the worker is stuck in
+        // the handshake and drains nothing.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let sink = WebSocketSink::new(
+            WebSocketConfig {
+                max_buffered_frames: 3,
+                ..config(url)
+            },
+            Box::new(|_| {}),
+        );
+        let _connection = listener.accept().unwrap();
+
+        for i in 0..10u8 {
+            sink.send(Payload::Binary(vec![i]));
+        }
+        assert_eq!(sink.dropped.load(Ordering::Relaxed), 7);
+        let queued: Vec<Payload> = sink.receiver.lock().unwrap().try_iter().collect();
+        let queued: Vec<Vec<u8>> = queued
+            .into_iter()
+            .map(|frame| match frame {
+                Payload::Binary(bytes) => bytes,
+                Payload::Text(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(queued, [[7], [8], [9]]);
+    }
+}